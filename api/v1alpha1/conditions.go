@@ -15,9 +15,21 @@ const (
 	KnownTrusteeAddressReason    string = "AddressFound"
 	UnknownTrusteeAddressReason  string = "NoAddressFound"
 
-	CommittedCondition          string = "Committed"
-	CommittedReason             string = "ImageCommitted"
-	NotCommittedReasonComputing string = "Computing"
-	NotCommittedReasonNoDigest  string = "NoDigestGiven"
-	NotCommittedReasonFailed    string = "ComputationFailed"
+	CommittedCondition               string = "Committed"
+	CommittedReason                  string = "ImageCommitted"
+	NotCommittedReasonComputing      string = "Computing"
+	NotCommittedReasonNoDigest       string = "NoDigestGiven"
+	NotCommittedReasonFailed         string = "ComputationFailed"
+	NotCommittedReasonUnverified     string = "SignatureUnverified"
+	NotCommittedReasonMismatch       string = "PcrLabelMismatch"
+	NotCommittedReasonUncomputable   string = "UncomputablePcrs"
+	NotCommittedReasonLabelUnusable  string = "PcrLabelUnusable"
+	NotCommittedReasonNoArchitecture string = "ArchitectureUnavailable"
+
+	DeprecatedCondition string = "Deprecated"
+	DrainingReason      string = "Draining"
+
+	PolledCondition string = "Polled"
+	PolledReason    string = "PollSucceeded"
+	NotPolledReason string = "PollFailed"
 )
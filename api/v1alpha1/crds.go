@@ -9,6 +9,7 @@
 package v1alpha1
 
 import (
+	corev1 "k8s.io/api/core/v1"
 	metav1 "k8s.io/apimachinery/pkg/apis/meta/v1"
 	"k8s.io/apimachinery/pkg/runtime/schema"
 	"sigs.k8s.io/controller-runtime/pkg/scheme"
@@ -25,16 +26,29 @@ var (
 	AddToScheme = SchemeBuilder.AddToScheme
 )
 
-// +kubebuilder:rbac:groups="",resources=configmaps,verbs=get;create;patch;update
-// +kubebuilder:rbac:groups="",resources=services,verbs=create
-// +kubebuilder:rbac:groups="",resources=secrets,verbs=create
+// +kubebuilder:rbac:groups="",resources=configmaps,verbs=get;list;create;patch;update;delete
+// +kubebuilder:rbac:groups="",resources=services,verbs=create;get
+// +kubebuilder:rbac:groups="",resources=secrets,verbs=create;get
+// +kubebuilder:rbac:groups="",resources=serviceaccounts,verbs=create;delete
+// +kubebuilder:rbac:groups="",resources=pods,verbs=list
+// +kubebuilder:rbac:groups="",resources=pods/log,verbs=get
+// +kubebuilder:rbac:groups="",resources=nodes,verbs=list
+// +kubebuilder:rbac:groups="",resources=events,verbs=list
+// +kubebuilder:rbac:groups=rbac.authorization.k8s.io,resources=roles;rolebindings,verbs=create;delete
+// +kubebuilder:rbac:groups=networking.k8s.io,resources=networkpolicies,verbs=create;patch
+// +kubebuilder:rbac:groups=networking.k8s.io,resources=ingresses,verbs=create;get
+// +kubebuilder:rbac:groups=route.openshift.io,resources=routes,verbs=create;get
+// +kubebuilder:rbac:groups=route.openshift.io,resources=routes/custom-host,verbs=create
+// +kubebuilder:rbac:groups=discovery.k8s.io,resources=endpointslices,verbs=list;watch
 // +kubebuilder:rbac:groups=apps,resources=deployments,verbs=get;create;update
-// +kubebuilder:rbac:groups=batch,resources=jobs,verbs=create;delete;list;watch
+// +kubebuilder:rbac:groups=batch,resources=jobs,verbs=create;get;delete;list;watch
 // +kubebuilder:rbac:groups=trusted-execution-clusters.io,resources=trustedexecutionclusters,verbs=list;watch
 // +kubebuilder:rbac:groups=trusted-execution-clusters.io,resources=trustedexecutionclusters/status,verbs=patch
 // +kubebuilder:rbac:groups=trusted-execution-clusters.io,resources=machines,verbs=create;list;delete;watch;patch
-// +kubebuilder:rbac:groups=trusted-execution-clusters.io,resources=approvedimages,verbs=get;list;watch;patch
+// +kubebuilder:rbac:groups=trusted-execution-clusters.io,resources=approvedimages,verbs=create;get;list;watch;patch;delete
 // +kubebuilder:rbac:groups=trusted-execution-clusters.io,resources=approvedimages/status,verbs=patch
+// +kubebuilder:rbac:groups=trusted-execution-clusters.io,resources=approvedimagestreams,verbs=list;watch
+// +kubebuilder:rbac:groups=trusted-execution-clusters.io,resources=approvedimagestreams/status,verbs=patch
 
 // TrustedExecutionClusterSpec defines the desired state of TrustedExecutionCluster
 // +kubebuilder:validation:XValidation:rule="!has(oldSelf.publicTrusteeAddr) || has(self.publicTrusteeAddr)", message="Value is required once set"
@@ -43,8 +57,8 @@ type TrustedExecutionClusterSpec struct {
 	// +kubebuilder:validation:XValidation:rule="self == oldSelf",message="Value is immutable"
 	TrusteeImage string `json:"trusteeImage"`
 
-	// Image reference to trusted-cluster-operator's compute-pcrs image
-	// +kubebuilder:validation:XValidation:rule="self == oldSelf",message="Value is immutable"
+	// Image reference to trusted-cluster-operator's compute-pcrs image.
+	// When changed, the PCRs of all approved images are recomputed.
 	PcrsComputeImage string `json:"pcrsComputeImage"`
 
 	// Image reference to trusted-cluster-operator's register-server image
@@ -65,6 +79,209 @@ type TrustedExecutionClusterSpec struct {
 	// +optional
 	// +kubebuilder:validation:XValidation:rule="self == oldSelf",message="Value is immutable"
 	RegisterServerPort int32 `json:"registerServerPort,omitempty"`
+
+	// Scheduling and resource settings for Trustee pods
+	// +optional
+	// +kubebuilder:validation:XValidation:rule="self == oldSelf",message="Value is immutable"
+	TrusteePodSettings *PodSettings `json:"trusteePodSettings,omitempty"`
+
+	// Scheduling and resource settings for trusted-cluster-operator's register-server pods
+	// +optional
+	// +kubebuilder:validation:XValidation:rule="self == oldSelf",message="Value is immutable"
+	RegisterServerPodSettings *PodSettings `json:"registerServerPodSettings,omitempty"`
+
+	// Scheduling and resource settings for trusted-cluster-operator's compute-pcrs job pods
+	// +optional
+	// +kubebuilder:validation:XValidation:rule="self == oldSelf",message="Value is immutable"
+	PcrsComputePodSettings *PodSettings `json:"pcrsComputePodSettings,omitempty"`
+
+	// Failure handling for trusted-cluster-operator's compute-pcrs jobs
+	// +optional
+	// +kubebuilder:validation:XValidation:rule="self == oldSelf",message="Value is immutable"
+	PcrsComputeJobSettings *JobSettings `json:"pcrsComputeJobSettings,omitempty"`
+
+	// CIDRs of the networks that nodes connect from. If set, ingress to
+	// Trustee and register-server is restricted to these.
+	// +optional
+	// +kubebuilder:validation:XValidation:rule="self == oldSelf",message="Value is immutable"
+	NodeNetworkCIDRs []string `json:"nodeNetworkCidrs,omitempty"`
+
+	// Endpoint to create to make Trustee reachable from outside the cluster.
+	// Its assigned address is used unless publicTrusteeAddr is set.
+	// +optional
+	// +kubebuilder:validation:XValidation:rule="self == oldSelf",message="Value is immutable"
+	TrusteeExposure *TrusteeExposure `json:"trusteeExposure,omitempty"`
+
+	// PCRs to compute for approved images and to require in attestation.
+	// Defaults to PCR4 and PCR14. Can be overridden per ApprovedImage.
+	// compute-pcrs computes PCR4, PCR7 and PCR14. Images whose PCRs must be
+	// computed are not committed if other PCRs are selected.
+	// +optional
+	// +listType=set
+	// +kubebuilder:validation:MinItems=1
+	// +kubebuilder:validation:items:Minimum=0
+	// +kubebuilder:validation:items:Maximum=23
+	// +kubebuilder:validation:XValidation:rule="self == oldSelf",message="Value is immutable"
+	PcrSelection []int32 `json:"pcrSelection,omitempty"`
+
+	// Firmware profiles that nodes boot with, i.e. names of ConfigMaps
+	// labeled trusted-execution-clusters.io/firmware-profile. Firmware-dependent
+	// PCRs are computed per profile. Defaults to the profile built into the
+	// compute-pcrs image. Can be overridden per ApprovedImage.
+	// +optional
+	// +listType=set
+	// +kubebuilder:validation:XValidation:rule="self == oldSelf",message="Value is immutable"
+	FirmwareProfiles []string `json:"firmwareProfiles,omitempty"`
+
+	// Pull secrets for approved images. They are used to read the images'
+	// PCR label and passed to compute-pcrs jobs.
+	// +optional
+	// +listType=atomic
+	// +kubebuilder:validation:XValidation:rule="self == oldSelf",message="Value is immutable"
+	ImagePullSecrets []corev1.LocalObjectReference `json:"imagePullSecrets,omitempty"`
+
+	// Signatures that approved images must carry. If set, images without a
+	// signature matching this policy are not committed.
+	// +optional
+	// +kubebuilder:validation:XValidation:rule="self == oldSelf",message="Value is immutable"
+	ImageVerification *ImageVerification `json:"imageVerification,omitempty"`
+
+	// How to treat PCR values that an image declares in its org.coreos.pcrs
+	// label. Trust uses them as they are, Recompute ignores them and always
+	// computes PCRs, Compare computes PCRs and refuses the image if they
+	// differ from the label or if it has no label with the selected PCRs.
+	// Defaults to Trust.
+	// +optional
+	// +kubebuilder:validation:Enum=Trust;Recompute;Compare
+	// +kubebuilder:validation:XValidation:rule="self == oldSelf",message="Value is immutable"
+	PcrLabelTrust string `json:"pcrLabelTrust,omitempty"`
+
+	// Registries that the operator accesses over plain HTTP, e.g. a local
+	// development registry, given as host[:port]
+	// +optional
+	// +listType=set
+	// +kubebuilder:validation:XValidation:rule="self == oldSelf",message="Value is immutable"
+	InsecureRegistries []string `json:"insecureRegistries,omitempty"`
+
+	// Seconds that a deleted ApprovedImage stays trusted, so that nodes
+	// still running it can update and reboot. Defaults to 0, i.e. the image
+	// is no longer trusted once deleted. Can be overridden per ApprovedImage.
+	// +optional
+	// +kubebuilder:validation:Minimum=0
+	// +kubebuilder:validation:XValidation:rule="self == oldSelf",message="Value is immutable"
+	ImageDrainSeconds *int64 `json:"imageDrainSeconds,omitempty"`
+}
+
+// JobSettings defines how failures of jobs that the operator generates are handled
+type JobSettings struct {
+	// Retries of a job's failed pod before the job is considered failed.
+	// Defaults to 2.
+	// +optional
+	// +kubebuilder:validation:Minimum=0
+	BackoffLimit *int32 `json:"backoffLimit,omitempty"`
+
+	// Seconds that a job's pod may stay pending, e.g. because its image cannot
+	// be pulled, before the job is considered failed. Defaults to 600.
+	// +optional
+	// +kubebuilder:validation:Minimum=1
+	PendingTimeoutSeconds *int64 `json:"pendingTimeoutSeconds,omitempty"`
+
+	// Seconds that a failed job and its pods are kept for inspection before
+	// they are deleted. Defaults to 3600.
+	// +optional
+	// +kubebuilder:validation:Minimum=0
+	FailedJobRetentionSeconds *int64 `json:"failedJobRetentionSeconds,omitempty"`
+}
+
+// ImageVerification defines which cosign signatures are accepted for
+// approved images. An image is verified if any of its signatures matches
+// any of the public keys or keyless identities.
+// +kubebuilder:validation:XValidation:rule="has(self.publicKeys) || has(self.keylessIdentities)",message="publicKeys or keylessIdentities is required"
+// +kubebuilder:validation:XValidation:rule="!has(self.keylessIdentities) || (has(self.fulcioCertificates) && has(self.rekorPublicKey))",message="fulcioCertificates and rekorPublicKey are required for keylessIdentities"
+type ImageVerification struct {
+	// PEM-encoded public keys that signatures are verified with
+	// +optional
+	// +kubebuilder:validation:MinItems=1
+	PublicKeys []string `json:"publicKeys,omitempty"`
+
+	// Identities of keyless signers, as certified by Fulcio
+	// +optional
+	// +kubebuilder:validation:MinItems=1
+	KeylessIdentities []KeylessIdentity `json:"keylessIdentities,omitempty"`
+
+	// PEM-encoded Fulcio root and intermediate certificates that keyless
+	// signing certificates must chain up to
+	// +optional
+	FulcioCertificates *string `json:"fulcioCertificates,omitempty"`
+
+	// PEM-encoded public key of the Rekor transparency log that keyless
+	// signatures must be logged in
+	// +optional
+	RekorPublicKey *string `json:"rekorPublicKey,omitempty"`
+}
+
+// KeylessIdentity defines a signer identity in a Fulcio certificate
+type KeylessIdentity struct {
+	// OIDC issuer that the signer authenticated with
+	Issuer string `json:"issuer"`
+
+	// Signer identity, i.e. the email or URI in the certificate's subject
+	// alternative name
+	Subject string `json:"subject"`
+}
+
+// TrusteeExposure defines an externally reachable endpoint for Trustee
+type TrusteeExposure struct {
+	// Kind of endpoint to create
+	// +kubebuilder:validation:Enum=LoadBalancer;Ingress;Route
+	Type string `json:"type"`
+
+	// Host name to request for Ingress and Route endpoints
+	// +optional
+	Host *string `json:"host,omitempty"`
+
+	// IngressClass to use for Ingress endpoints
+	// +optional
+	IngressClassName *string `json:"ingressClassName,omitempty"`
+
+	// Namespace of the ingress controller that serves Ingress endpoints.
+	// When nodeNetworkCidrs is set, ingress to Trustee is also allowed from
+	// this namespace. Defaults to allowing pods of all namespaces.
+	// +optional
+	IngressControllerNamespace *string `json:"ingressControllerNamespace,omitempty"`
+}
+
+// PodSettings defines scheduling and resource settings for pods that the operator generates
+type PodSettings struct {
+	// Compute resources of the component's container
+	// +optional
+	Resources *corev1.ResourceRequirements `json:"resources,omitempty"`
+
+	// Node labels that a node must have for the pod to be scheduled on it
+	// +optional
+	NodeSelector map[string]string `json:"nodeSelector,omitempty"`
+
+	// Tolerations of the pod
+	// +optional
+	Tolerations []corev1.Toleration `json:"tolerations,omitempty"`
+
+	// Scheduling constraints of the pod
+	// +optional
+	Affinity *corev1.Affinity `json:"affinity,omitempty"`
+
+	// Priority class of the pod
+	// +optional
+	PriorityClassName string `json:"priorityClassName,omitempty"`
+
+	// Secrets to pull the component's image with
+	// +optional
+	ImagePullSecrets []corev1.LocalObjectReference `json:"imagePullSecrets,omitempty"`
+
+	// User ID to run the pod as. If unset, the platform assigns one, as
+	// OpenShift does from the range of the namespace. Platforms that assign
+	// none need it for images that would run as root.
+	// +optional
+	RunAsUser *int64 `json:"runAsUser,omitempty"`
 }
 
 // TrustedExecutionClusterStatus defines the observed state of TrustedExecutionCluster.
@@ -73,6 +290,36 @@ type TrustedExecutionClusterStatus struct {
 	// +listMapKey=type
 	// +optional
 	Conditions []metav1.Condition `json:"conditions,omitempty"`
+
+	// Address where attesters can connect to Trustee, either
+	// publicTrusteeAddr or the address assigned to trusteeExposure
+	// +optional
+	TrusteeAddr *string `json:"trusteeAddr,omitempty"`
+
+	// Size and entry count of the stored image PCRs and of the reference
+	// values that are given to Trustee, as of their last recomputation
+	// +optional
+	ReferenceValues *ReferenceValuesStatus `json:"referenceValues,omitempty"`
+}
+
+// ReferenceValuesStatus describes how much reference value data is stored.
+// ConfigMaps are limited to 1 MiB each.
+type ReferenceValuesStatus struct {
+	// Number of images that PCRs are stored for
+	ImageCount int32 `json:"imageCount"`
+
+	// Total size of the image PCRs shards in bytes
+	ImagePcrsBytes int64 `json:"imagePcrsBytes"`
+
+	// Size of the largest image PCRs shard in bytes
+	LargestShardBytes int64 `json:"largestShardBytes"`
+
+	// Number of PCR tuples, i.e. of images and firmware profiles, in the
+	// reference values
+	PcrTupleCount int32 `json:"pcrTupleCount"`
+
+	// Size of the reference values file in bytes
+	ReferenceValuesBytes int64 `json:"referenceValuesBytes"`
 }
 
 // +kubebuilder:object:root=true
@@ -119,6 +366,13 @@ type MachineStatus struct {
 	// +listMapKey=type
 	// +optional
 	Conditions []metav1.Condition `json:"conditions,omitempty"`
+
+	// Image that the machine last booted, as reported by whatever updates
+	// it. Used to tell which machines still boot a deleted ApprovedImage.
+	// When unset, the image that the Machine Config Operator annotates on
+	// the node at the machine's registration address is used instead.
+	// +optional
+	BootImage *string `json:"bootImage,omitempty"`
 }
 
 // +kubebuilder:object:root=true
@@ -151,12 +405,52 @@ type MachineList struct {
 }
 
 // ApprovedImageSpec defines the desired state of ApprovedImage
+// +kubebuilder:validation:XValidation:rule="self.image.matches(r'.*@sha256:.*') || (has(self.resolveTag) && self.resolveTag)",message="Image must be provided with a digest unless resolveTag is set"
 type ApprovedImageSpec struct {
-	// Approved image reference, specified with digest
+	// Approved image reference, specified with digest unless resolveTag is set
 	// +required
 	// +kubebuilder:validation:XValidation:rule="self == oldSelf",message="Value is immutable"
-	// +kubebuilder:validation:XValidation:rule="self.matches(r'.*@sha256:.*')",message="Image must be provided with a digest"
 	Reference string `json:"image"`
+
+	// Resolve the tag of image to the digest that it points to when the
+	// image is first reconciled. PCRs are computed for that digest only;
+	// later changes of the tag are not followed.
+	// +optional
+	// +kubebuilder:validation:XValidation:rule="self == oldSelf",message="Value is immutable"
+	ResolveTag bool `json:"resolveTag,omitempty"`
+
+	// PCRs to compute for this image and to require in attestation.
+	// Overrides the TrustedExecutionCluster's pcrSelection.
+	// compute-pcrs computes PCR4, PCR7 and PCR14. Images whose PCRs must be
+	// computed are not committed if other PCRs are selected.
+	// +optional
+	// +listType=set
+	// +kubebuilder:validation:MinItems=1
+	// +kubebuilder:validation:items:Minimum=0
+	// +kubebuilder:validation:items:Maximum=23
+	// +kubebuilder:validation:XValidation:rule="self == oldSelf",message="Value is immutable"
+	PcrSelection []int32 `json:"pcrSelection,omitempty"`
+
+	// Firmware profiles to compute firmware-dependent PCRs for.
+	// Overrides the TrustedExecutionCluster's firmwareProfiles.
+	// +optional
+	// +listType=set
+	// +kubebuilder:validation:XValidation:rule="self == oldSelf",message="Value is immutable"
+	FirmwareProfiles []string `json:"firmwareProfiles,omitempty"`
+
+	// Pull secrets for this image, used in addition to the
+	// TrustedExecutionCluster's imagePullSecrets
+	// +optional
+	// +listType=atomic
+	// +kubebuilder:validation:XValidation:rule="self == oldSelf",message="Value is immutable"
+	ImagePullSecrets []corev1.LocalObjectReference `json:"imagePullSecrets,omitempty"`
+
+	// Seconds that the image stays trusted once deleted. Overrides the
+	// TrustedExecutionCluster's imageDrainSeconds, and can be changed while
+	// the image is deleted to shorten or extend its drain window.
+	// +optional
+	// +kubebuilder:validation:Minimum=0
+	DrainSeconds *int64 `json:"drainSeconds,omitempty"`
 }
 
 // ApprovedImageStatus defines the observed state of ApprovedImage.
@@ -165,6 +459,112 @@ type ApprovedImageStatus struct {
 	// +listMapKey=type
 	// +optional
 	Conditions []metav1.Condition `json:"conditions,omitempty"`
+
+	// Image reference pinned to the digest that its tag resolved to, if
+	// resolveTag is set
+	// +optional
+	ResolvedImage string `json:"resolvedImage,omitempty"`
+
+	// PCR values that the image measures to. If firmware profiles are
+	// selected, firmware-dependent PCRs are given per profile in
+	// firmwareProfilePcrs instead.
+	// +optional
+	// +listType=map
+	// +listMapKey=id
+	Pcrs []PcrValue `json:"pcrs,omitempty"`
+
+	// Firmware-dependent PCR values per firmware profile
+	// +optional
+	// +listType=map
+	// +listMapKey=profile
+	FirmwareProfilePcrs []FirmwareProfilePcrValues `json:"firmwareProfilePcrs,omitempty"`
+
+	// PCR values per architecture of a multi-architecture image, in place
+	// of pcrs and firmwareProfilePcrs
+	// +optional
+	// +listType=map
+	// +listMapKey=architecture
+	PlatformPcrs []PlatformPcrValues `json:"platformPcrs,omitempty"`
+
+	// Whether the PCRs were read from the image's org.coreos.pcrs label or
+	// computed by a compute-pcrs job
+	// +optional
+	// +kubebuilder:validation:Enum=Label;Computed
+	PcrSource string `json:"pcrSource,omitempty"`
+
+	// Name of the job that computed the PCRs
+	// +optional
+	ComputeJob string `json:"computeJob,omitempty"`
+
+	// pcrsComputeImage that the job ran
+	// +optional
+	ComputeImage string `json:"computeImage,omitempty"`
+
+	// Version of the compute-pcrs tool that computed the PCRs
+	// +optional
+	ComputeToolVersion string `json:"computeToolVersion,omitempty"`
+
+	// When PCRs were first stored for the image
+	// +optional
+	FirstSeen *metav1.Time `json:"firstSeen,omitempty"`
+
+	// When the PCRs were last computed. Unset for PCRs read from a label.
+	// +optional
+	LastComputed *metav1.Time `json:"lastComputed,omitempty"`
+
+	// When a deleted image stops being trusted
+	// +optional
+	DrainDeadline *metav1.Time `json:"drainDeadline,omitempty"`
+
+	// Machines that still booted a deleted image
+	// +optional
+	// +listType=set
+	DrainingMachines []string `json:"drainingMachines,omitempty"`
+}
+
+// PcrValue is the value of a single PCR
+type PcrValue struct {
+	// +required
+	// +kubebuilder:validation:Minimum=0
+	// +kubebuilder:validation:Maximum=23
+	ID int32 `json:"id"`
+
+	// Hex-encoded PCR value
+	// +required
+	Value string `json:"value"`
+}
+
+// FirmwareProfilePcrValues are the firmware-dependent PCR values of an
+// image when booted with a firmware profile
+type FirmwareProfilePcrValues struct {
+	// Firmware profile name
+	// +required
+	Profile string `json:"profile"`
+
+	// +required
+	// +listType=map
+	// +listMapKey=id
+	Pcrs []PcrValue `json:"pcrs"`
+}
+
+// PlatformPcrValues are the PCR values of one architecture of a
+// multi-architecture image
+type PlatformPcrValues struct {
+	// Architecture as named by OCI platforms and the kubernetes.io/arch
+	// node label, e.g. amd64 or arm64
+	// +required
+	Architecture string `json:"architecture"`
+
+	// +required
+	// +listType=map
+	// +listMapKey=id
+	Pcrs []PcrValue `json:"pcrs"`
+
+	// Firmware-dependent PCR values per firmware profile
+	// +optional
+	// +listType=map
+	// +listMapKey=profile
+	FirmwareProfilePcrs []FirmwareProfilePcrValues `json:"firmwareProfilePcrs,omitempty"`
 }
 
 // +kubebuilder:object:root=true
@@ -195,3 +595,101 @@ type ApprovedImageList struct {
 	metav1.ListMeta `json:"metadata,omitempty"`
 	Items           []ApprovedImage `json:"items"`
 }
+
+// ApprovedImageStreamSpec defines the desired state of ApprovedImageStream
+// +kubebuilder:validation:XValidation:rule="has(self.tag) != has(self.streamMetadata)",message="Exactly one of tag and streamMetadata is required"
+type ApprovedImageStreamSpec struct {
+	// Image reference by tag to follow, e.g. quay.io/fedora/fedora-coreos:stable
+	// +optional
+	Tag *string `json:"tag,omitempty"`
+
+	// CoreOS stream metadata to follow
+	// +optional
+	StreamMetadata *StreamMetadata `json:"streamMetadata,omitempty"`
+
+	// Seconds between polls for new images. Defaults to 3600.
+	// +optional
+	// +kubebuilder:validation:Minimum=60
+	PollIntervalSeconds *int64 `json:"pollIntervalSeconds,omitempty"`
+
+	// Number of most recent images to keep approved. Older images are
+	// retired. By default, images are not retired by count.
+	// +optional
+	// +kubebuilder:validation:Minimum=1
+	KeepLatest *int32 `json:"keepLatest,omitempty"`
+
+	// Seconds that an image is kept approved after a newer image appeared.
+	// By default, images are not retired by age.
+	// +optional
+	// +kubebuilder:validation:Minimum=0
+	RetireAfterSeconds *int64 `json:"retireAfterSeconds,omitempty"`
+
+	// Pull secrets for the followed images, used in addition to the
+	// TrustedExecutionCluster's imagePullSecrets. They are passed on to
+	// the ApprovedImages that are created.
+	// +optional
+	// +listType=atomic
+	ImagePullSecrets []corev1.LocalObjectReference `json:"imagePullSecrets,omitempty"`
+}
+
+// StreamMetadata defines where to find new images in CoreOS stream metadata
+type StreamMetadata struct {
+	// URL of the stream metadata JSON, e.g.
+	// https://builds.coreos.fedoraproject.org/streams/stable.json.
+	// Plain HTTP is only allowed for hosts listed in insecureRegistries.
+	URL string `json:"url"`
+
+	// Architecture to follow images of. Defaults to x86_64.
+	// +optional
+	Architecture *string `json:"architecture,omitempty"`
+
+	// Name of the container image among the architecture's images. Its
+	// digest-ref is followed.
+	Image string `json:"image"`
+}
+
+// ApprovedImageStreamStatus defines the observed state of ApprovedImageStream.
+type ApprovedImageStreamStatus struct {
+	// +listType=map
+	// +listMapKey=type
+	// +optional
+	Conditions []metav1.Condition `json:"conditions,omitempty"`
+
+	// Image reference, with digest, that the stream pointed to when last polled
+	// +optional
+	LatestImage string `json:"latestImage,omitempty"`
+
+	// When the stream was last polled, successfully or not
+	// +optional
+	LastPolled *metav1.Time `json:"lastPolled,omitempty"`
+}
+
+// +kubebuilder:object:root=true
+// +kubebuilder:subresource:status
+
+// ApprovedImageStream is the Schema for the approvedimagestreams API. It
+// follows a tag or stream and creates an ApprovedImage for each new image.
+type ApprovedImageStream struct {
+	metav1.TypeMeta `json:",inline"`
+
+	// metadata is a standard object metadata
+	// +optional
+	metav1.ObjectMeta `json:"metadata,omitempty,omitzero"`
+
+	// spec defines the desired state of ApprovedImageStream
+	// +required
+	Spec ApprovedImageStreamSpec `json:"spec"`
+
+	// status defines the observed state of ApprovedImageStream
+	// +optional
+	Status ApprovedImageStreamStatus `json:"status,omitempty,omitzero"`
+}
+
+// +kubebuilder:object:root=true
+
+// ApprovedImageStreamList contains a list of ApprovedImageStream
+type ApprovedImageStreamList struct {
+	metav1.TypeMeta `json:",inline"`
+	metav1.ListMeta `json:"metadata,omitempty"`
+	Items           []ApprovedImageStream `json:"items"`
+}
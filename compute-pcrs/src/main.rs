@@ -3,72 +3,284 @@
 //
 // SPDX-License-Identifier: MIT
 
-use anyhow::{Context, Result};
-use clap::Parser;
+use anyhow::{Context, Result, anyhow, bail};
+use clap::{Parser, Subcommand};
 use compute_pcrs_lib::*;
-use k8s_openapi::{api::core::v1::ConfigMap, chrono::Utc};
-use kube::{Api, Client};
+use oci_client::{Reference, secrets::RegistryAuth};
+use std::path::Path;
+use std::{collections::BTreeMap, fs};
 
-use trusted_cluster_operator_lib::{conditions::INSTALLED_REASON, reference_values::*, *};
+use trusted_cluster_operator_lib::pull_secrets::*;
+use trusted_cluster_operator_lib::reference_values::*;
+
+mod oci;
 
 #[derive(Parser)]
 #[command(version, about)]
 struct Args {
-    /// Path to the kernel modules directory
-    #[arg(short, long)]
-    kernels: String,
-    /// Path to the ESP directory
-    #[arg(short, long)]
-    esp: String,
+    #[command(subcommand)]
+    mode: Mode,
+}
+
+#[derive(Subcommand)]
+enum Mode {
+    /// Compute the PCRs of an image and log them for the operator to store,
+    /// as compute-pcrs jobs do
+    Cluster(ClusterArgs),
+    /// Compute the PCRs of an unpacked image or OCI archive and print them
+    /// as the image's org.coreos.pcrs label expects them
+    Local(LocalArgs),
+}
+
+/// Which PCRs to compute, and the firmware variables to compute them for
+#[derive(clap::Args)]
+struct ComputeArgs {
     /// Path to the directory storing EFIVar files
-    #[arg(short = 's', long)]
+    #[arg(
+        short = 's',
+        long,
+        default_value = "/reference-values/efivars/qemu-ovmf/fedora-42"
+    )]
     efivars: String,
     /// Path to directory storing MokListRT, MokListTrustedRT and MokListXRT
-    #[arg(short, long)]
+    #[arg(
+        short,
+        long,
+        default_value = "/reference-values/mok-variables/fedora-42"
+    )]
     mokvars: String,
-    /// ApprovedImage resource name
-    #[arg(short, long)]
-    resource_name: String,
+    /// Comma-separated PCRs to compute
+    #[arg(short, long, value_delimiter = ',', required = true)]
+    pcrs: Vec<u64>,
+}
+
+#[derive(clap::Args)]
+struct ClusterArgs {
+    /// Path to the kernel modules directory
+    #[arg(short, long, required_unless_present = "pull")]
+    kernels: Option<String>,
+    /// Path to the ESP directory
+    #[arg(short, long, required_unless_present = "pull")]
+    esp: Option<String>,
+    /// Pull the image from its registry instead of reading it from
+    /// --kernels and --esp, e.g. when it cannot be mounted as an image volume
+    #[arg(long, conflicts_with_all = ["kernels", "esp"])]
+    pull: bool,
+    /// Path to a directory with one mounted pull secret per directory, named
+    /// by their precedence (0, 1, ...), to pull the image with
+    #[arg(long, requires = "pull")]
+    pull_secrets: Option<String>,
+    /// Comma-separated registries to pull from over plain HTTP
+    #[arg(long, value_delimiter = ',', requires = "pull")]
+    insecure_registries: Vec<String>,
+    #[command(flatten)]
+    compute: ComputeArgs,
     /// Image reference
     #[arg(short, long)]
     image: String,
+    /// Path to a directory with one directory per firmware profile, each
+    /// with efivars and mokvars directories. If given, firmware-dependent
+    /// PCRs are computed per profile instead of from --efivars and --mokvars.
+    #[arg(short, long)]
+    firmware_profiles: Option<String>,
+}
+
+#[derive(clap::Args)]
+#[group(id = "source", required = true, multiple = false)]
+struct LocalArgs {
+    /// Root directory of an unpacked image
+    #[arg(long, group = "source")]
+    image_dir: Option<String>,
+    /// OCI layout tarball of an image, e.g. as written by
+    /// `podman save --format oci-archive`
+    #[arg(long, group = "source")]
+    oci_archive: Option<String>,
+    #[command(flatten)]
+    compute: ComputeArgs,
+}
+
+/// Variables that firmware-dependent PCRs are computed from
+struct Firmware {
+    efivars: String,
+    mokvars: String,
+}
+
+fn read_firmware_profiles(dir: &str) -> Result<BTreeMap<String, Firmware>> {
+    let mut profiles = BTreeMap::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let err = format!("Invalid firmware profile path {}", path.display());
+        let name = path.file_name().and_then(|n| n.to_str()).context(err)?;
+        let subdir = |d: &str| path.join(d).to_string_lossy().to_string();
+        let firmware = Firmware {
+            efivars: subdir("efivars"),
+            mokvars: subdir("mokvars"),
+        };
+        profiles.insert(name.to_string(), firmware);
+    }
+    Ok(profiles)
+}
+
+/// Docker configs of the pull secrets mounted in a directory, in the order
+/// of precedence that their directory names give
+fn read_pull_secrets(dir: &str) -> Result<Vec<DockerConfig>> {
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().and_then(|n| n.to_str());
+        let precedence = name.and_then(|n| n.parse::<u64>().ok());
+        let err = format!("Invalid pull secret path {}", path.display());
+        dirs.push((precedence.context(err)?, path));
+    }
+    dirs.sort();
+    let mut configs = Vec::new();
+    for (_, path) in dirs {
+        let keys = [DOCKER_CONFIG_JSON_KEY, DOCKER_CONFIG_KEY].into_iter();
+        let read = |key| Some((key, fs::read(path.join(key)).ok()?));
+        let files: BTreeMap<_, _> = keys.filter_map(read).collect();
+        let name = path.to_string_lossy();
+        configs.push(docker_config(&name, |key| {
+            files.get(key).map(Vec::as_slice)
+        })?);
+    }
+    Ok(configs)
+}
+
+/// Pull the directories that PCRs are computed from out of an image
+async fn pull_image(args: &ClusterArgs) -> Result<tempfile::TempDir> {
+    let image: Reference = args.image.parse()?;
+    let configs = match &args.pull_secrets {
+        Some(dir) => read_pull_secrets(dir)?,
+        None => vec![],
+    };
+    let credentials = registry_credentials(configs, image.registry(), image.repository())?;
+    let auth = match credentials {
+        Some((username, password)) => RegistryAuth::Basic(username, password),
+        None => RegistryAuth::Anonymous,
+    };
+    let paths = [IMAGE_KERNELS_DIR, IMAGE_ESP_DIR];
+    oci::pull_image(&image, &auth, &args.insecure_registries, &paths).await
+}
+
+/// Directories of an image that PCRs are computed from
+struct ImageDirs {
+    kernels: String,
+    esp: String,
+}
+
+/// compute-pcrs-lib panics on missing firmware variable directories, e.g.
+/// of a firmware profile that lacks them
+fn existing_dir(dir: &str) -> Result<&str> {
+    match Path::new(dir).is_dir() {
+        true => Ok(dir),
+        false => Err(anyhow!("Firmware variable directory {dir} does not exist")),
+    }
+}
+
+fn compute_pcr(id: u64, image: &ImageDirs, firmware: &Firmware) -> Result<Pcr> {
+    match id {
+        4 => Ok(compute_pcr4(&image.kernels, &image.esp, false, true)),
+        7 => {
+            let efivars = existing_dir(&firmware.efivars)?;
+            Ok(compute_pcr7(Some(efivars), &image.esp, true))
+        }
+        14 => Ok(compute_pcr14(existing_dir(&firmware.mokvars)?)),
+        _ => Err(anyhow!(
+            "PCR{id} cannot be computed from the image. \
+             Provide it in the image's org.coreos.pcrs label instead."
+        )),
+    }
+}
+
+/// Print the PCRs of an image without a cluster, e.g. to set them as its
+/// label when it is built
+fn run_local(args: LocalArgs) -> Result<()> {
+    // Keeps an unpacked archive until PCRs are computed from it
+    let unpacked;
+    let root = match (&args.image_dir, &args.oci_archive) {
+        (Some(dir), _) => Path::new(dir),
+        (None, Some(archive)) => {
+            unpacked =
+                oci::unpack_oci_archive(Path::new(archive), &[IMAGE_KERNELS_DIR, IMAGE_ESP_DIR])?;
+            unpacked.path()
+        }
+        (None, None) => bail!("Either an image directory or an OCI archive is required"),
+    };
+    let dir = |d: &str| root.join(d).to_string_lossy().to_string();
+    let image = ImageDirs {
+        kernels: dir(IMAGE_KERNELS_DIR),
+        esp: dir(IMAGE_ESP_DIR),
+    };
+    let firmware = Firmware {
+        efivars: args.compute.efivars,
+        mokvars: args.compute.mokvars,
+    };
+    let pcrs = args.compute.pcrs.iter();
+    let pcrs = pcrs.map(|id| compute_pcr(*id, &image, &firmware));
+    let output = ComputePcrsOutput {
+        pcrs: pcrs.collect::<Result<_>>()?,
+    };
+    println!("{}", serde_json::to_string(&output)?);
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = Args::parse();
-
-    let pcrs = vec![
-        compute_pcr4(&args.kernels, &args.esp, false, true),
-        compute_pcr7(Some(&args.efivars), &args.esp, true),
-        compute_pcr14(&args.mokvars),
-    ];
-
-    let client = Client::try_default().await?;
-    let config_maps: Api<ConfigMap> = Api::default_namespaced(client.clone());
-
-    let mut image_pcrs_map = config_maps.get(PCR_CONFIG_MAP).await?;
-    let image_pcrs_data = image_pcrs_map
-        .data
-        .context("Image PCRs map existed, but had no data")?;
-    let image_pcrs_str = image_pcrs_data
-        .get(PCR_CONFIG_FILE)
-        .context("Image PCRs data existed, but had no file")?;
-    let mut image_pcrs: ImagePcrs = serde_json::from_str(image_pcrs_str)?;
-
-    let image_pcr = ImagePcr {
-        first_seen: Utc::now(),
-        reference: args.image,
+    match Args::parse().mode {
+        Mode::Cluster(args) => run_cluster(args).await,
+        Mode::Local(args) => run_local(args),
+    }
+}
+
+/// Compute the PCRs of an image in a compute-pcrs job. The operator reads
+/// them from the job's log.
+async fn run_cluster(args: ClusterArgs) -> Result<()> {
+    // Keeps a pulled image until PCRs are computed from it
+    let pulled;
+    let image = match (&args.kernels, &args.esp) {
+        (Some(kernels), Some(esp)) if !args.pull => ImageDirs {
+            kernels: kernels.clone(),
+            esp: esp.clone(),
+        },
+        _ => {
+            pulled = pull_image(&args).await?;
+            let dir = |d: &str| pulled.path().join(d).to_string_lossy().to_string();
+            ImageDirs {
+                kernels: dir(IMAGE_KERNELS_DIR),
+                esp: dir(IMAGE_ESP_DIR),
+            }
+        }
+    };
+    let default_firmware = Firmware {
+        efivars: args.compute.efivars.clone(),
+        mokvars: args.compute.mokvars.clone(),
+    };
+    let firmware_profiles = match &args.firmware_profiles {
+        Some(dir) => read_firmware_profiles(dir)?,
+        None => BTreeMap::new(),
+    };
+    let (firmware_ids, ids): (Vec<u64>, Vec<u64>) = match firmware_profiles.is_empty() {
+        true => (vec![], args.compute.pcrs.clone()),
+        false => {
+            let pcrs = args.compute.pcrs.iter().copied();
+            pcrs.partition(|id| FIRMWARE_PROFILE_PCRS.contains(id))
+        }
+    };
+    let compute = |ids: &[u64], firmware: &Firmware| {
+        let pcrs = ids.iter().map(|id| compute_pcr(*id, &image, firmware));
+        pcrs.collect::<Result<Vec<_>>>()
+    };
+    let pcrs = compute(&ids, &default_firmware)?;
+    let mut firmware_profile_pcrs = BTreeMap::new();
+    for (name, firmware) in firmware_profiles.iter() {
+        firmware_profile_pcrs.insert(name.clone(), compute(&firmware_ids, firmware)?);
+    }
+
+    let result = ComputePcrsResult {
         pcrs,
+        firmware_profile_pcrs,
+        tool_version: env!("CARGO_PKG_VERSION").to_string(),
     };
-    image_pcrs.0.insert(args.resource_name.clone(), image_pcr);
-    update_image_pcrs!(config_maps, image_pcrs_map, image_pcrs);
-
-    let approved_images: Api<ApprovedImage> = Api::default_namespaced(client);
-    let image = approved_images.get(&args.resource_name).await?;
-    let committed = committed_condition(INSTALLED_REASON, image.metadata.generation);
-    let conditions = Some(vec![committed]);
-    let status = ApprovedImageStatus { conditions };
-    update_status!(approved_images, &args.resource_name, status)?;
+    println!("{}", result.log_line()?);
     Ok(())
 }
@@ -0,0 +1,451 @@
+// SPDX-FileCopyrightText: Jakob Naucke <jnaucke@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+// Unpacking of the paths that PCRs are computed from out of image layers,
+// for when the image is not mounted.
+
+use anyhow::{Context, Result, bail};
+use flate2::read::GzDecoder;
+use oci_client::client::{ClientConfig, ClientProtocol};
+use oci_client::{Reference, secrets::RegistryAuth};
+use oci_spec::image::{Descriptor, ImageIndex, ImageManifest};
+use std::fs::{self, File, Permissions};
+use std::io::{self, BufReader, Read, Seek};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use tar::Archive;
+use tempfile::TempDir;
+
+const WHITEOUT_PREFIX: &str = ".wh.";
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+/// Unpack the given paths of the image in an OCI layout tarball, e.g. as
+/// written by `podman save --format oci-archive`, into a temporary root
+/// directory
+pub fn unpack_oci_archive(archive: &Path, paths: &[&str]) -> Result<TempDir> {
+    let layout = tempfile::tempdir()?;
+    let open = File::open(archive);
+    let archive_file = open.with_context(|| format!("Failed to open {}", archive.display()))?;
+    Archive::new(BufReader::new(archive_file)).unpack(layout.path())?;
+    let index = ImageIndex::from_file(layout.path().join("index.json"))?;
+    let [descriptor] = index.manifests().as_slice() else {
+        let count = index.manifests().len();
+        bail!("OCI archive must hold exactly one image, but held {count}");
+    };
+    let manifest = ImageManifest::from_file(blob_path(layout.path(), descriptor))?;
+    let rootfs = tempfile::tempdir()?;
+    for layer in manifest.layers() {
+        let blob = BufReader::new(File::open(blob_path(layout.path(), layer))?);
+        apply_layer(blob, layer.media_type().as_ref(), paths, rootfs.path())?;
+    }
+    Ok(rootfs)
+}
+
+/// Pull the given paths of an image from its registry into a temporary root
+/// directory. Layers are downloaded one at a time and dropped once applied.
+pub async fn pull_image(
+    image: &Reference,
+    auth: &RegistryAuth,
+    insecure_registries: &[String],
+    paths: &[&str],
+) -> Result<TempDir> {
+    let client = oci_client::Client::new(ClientConfig {
+        protocol: ClientProtocol::HttpsExcept(insecure_registries.to_vec()),
+        ..Default::default()
+    });
+    let (manifest, _) = client.pull_image_manifest(image, auth).await?;
+    let rootfs = tempfile::tempdir()?;
+    for layer in manifest.layers.iter() {
+        let mut blob = tempfile::tempfile()?;
+        let mut out = tokio::fs::File::from_std(blob.try_clone()?);
+        let pull = client.pull_blob(image, layer, &mut out).await;
+        pull.with_context(|| format!("Failed to pull layer {}", layer.digest))?;
+        out.sync_all().await?;
+        blob.rewind()?;
+        apply_layer(
+            BufReader::new(blob),
+            &layer.media_type,
+            paths,
+            rootfs.path(),
+        )?;
+    }
+    Ok(rootfs)
+}
+
+fn blob_path(layout: &Path, descriptor: &Descriptor) -> PathBuf {
+    let digest = descriptor.digest();
+    let blobs = layout.join("blobs").join(digest.algorithm().as_ref());
+    blobs.join(digest.digest())
+}
+
+/// Extract the given paths from a layer over the layers below it, and apply
+/// its whiteouts
+pub fn apply_layer<R: Read>(
+    blob: R,
+    media_type: &str,
+    paths: &[&str],
+    rootfs: &Path,
+) -> Result<()> {
+    let blob: Box<dyn Read> = match media_type {
+        t if t.ends_with("tar+gzip") || t.ends_with("tar.gzip") => Box::new(GzDecoder::new(blob)),
+        t if t.ends_with(".tar") => Box::new(blob),
+        t => bail!("Layer media type {t} is not supported"),
+    };
+    let rootfs = &rootfs.canonicalize()?;
+    let mut layer = Archive::new(blob);
+    // Entries that this layer added, which its opaque whiteouts keep
+    let mut added = Vec::new();
+    for entry in layer.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if !path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            bail!("Layer entry {} leaves the image root", path.display());
+        }
+        let path: PathBuf = path
+            .components()
+            .filter(|c| *c != Component::CurDir)
+            .collect();
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        let parent = rootfs.join(path.parent().unwrap_or(Path::new("")));
+        if name == OPAQUE_WHITEOUT {
+            // Hide what lower layers had in the directory
+            let Some(dir) = inside(rootfs, &parent)? else {
+                continue;
+            };
+            for child in fs::read_dir(dir)? {
+                let child = child?.path();
+                if !added.iter().any(|a: &PathBuf| a.starts_with(&child)) {
+                    remove(&child)?;
+                }
+            }
+        } else if let Some(hidden) = name.strip_prefix(WHITEOUT_PREFIX) {
+            if matches!(hidden, "" | "." | "..") {
+                bail!("Layer entry {} is not a valid whiteout", path.display());
+            }
+            if let Some(dir) = inside(rootfs, &parent)? {
+                remove(&dir.join(hidden))?;
+            }
+        } else if paths.iter().any(|p| path.starts_with(p)) {
+            entry.unpack_in(rootfs)?;
+            let unpacked = rootfs.join(&path);
+            // Unpacking does not run as root, so keep directories writable
+            // for the entries in them
+            if entry.header().entry_type().is_dir() {
+                let mode = fs::metadata(&unpacked)?.permissions().mode();
+                fs::set_permissions(&unpacked, Permissions::from_mode(mode | 0o700))?;
+            }
+            added.push(unpacked);
+        }
+    }
+    Ok(())
+}
+
+/// Canonical path of an existing directory, if it lies inside the
+/// canonical root. Whiteouts must not remove anything outside it through
+/// symlinks.
+fn inside(rootfs: &Path, dir: &Path) -> Result<Option<PathBuf>> {
+    let dir = match dir.canonicalize() {
+        Ok(dir) => dir,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if !dir.starts_with(rootfs) {
+        bail!("Whiteout in {} leaves the image root", dir.display());
+    }
+    Ok(Some(dir))
+}
+
+fn remove(path: &Path) -> Result<()> {
+    let removal = match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => Err(e),
+    };
+    removal.with_context(|| format!("Failed to remove {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{Compression, write::GzEncoder};
+    use std::io::Write;
+    use tar::{Builder, EntryType, Header};
+    use trusted_cluster_operator_test_utils::registry::*;
+
+    const TAR: &str = "application/vnd.oci.image.layer.v1.tar";
+    const TAR_GZIP: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+    const PATHS: &[&str] = &["usr/lib"];
+
+    enum Entry<'a> {
+        Dir(&'a str),
+        File(&'a str, &'a str),
+        Symlink(&'a str, &'a str),
+    }
+
+    /// Layer with the given entries. Their names are written as given,
+    /// which `Header::set_path` refuses for names that leave the root.
+    fn layer(entries: &[Entry]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        for entry in entries {
+            let (path, entry_type, content) = match entry {
+                Entry::Dir(path) => (path, EntryType::Directory, ""),
+                Entry::File(path, content) => (path, EntryType::Regular, *content),
+                Entry::Symlink(path, _) => (path, EntryType::Symlink, ""),
+            };
+            let mut header = Header::new_gnu();
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_entry_type(entry_type);
+            header.set_mode(if entry_type.is_dir() { 0o755 } else { 0o644 });
+            header.set_size(content.len() as u64);
+            if let Entry::Symlink(_, target) = entry {
+                header.set_link_name(target).unwrap();
+            }
+            header.set_cksum();
+            builder.append(&header, content.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Root with usr/lib/a and usr/lib/b, and a file outside of it
+    fn lower_layer(outside: &Path) -> (TempDir, TempDir) {
+        let rootfs = tempfile::tempdir().unwrap();
+        let blob = layer(&[
+            Entry::Dir("usr/"),
+            Entry::Dir("usr/lib/"),
+            Entry::File("usr/lib/a", "a"),
+            Entry::File("usr/lib/b", "b"),
+        ]);
+        apply_layer(blob.as_slice(), TAR, PATHS, rootfs.path()).unwrap();
+        let outside_dir = tempfile::tempdir_in(outside).unwrap();
+        fs::write(outside_dir.path().join("victim"), "victim").unwrap();
+        (rootfs, outside_dir)
+    }
+
+    fn lib(rootfs: &TempDir, name: &str) -> PathBuf {
+        rootfs.path().join("usr/lib").join(name)
+    }
+
+    #[test]
+    fn test_apply_layer() {
+        let rootfs = tempfile::tempdir().unwrap();
+        let blob = layer(&[
+            Entry::Dir("./usr/"),
+            Entry::Dir("./usr/lib/"),
+            Entry::File("./usr/lib/a", "a"),
+            Entry::Dir("./etc/"),
+            Entry::File("./etc/passwd", "root"),
+        ]);
+        apply_layer(gzip(&blob).as_slice(), TAR_GZIP, PATHS, rootfs.path()).unwrap();
+        assert_eq!(fs::read_to_string(lib(&rootfs, "a")).unwrap(), "a");
+        // Only the given paths are unpacked
+        assert!(!rootfs.path().join("etc").exists());
+    }
+
+    #[test]
+    fn test_apply_layer_unsupported_media_type() {
+        let rootfs = tempfile::tempdir().unwrap();
+        let blob = layer(&[Entry::Dir("usr/")]);
+        let media_type = "application/vnd.oci.image.layer.v1.tar+zstd";
+        assert!(apply_layer(blob.as_slice(), media_type, PATHS, rootfs.path()).is_err());
+    }
+
+    #[test]
+    fn test_apply_layer_escapes() {
+        let outside = tempfile::tempdir().unwrap();
+        for path in ["usr/lib/../../../escape", "/usr/lib/escape"] {
+            let rootfs = tempfile::tempdir().unwrap();
+            let blob = layer(&[Entry::File(path, "escape")]);
+            let err = apply_layer(blob.as_slice(), TAR, PATHS, rootfs.path()).unwrap_err();
+            assert!(err.to_string().contains("leaves the image root"));
+        }
+        // Entries through a symlink that leaves the root
+        let (rootfs, outside_dir) = lower_layer(outside.path());
+        let target = outside_dir.path().to_str().unwrap();
+        let blob = layer(&[
+            Entry::Symlink("usr/lib/link", target),
+            Entry::File("usr/lib/link/victim", "overwritten"),
+        ]);
+        assert!(apply_layer(blob.as_slice(), TAR, PATHS, rootfs.path()).is_err());
+        let victim = fs::read_to_string(outside_dir.path().join("victim")).unwrap();
+        assert_eq!(victim, "victim");
+    }
+
+    #[test]
+    fn test_apply_layer_whiteout() {
+        let outside = tempfile::tempdir().unwrap();
+        let (rootfs, _) = lower_layer(outside.path());
+        let blob = layer(&[Entry::File("usr/lib/.wh.a", "")]);
+        apply_layer(blob.as_slice(), TAR, PATHS, rootfs.path()).unwrap();
+        assert!(!lib(&rootfs, "a").exists());
+        assert!(lib(&rootfs, "b").exists());
+        // Whiteouts of what no layer had are fine
+        let blob = layer(&[Entry::File("opt/.wh.missing", "")]);
+        apply_layer(blob.as_slice(), TAR, PATHS, rootfs.path()).unwrap();
+        for path in ["usr/lib/.wh.", "usr/lib/.wh..", "usr/lib/.wh..."] {
+            let blob = layer(&[Entry::File(path, "")]);
+            let err = apply_layer(blob.as_slice(), TAR, PATHS, rootfs.path()).unwrap_err();
+            assert!(err.to_string().contains("not a valid whiteout"), "{path}");
+        }
+    }
+
+    #[test]
+    fn test_apply_layer_whiteout_symlink() {
+        let outside = tempfile::tempdir().unwrap();
+        let (rootfs, outside_dir) = lower_layer(outside.path());
+        let target = outside_dir.path().to_str().unwrap();
+        let blob = layer(&[Entry::Symlink("usr/lib/link", target)]);
+        apply_layer(blob.as_slice(), TAR, PATHS, rootfs.path()).unwrap();
+        for whiteout in ["usr/lib/link/.wh.victim", "usr/lib/link/.wh..wh..opq"] {
+            let blob = layer(&[Entry::File(whiteout, "")]);
+            let err = apply_layer(blob.as_slice(), TAR, PATHS, rootfs.path()).unwrap_err();
+            assert!(err.to_string().contains("leaves the image root"));
+            assert!(outside_dir.path().join("victim").exists());
+        }
+        // A whiteout of the symlink removes the symlink only
+        let blob = layer(&[Entry::File("usr/lib/.wh.link", "")]);
+        apply_layer(blob.as_slice(), TAR, PATHS, rootfs.path()).unwrap();
+        assert!(fs::symlink_metadata(lib(&rootfs, "link")).is_err());
+        assert!(outside_dir.path().join("victim").exists());
+    }
+
+    #[test]
+    fn test_apply_layer_opaque_whiteout() {
+        let outside = tempfile::tempdir().unwrap();
+        let (rootfs, _) = lower_layer(outside.path());
+        // Entries of the same layer stay, regardless of their order
+        let blob = layer(&[
+            Entry::File("usr/lib/c", "c"),
+            Entry::File("usr/lib/.wh..wh..opq", ""),
+        ]);
+        apply_layer(blob.as_slice(), TAR, PATHS, rootfs.path()).unwrap();
+        assert!(!lib(&rootfs, "a").exists());
+        assert!(!lib(&rootfs, "b").exists());
+        assert_eq!(fs::read_to_string(lib(&rootfs, "c")).unwrap(), "c");
+    }
+
+    /// Manifest of an image with the given gzipped layers
+    fn manifest(content: &mut Vec<(String, Vec<u8>)>, layers: &[Vec<u8>]) -> Vec<u8> {
+        let mut descriptor = |media_type: &str, data: Vec<u8>| {
+            let digest = sha256_digest(&data);
+            let size = data.len();
+            content.push((digest.clone(), data));
+            serde_json::json!({"mediaType": media_type, "digest": digest, "size": size})
+        };
+        let config_type = "application/vnd.oci.image.config.v1+json";
+        let config = descriptor(config_type, br#"{"rootfs":{}}"#.to_vec());
+        let layers: Vec<_> = layers
+            .iter()
+            .map(|l| descriptor(TAR_GZIP, gzip(l)))
+            .collect();
+        let manifest = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": OCI_MANIFEST_MEDIA_TYPE,
+            "config": config,
+            "layers": layers,
+        });
+        serde_json::to_vec(&manifest).unwrap()
+    }
+
+    fn image_layers() -> Vec<Vec<u8>> {
+        vec![
+            layer(&[
+                Entry::Dir("usr/"),
+                Entry::Dir("usr/lib/"),
+                Entry::File("usr/lib/a", "a"),
+                Entry::File("usr/lib/b", "b"),
+            ]),
+            layer(&[Entry::File("usr/lib/.wh.a", "")]),
+        ]
+    }
+
+    /// OCI archive with one image per manifest
+    fn oci_archive(manifests: usize) -> tempfile::NamedTempFile {
+        let mut blobs = Vec::new();
+        let mut descriptors = Vec::new();
+        for _ in 0..manifests {
+            let manifest = manifest(&mut blobs, &image_layers());
+            let digest = sha256_digest(&manifest);
+            descriptors.push(serde_json::json!({
+                "mediaType": OCI_MANIFEST_MEDIA_TYPE,
+                "digest": digest,
+                "size": manifest.len(),
+            }));
+            blobs.push((digest, manifest));
+        }
+        let index = serde_json::json!({"schemaVersion": 2, "manifests": descriptors});
+        let mut builder = Builder::new(Vec::new());
+        let mut append = |path: &str, data: &[u8]| {
+            let mut header = Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, path, data).unwrap();
+        };
+        append("index.json", &serde_json::to_vec(&index).unwrap());
+        for (digest, data) in blobs {
+            let path = format!("blobs/{}", digest.replace(':', "/"));
+            append(&path, &data);
+        }
+        let mut archive = tempfile::NamedTempFile::new().unwrap();
+        archive.write_all(&builder.into_inner().unwrap()).unwrap();
+        archive
+    }
+
+    #[test]
+    fn test_unpack_oci_archive() {
+        let archive = oci_archive(1);
+        let rootfs = unpack_oci_archive(archive.path(), PATHS).unwrap();
+        assert!(!lib(&rootfs, "a").exists());
+        assert_eq!(fs::read_to_string(lib(&rootfs, "b")).unwrap(), "b");
+    }
+
+    #[test]
+    fn test_unpack_oci_archive_several_images() {
+        let archive = oci_archive(2);
+        let err = unpack_oci_archive(archive.path(), PATHS).unwrap_err();
+        assert!(err.to_string().contains("exactly one image"));
+    }
+
+    #[tokio::test]
+    async fn test_pull_image() {
+        let mut blobs = Vec::new();
+        let manifest = manifest(&mut blobs, &image_layers());
+        let mut content = RegistryContent::default();
+        for (_, data) in blobs {
+            content.add_blob("os", &data);
+        }
+        content.add_manifest("os", "latest", &manifest);
+        let addr = serve_registry(content).await;
+        let image: Reference = format!("{addr}/os:latest").parse().unwrap();
+        let auth = RegistryAuth::Anonymous;
+        let rootfs = pull_image(&image, &auth, &[addr], PATHS).await.unwrap();
+        assert!(!lib(&rootfs, "a").exists());
+        assert_eq!(fs::read_to_string(lib(&rootfs, "b")).unwrap(), "b");
+    }
+
+    #[tokio::test]
+    async fn test_pull_image_missing_layer() {
+        let mut blobs = Vec::new();
+        let manifest = manifest(&mut blobs, &image_layers());
+        let mut content = RegistryContent::default();
+        content.add_manifest("os", "latest", &manifest);
+        let addr = serve_registry(content).await;
+        let image: Reference = format!("{addr}/os:latest").parse().unwrap();
+        let auth = RegistryAuth::Anonymous;
+        let err = pull_image(&image, &auth, &[addr], PATHS).await.unwrap_err();
+        assert!(err.to_string().contains("Failed to pull layer"));
+    }
+}
@@ -17,3 +17,15 @@ pub const COMMITTED_REASON: &str = "ImageCommitted";
 pub const NOT_COMMITTED_REASON_COMPUTING: &str = "Computing";
 pub const NOT_COMMITTED_REASON_NO_DIGEST: &str = "NoDigestGiven";
 pub const NOT_COMMITTED_REASON_FAILED: &str = "ComputationFailed";
+pub const NOT_COMMITTED_REASON_UNVERIFIED: &str = "SignatureUnverified";
+pub const NOT_COMMITTED_REASON_MISMATCH: &str = "PcrLabelMismatch";
+pub const NOT_COMMITTED_REASON_UNCOMPUTABLE: &str = "UncomputablePcrs";
+pub const NOT_COMMITTED_REASON_LABEL_UNUSABLE: &str = "PcrLabelUnusable";
+pub const NOT_COMMITTED_REASON_NO_ARCHITECTURE: &str = "ArchitectureUnavailable";
+
+pub const DEPRECATED_CONDITION: &str = "Deprecated";
+pub const DRAINING_REASON: &str = "Draining";
+
+pub const POLLED_CONDITION: &str = "Polled";
+pub const POLLED_REASON: &str = "PollSucceeded";
+pub const NOT_POLLED_REASON: &str = "PollFailed";
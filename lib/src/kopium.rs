@@ -3,5 +3,6 @@
 // SPDX-License-Identifier: MIT
 
 pub mod approvedimages;
+pub mod approvedimagestreams;
 pub mod machines;
 pub mod trustedexecutionclusters;
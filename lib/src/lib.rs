@@ -3,12 +3,14 @@
 // SPDX-License-Identifier: MIT
 
 pub mod conditions;
+pub mod pull_secrets;
 pub mod reference_values;
 
 mod kopium;
 #[allow(clippy::all)]
 mod vendor_kopium;
 pub use kopium::approvedimages::*;
+pub use kopium::approvedimagestreams::*;
 pub use kopium::machines::*;
 pub use kopium::trustedexecutionclusters::*;
 pub use vendor_kopium::virtualmachineinstances;
@@ -46,6 +48,12 @@ pub fn committed_condition(reason: &str, generation: Option<i64>) -> Condition {
                  Only images with a digest are supported to avoid ambiguity."
             }
             NOT_COMMITTED_REASON_FAILED => "Computation failed, check operator log for details",
+            NOT_COMMITTED_REASON_UNVERIFIED => {
+                "Image carried no signature matching the image verification policy"
+            }
+            NOT_COMMITTED_REASON_MISMATCH => {
+                "PCRs computed from the image did not match its org.coreos.pcrs label"
+            }
             _ => "",
         }
         .to_string(),
@@ -53,3 +61,23 @@ pub fn committed_condition(reason: &str, generation: Option<i64>) -> Condition {
         observed_generation: generation,
     }
 }
+
+/// ApprovedImage status that sets the Committed condition only. Other
+/// fields are kept when it is merge-patched.
+pub fn committed_status(committed: Condition) -> ApprovedImageStatus {
+    ApprovedImageStatus {
+        conditions: Some(vec![committed]),
+        resolved_image: None,
+        pcrs: None,
+        firmware_profile_pcrs: None,
+        platform_pcrs: None,
+        pcr_source: None,
+        compute_job: None,
+        compute_image: None,
+        compute_tool_version: None,
+        first_seen: None,
+        last_computed: None,
+        drain_deadline: None,
+        draining_machines: None,
+    }
+}
@@ -0,0 +1,98 @@
+// SPDX-FileCopyrightText: Jakob Naucke <jnaucke@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+use anyhow::{Context, Result, anyhow};
+use base64::{Engine as _, engine::general_purpose};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+pub const DOCKER_CONFIG_JSON_KEY: &str = ".dockerconfigjson";
+pub const DOCKER_CONFIG_KEY: &str = ".dockercfg";
+
+#[derive(Deserialize)]
+struct DockerConfigJson {
+    auths: DockerConfig,
+}
+
+/// Credentials per registry, optionally scoped to a repository path
+pub type DockerConfig = BTreeMap<String, DockerAuth>;
+
+#[derive(Deserialize)]
+pub struct DockerAuth {
+    auth: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl DockerAuth {
+    /// Username and password
+    pub fn credentials(&self) -> Result<(String, String)> {
+        if let (Some(username), Some(password)) = (&self.username, &self.password) {
+            return Ok((username.clone(), password.clone()));
+        }
+        let err = "Registry credentials had neither auth nor username & password";
+        let decoded = general_purpose::STANDARD.decode(self.auth.as_ref().context(err)?)?;
+        let decoded = String::from_utf8(decoded)?;
+        let err = "Registry auth was not of the form username:password";
+        let (username, password) = decoded.split_once(':').context(err)?;
+        Ok((username.to_string(), password.to_string()))
+    }
+}
+
+/// Docker config of a pull secret, given the data under each of its keys
+pub fn docker_config<'a>(
+    name: &str,
+    mut data: impl FnMut(&str) -> Option<&'a [u8]>,
+) -> Result<DockerConfig> {
+    if let Some(json) = data(DOCKER_CONFIG_JSON_KEY) {
+        let config: DockerConfigJson = serde_json::from_slice(json)?;
+        return Ok(config.auths);
+    }
+    if let Some(json) = data(DOCKER_CONFIG_KEY) {
+        return serde_json::from_slice(json).map_err(Into::into);
+    }
+    Err(anyhow!(
+        "Secret {name} is not a pull secret. \
+         It must have a {DOCKER_CONFIG_JSON_KEY} or {DOCKER_CONFIG_KEY} key."
+    ))
+}
+
+/// Strip scheme and API version, as kubelet does when matching pull
+/// secrets to images
+fn normalize_registry_key(key: &str) -> String {
+    let key = key
+        .trim_start_matches("https://")
+        .trim_start_matches("http://");
+    let key = key.trim_end_matches('/');
+    let key = key
+        .strip_suffix("/v1")
+        .or(key.strip_suffix("/v2"))
+        .unwrap_or(key);
+    match key {
+        "index.docker.io" | "registry-1.docker.io" => "docker.io".to_string(),
+        _ => key.to_string(),
+    }
+}
+
+/// Credentials for an image repository from the docker configs of pull
+/// secrets. The most specific matching entry wins; configs given first take
+/// precedence.
+pub fn registry_credentials(
+    configs: impl IntoIterator<Item = DockerConfig>,
+    registry: &str,
+    repository: &str,
+) -> Result<Option<(String, String)>> {
+    let image_path = format!("{registry}/{repository}");
+    let mut best: Option<(usize, DockerAuth)> = None;
+    for config in configs {
+        for (key, auth) in config {
+            let key = normalize_registry_key(&key);
+            let matches = image_path == key || image_path.starts_with(&format!("{key}/"));
+            if matches && best.as_ref().is_none_or(|(len, _)| key.len() > *len) {
+                best = Some((key.len(), auth));
+            }
+        }
+    }
+    best.map(|(_, auth)| auth.credentials()).transpose()
+}
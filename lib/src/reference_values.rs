@@ -3,32 +3,552 @@
 //
 // SPDX-License-Identifier: MIT
 
+use anyhow::{Context, Result, anyhow, bail};
+use base64::{Engine as _, engine::general_purpose};
 use compute_pcrs_lib::Pcr;
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use k8s_openapi::api::core::v1::ConfigMap;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
 use k8s_openapi::chrono::{DateTime, Utc};
+use kube::{Api, Client, api::ListParams};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Read, Write};
 
+use crate::ApprovedImageStatusPcrSource;
+
+/// Name prefix of the image PCRs shards, which is followed by their index.
+/// Before sharding, all image PCRs were stored under this name.
 pub const PCR_CONFIG_MAP: &str = "image-pcrs";
 pub const PCR_CONFIG_FILE: &str = "image-pcrs.json";
+/// Image PCRs are spread over this many ConfigMaps, so that they do not
+/// outgrow the object size limit. Images are assigned to shards by this
+/// count, so changing it requires moving all entries to their new shards.
+pub const PCR_SHARDS: usize = 8;
+/// Label that marks a ConfigMap as an image PCRs shard, with its index
+pub const PCR_SHARD_LABEL: &str = "trusted-execution-clusters.io/image-pcrs-shard";
+/// How often to reapply a modification of image PCRs that another writer
+/// raced with
+const MAX_CONFLICT_RETRIES: usize = 10;
+/// PCRs to compute and attest if neither cluster nor image select any
+pub const DEFAULT_PCR_SELECTION: [u64; 2] = [4, 14];
+/// PCRs that compute-pcrs can compute. Others can only be read from an
+/// image's label.
+pub const COMPUTABLE_PCRS: [u64; 3] = [4, 7, 14];
+/// PCRs that depend on a node's firmware and are computed per firmware profile
+pub const FIRMWARE_PROFILE_PCRS: [u64; 2] = [7, 14];
+/// Label that marks a ConfigMap as a firmware profile
+pub const FIRMWARE_PROFILE_LABEL: &str = "trusted-execution-clusters.io/firmware-profile";
+/// Key prefixes for EFI variables and MOK lists in firmware profile ConfigMaps
+pub const FIRMWARE_PROFILE_EFIVARS_PREFIX: &str = "efivars.";
+pub const FIRMWARE_PROFILE_MOKVARS_PREFIX: &str = "mokvars.";
+
+/// Directories of a bootable image that PCRs are computed from
+pub const IMAGE_KERNELS_DIR: &str = "usr/lib/modules";
+pub const IMAGE_ESP_DIR: &str = "usr/lib/bootupd/updates";
+
+/// PCRs as given in an image's org.coreos.pcrs label.
+/// Synchronize with compute_pcrs_cli::Output
+#[derive(Deserialize, Serialize)]
+pub struct ComputePcrsOutput {
+    pub pcrs: Vec<Pcr>,
+}
+
+/// Prefix of the log line that a compute-pcrs job reports its result on.
+/// Unlike termination messages, logs are not limited to 4 KiB.
+pub const RESULT_LOG_PREFIX: &str = "compute-pcrs-result: ";
+
+/// PCRs that a compute-pcrs job computed, as reported in its log. The
+/// operator validates and stores them.
+#[derive(Deserialize, Serialize)]
+pub struct ComputePcrsResult {
+    pub pcrs: Vec<Pcr>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub firmware_profile_pcrs: BTreeMap<String, Vec<Pcr>>,
+    pub tool_version: String,
+}
+
+impl ComputePcrsResult {
+    /// Compressed JSON, so that PCR parts for many firmware profiles stay
+    /// on a log line of moderate length
+    pub fn encode(&self) -> Result<String> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        serde_json::to_writer(&mut encoder, self)?;
+        encoder.flush()?;
+        Ok(general_purpose::STANDARD.encode(encoder.finish()?))
+    }
+
+    pub fn decode(message: &str) -> Result<Self> {
+        let compressed = general_purpose::STANDARD.decode(message.trim())?;
+        let mut json = Vec::new();
+        GzDecoder::new(compressed.as_slice()).read_to_end(&mut json)?;
+        serde_json::from_slice(&json).map_err(Into::into)
+    }
+
+    /// Log line that reports this result
+    pub fn log_line(&self) -> Result<String> {
+        Ok(format!("{RESULT_LOG_PREFIX}{}", self.encode()?))
+    }
+
+    /// Result that the last result line of a job's log reports
+    pub fn from_log(log: &str) -> Result<Self> {
+        let line = log
+            .lines()
+            .rev()
+            .find_map(|l| l.strip_prefix(RESULT_LOG_PREFIX));
+        Self::decode(line.context("Job logged no result")?)
+    }
+}
 
 #[derive(Deserialize, Serialize)]
 pub struct ImagePcr {
     pub first_seen: DateTime<Utc>,
     pub pcrs: Vec<Pcr>,
     pub reference: String,
+    /// Firmware-dependent PCRs per firmware profile, if profiles were
+    /// selected. These complement `pcrs`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub firmware_profile_pcrs: BTreeMap<String, Vec<Pcr>>,
+    /// Whether the PCRs were read from the image's label or computed.
+    /// Entries from before this was stored count as computed.
+    #[serde(default = "legacy_pcr_source")]
+    pub source: ApprovedImageStatusPcrSource,
+    /// compute-pcrs image that computed the PCRs, pinned to a digest if it
+    /// could be resolved. None for label PCRs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compute_image: Option<String>,
+    /// PCRs of the same reference that a different compute-pcrs image
+    /// computed. Both are trusted until all images have been recomputed by
+    /// that image, when these replace the entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub staged: Option<Box<ImagePcr>>,
+    /// PCRs per architecture of a multi-architecture image, which replace
+    /// `pcrs` and `firmware_profile_pcrs`. Architectures whose PCRs are
+    /// still being computed are null.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub platforms: BTreeMap<String, Option<PlatformPcrs>>,
+}
+
+/// Source of entries that were stored without one. Computed PCRs were not
+/// always stored with their compute-pcrs image, so these cannot be told
+/// apart from label PCRs and are recomputed.
+fn legacy_pcr_source() -> ApprovedImageStatusPcrSource {
+    ApprovedImageStatusPcrSource::Computed
+}
+
+/// PCRs of one architecture of a multi-architecture image
+#[derive(Deserialize, Serialize)]
+pub struct PlatformPcrs {
+    pub pcrs: Vec<Pcr>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub firmware_profile_pcrs: BTreeMap<String, Vec<Pcr>>,
+}
+
+/// Full PCR sets of PCRs and their firmware-dependent complements, one per
+/// firmware profile
+fn pcr_sets<'a>(
+    pcrs: &'a [Pcr],
+    firmware_profile_pcrs: &'a BTreeMap<String, Vec<Pcr>>,
+) -> Vec<Vec<&'a Pcr>> {
+    if firmware_profile_pcrs.is_empty() {
+        return vec![pcrs.iter().collect()];
+    }
+    let profiles = firmware_profile_pcrs.values();
+    profiles
+        .map(|profile_pcrs| pcrs.iter().chain(profile_pcrs).collect())
+        .collect()
+}
+
+impl ImagePcr {
+    /// Full PCR sets that a node booting this image can have, one per
+    /// firmware profile and architecture
+    pub fn pcr_sets(&self) -> Vec<Vec<&Pcr>> {
+        if self.platforms.is_empty() {
+            return pcr_sets(&self.pcrs, &self.firmware_profile_pcrs);
+        }
+        let platforms = self.platforms.values().flatten();
+        platforms
+            .flat_map(|p| pcr_sets(&p.pcrs, &p.firmware_profile_pcrs))
+            .collect()
+    }
+
+    /// Whether any PCR set is empty. Such a set would match any node, so
+    /// these PCRs must not be trusted.
+    pub fn has_empty_pcr_set(&self) -> bool {
+        self.pcr_sets().iter().any(Vec::is_empty)
+    }
+
+    /// Whether PCRs of all architectures have been stored
+    pub fn is_complete(&self) -> bool {
+        self.platforms.values().all(Option::is_some)
+    }
+
+    /// Take over PCRs of architectures that are still pending here from
+    /// other PCRs of the same image
+    fn fill_pending_platforms(&mut self, other: &mut ImagePcr) {
+        for (architecture, pcrs) in self.platforms.iter_mut() {
+            if pcrs.is_none() {
+                *pcrs = other.platforms.remove(architecture).flatten();
+            }
+        }
+    }
+
+    /// PCRs as the given compute-pcrs image yields them: those it staged,
+    /// or these if they were read from a label or computed by it. Computed
+    /// PCRs without a compute-pcrs image are stale.
+    pub fn by_compute_image(&self, compute_image: &str) -> Option<&ImagePcr> {
+        let staged = self.staged.as_deref();
+        let staged = staged.filter(|s| s.compute_image.as_deref() == Some(compute_image));
+        let current = match self.source {
+            ApprovedImageStatusPcrSource::Label => true,
+            ApprovedImageStatusPcrSource::Computed => {
+                self.compute_image.as_deref() == Some(compute_image)
+            }
+        };
+        staged.or(current.then_some(self))
+    }
 }
 
 #[derive(Default, Deserialize, Serialize)]
 pub struct ImagePcrs(pub BTreeMap<String, ImagePcr>);
 
-#[macro_export]
-macro_rules! update_image_pcrs {
-    ($api:ident, $map:ident, $pcrs:ident) => {
-        let image_pcrs_json = serde_json::to_string(&$pcrs)?;
-        let map = (PCR_CONFIG_FILE.to_string(), image_pcrs_json.to_string());
-        let data = std::collections::BTreeMap::from([map]);
-        $map.data = Some(data);
-        $api.replace(PCR_CONFIG_MAP, &Default::default(), &$map)
-            .await?
+pub fn get_image_pcrs(image_pcrs_map: &ConfigMap) -> Result<ImagePcrs> {
+    let err = "Image PCRs map existed, but had no data";
+    let image_pcrs_data = image_pcrs_map.data.as_ref().context(err)?;
+    let err = "Image PCRs data existed, but had no file";
+    let image_pcrs_str = image_pcrs_data.get(PCR_CONFIG_FILE).context(err)?;
+    serde_json::from_str(image_pcrs_str).map_err(Into::into)
+}
+
+pub fn pcrs_shard_name(shard: usize) -> String {
+    format!("{PCR_CONFIG_MAP}-{shard}")
+}
+
+/// Name of the shard that stores the PCRs of an image. FNV-1a is used
+/// because the shard must not change across builds and platforms.
+pub fn image_pcrs_shard_name(name: &str) -> String {
+    let hash = name.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    pcrs_shard_name((hash % PCR_SHARDS as u64) as usize)
+}
+
+/// Typed access to the image PCRs shards, which only the operator writes.
+/// Writes are conditional on the resourceVersion that was read and are
+/// reapplied to the latest image PCRs on conflict, so that concurrent
+/// reconciliations cannot overwrite each other.
+pub struct ImagePcrsStore {
+    config_maps: Api<ConfigMap>,
+}
+
+impl ImagePcrsStore {
+    pub fn new(client: Client) -> Self {
+        Self {
+            config_maps: Api::default_namespaced(client),
+        }
+    }
+
+    /// Shard that stores the PCRs of an image, along with its image PCRs
+    async fn get_shard(&self, name: &str) -> Result<(ConfigMap, ImagePcrs)> {
+        let image_pcrs_map = self.config_maps.get(&image_pcrs_shard_name(name)).await?;
+        let image_pcrs = get_image_pcrs(&image_pcrs_map)?;
+        Ok((image_pcrs_map, image_pcrs))
+    }
+
+    /// PCRs of all images, and the size of each shard's data in bytes
+    pub async fn get_with_shard_sizes(&self) -> Result<(ImagePcrs, Vec<usize>)> {
+        let params = ListParams::default().labels(PCR_SHARD_LABEL);
+        let mut shards = BTreeMap::new();
+        for image_pcrs_map in self.config_maps.list(&params).await?.items {
+            let err = "Image PCRs map existed, but had no name";
+            let name = image_pcrs_map.metadata.name.clone().context(err)?;
+            shards.insert(name, image_pcrs_map);
+        }
+        let mut image_pcrs = ImagePcrs::default();
+        let mut shard_sizes = Vec::with_capacity(PCR_SHARDS);
+        for name in (0..PCR_SHARDS).map(pcrs_shard_name) {
+            // A missing shard would silently drop its images
+            let image_pcrs_map = shards.get(&name);
+            let image_pcrs_map =
+                image_pcrs_map.with_context(|| format!("Image PCRs map {name} not found"))?;
+            let shard = get_image_pcrs(image_pcrs_map)?;
+            let data = image_pcrs_map.data.iter().flatten();
+            shard_sizes.push(data.map(|(k, v)| k.len() + v.len()).sum());
+            image_pcrs.0.extend(shard.0);
+        }
+        Ok((image_pcrs, shard_sizes))
+    }
+
+    pub async fn get(&self) -> Result<ImagePcrs> {
+        let get = self.get_with_shard_sizes();
+        get.await.map(|(image_pcrs, _)| image_pcrs)
+    }
+
+    /// PCRs of a single image, read from its shard only
+    pub async fn get_image(&self, name: &str) -> Result<Option<ImagePcr>> {
+        let (_, mut image_pcrs) = self.get_shard(name).await?;
+        Ok(image_pcrs.0.remove(name))
+    }
+
+    /// Store image PCRs into the shard they were read along with. False if
+    /// the shard was changed in the meantime.
+    async fn try_store(
+        &self,
+        mut image_pcrs_map: ConfigMap,
+        image_pcrs: &ImagePcrs,
+    ) -> Result<bool> {
+        // The resourceVersion that was read makes the replace conditional
+        let version = image_pcrs_map.metadata.resource_version.as_ref();
+        version.context("Image PCRs map existed, but had no resourceVersion")?;
+        let err = "Image PCRs map existed, but had no name";
+        let name = image_pcrs_map.metadata.name.clone().context(err)?;
+        let image_pcrs_json = serde_json::to_string(image_pcrs)?;
+        let data = BTreeMap::from([(PCR_CONFIG_FILE.to_string(), image_pcrs_json)]);
+        image_pcrs_map.data = Some(data);
+        let params = Default::default();
+        let replace = self.config_maps.replace(&name, &params, &image_pcrs_map);
+        match replace.await {
+            Ok(_) => Ok(true),
+            Err(kube::Error::Api(e)) if e.code == 409 => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Apply a modification to the shard of an image that returns whether
+    /// it changed anything, and store the result if it did. Returns whether
+    /// anything was stored.
+    pub async fn modify<F: FnMut(&mut ImagePcrs) -> bool>(
+        &self,
+        name: &str,
+        modify: F,
+    ) -> Result<bool> {
+        self.modify_shard(&image_pcrs_shard_name(name), modify)
+            .await
+    }
+
+    async fn modify_shard<F: FnMut(&mut ImagePcrs) -> bool>(
+        &self,
+        shard: &str,
+        mut modify: F,
+    ) -> Result<bool> {
+        for _ in 0..=MAX_CONFLICT_RETRIES {
+            let image_pcrs_map = self.config_maps.get(shard).await?;
+            let mut image_pcrs = get_image_pcrs(&image_pcrs_map)?;
+            if !modify(&mut image_pcrs) {
+                return Ok(false);
+            }
+            if self.try_store(image_pcrs_map, &image_pcrs).await? {
+                return Ok(true);
+            }
+        }
+        Err(conflict_error())
+    }
+
+    /// Insert or replace the PCRs of an image and return them as stored.
+    /// PCRs that replace those of the same reference keep their first_seen.
+    /// If a different compute-pcrs image computed those, the new PCRs are
+    /// staged instead of replacing them. Architectures that are pending in
+    /// the new PCRs keep those that the same compute-pcrs image stored.
+    /// PCRs with an empty PCR set are rejected.
+    pub async fn insert(&self, name: &str, mut image_pcr: ImagePcr) -> Result<ImagePcr> {
+        if image_pcr.has_empty_pcr_set() {
+            bail!("Refusing to store an empty PCR set for image {name}");
+        }
+        for _ in 0..=MAX_CONFLICT_RETRIES {
+            let (image_pcrs_map, mut image_pcrs) = self.get_shard(name).await?;
+            let existing = image_pcrs.0.remove(name);
+            let existing = existing.filter(|e| e.reference == image_pcr.reference);
+            image_pcr.staged = None;
+            let staging = existing.as_ref().is_some_and(|e| {
+                let (old, new) = (&e.compute_image, &image_pcr.compute_image);
+                old.is_some() && new.is_some() && old != new
+            });
+            let entry = match existing {
+                Some(mut existing) if staging => {
+                    image_pcr.first_seen = existing.first_seen;
+                    let staged = existing.staged.take();
+                    let same_image = staged.filter(|s| s.compute_image == image_pcr.compute_image);
+                    if let Some(mut staged) = same_image {
+                        image_pcr.fill_pending_platforms(&mut staged);
+                    }
+                    existing.staged = Some(Box::new(image_pcr));
+                    existing
+                }
+                Some(mut existing) => {
+                    if existing.compute_image == image_pcr.compute_image {
+                        image_pcr.fill_pending_platforms(&mut existing);
+                    }
+                    image_pcr.first_seen = existing.first_seen;
+                    // Still staged for a change of the compute-pcrs image
+                    let computed = image_pcr.source == ApprovedImageStatusPcrSource::Computed;
+                    image_pcr.staged = existing.staged.filter(|_| computed);
+                    image_pcr
+                }
+                None => image_pcr,
+            };
+            image_pcrs.0.insert(name.to_string(), entry);
+            let stored = self.try_store(image_pcrs_map, &image_pcrs).await?;
+            // Reclaim the entry to return it or to retry with it
+            let err = "Inserted image PCRs vanished";
+            let entry = image_pcrs.0.remove(name).context(err)?;
+            image_pcr = match staging {
+                true => *entry.staged.context(err)?,
+                false => entry,
+            };
+            if stored {
+                return Ok(image_pcr);
+            }
+        }
+        Err(conflict_error())
+    }
+
+    /// Complete a change of the compute-pcrs image: Replace the PCRs of the
+    /// given images by those staged by that image, and drop those staged by
+    /// any other image.
+    pub async fn promote_staged<'a>(
+        &self,
+        names: impl IntoIterator<Item = &'a str>,
+        compute_image: &str,
+    ) -> Result<()> {
+        let shards: BTreeSet<_> = names.into_iter().map(image_pcrs_shard_name).collect();
+        for shard in shards {
+            let promote = |image_pcrs: &mut ImagePcrs| {
+                let mut changed = false;
+                for image_pcr in image_pcrs.0.values_mut() {
+                    let Some(staged) = image_pcr.staged.take() else {
+                        continue;
+                    };
+                    if staged.compute_image.as_deref() == Some(compute_image) {
+                        *image_pcr = *staged;
+                    }
+                    changed = true;
+                }
+                changed
+            };
+            self.modify_shard(&shard, promote).await?;
+        }
+        Ok(())
+    }
+
+    /// Remove the PCRs of an image, and those it staged, if some of their
+    /// architectures are still pending, so that no architecture is trusted
+    /// once the computation of another one failed. Returns whether there
+    /// were any.
+    pub async fn discard_incomplete(&self, name: &str) -> Result<bool> {
+        self.modify(name, |image_pcrs| {
+            let Some(image_pcr) = image_pcrs.0.get_mut(name) else {
+                return false;
+            };
+            let staged = image_pcr.staged.take_if(|s| !s.is_complete()).is_some();
+            let incomplete = !image_pcr.is_complete();
+            if incomplete {
+                image_pcrs.0.remove(name);
+            }
+            staged || incomplete
+        })
+        .await
+    }
+
+    /// Remove the PCRs of an image. Returns whether there were any.
+    pub async fn remove(&self, name: &str) -> Result<bool> {
+        self.modify(name, |image_pcrs| image_pcrs.0.remove(name).is_some())
+            .await
+    }
+
+    /// Copy the entries of the image PCRs map from before sharding into
+    /// the shards, unless the shards have newer entries for the images.
+    /// Returns whether there was such a map.
+    pub async fn import_unsharded(&self) -> Result<bool> {
+        let Some(legacy_map) = self.config_maps.get_opt(PCR_CONFIG_MAP).await? else {
+            return Ok(false);
+        };
+        let legacy = get_image_pcrs(&legacy_map)?;
+        // Entries are kept as JSON to reapply them on conflict
+        let mut shards: BTreeMap<_, BTreeMap<_, _>> = BTreeMap::new();
+        for (name, image_pcr) in legacy.0 {
+            let shard = shards.entry(image_pcrs_shard_name(&name)).or_default();
+            shard.insert(name, serde_json::to_value(image_pcr)?);
+        }
+        for (shard, entries) in shards {
+            let import = |image_pcrs: &mut ImagePcrs| {
+                let mut changed = false;
+                for (name, image_pcr) in entries.iter() {
+                    let image_pcr = serde_json::from_value(image_pcr.clone());
+                    if let (false, Ok(image_pcr)) = (image_pcrs.0.contains_key(name), image_pcr) {
+                        image_pcrs.0.insert(name.clone(), image_pcr);
+                        changed = true;
+                    }
+                }
+                changed
+            };
+            self.modify_shard(&shard, import).await?;
+        }
+        Ok(true)
+    }
+
+    /// Delete the image PCRs map from before sharding
+    pub async fn delete_unsharded(&self) -> Result<()> {
+        let params = Default::default();
+        match self.config_maps.delete(PCR_CONFIG_MAP, &params).await {
+            Ok(_) => Ok(()),
+            Err(kube::Error::Api(e)) if e.code == 404 => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+fn conflict_error() -> anyhow::Error {
+    anyhow!("Image PCRs were changed concurrently more than {MAX_CONFLICT_RETRIES} times in a row")
+}
+
+/// compute-pcrs job that computed the PCRs of an image
+pub struct PcrsComputation {
+    pub job: String,
+    pub image: String,
+    pub tool_version: String,
+}
+
+/// ApprovedImage status fields that describe stored PCRs and where they
+/// came from. Fields that do not apply are null, so that a merge patch
+/// removes those left over from an earlier computation.
+pub fn pcrs_status(image_pcr: &ImagePcr, computation: Option<&PcrsComputation>) -> Value {
+    let values = |pcrs: &[Pcr]| {
+        let values = pcrs.iter().map(|p| json!({"id": p.id, "value": p.value}));
+        values.collect::<Vec<_>>()
+    };
+    let profiles = |firmware_profile_pcrs: &BTreeMap<String, Vec<Pcr>>| {
+        let profiles = firmware_profile_pcrs.iter();
+        let profiles: Vec<_> = profiles
+            .map(|(profile, pcrs)| json!({"profile": profile, "pcrs": values(pcrs)}))
+            .collect();
+        (!profiles.is_empty()).then_some(profiles)
+    };
+    let platforms = image_pcr.platforms.iter();
+    let platforms: Vec<_> = platforms
+        .filter_map(|(architecture, pcrs)| Some((architecture, pcrs.as_ref()?)))
+        .map(|(architecture, platform)| {
+            json!({
+                "architecture": architecture,
+                "pcrs": values(&platform.pcrs),
+                "firmwareProfilePcrs": profiles(&platform.firmware_profile_pcrs),
+            })
+        })
+        .collect();
+    let source = match computation {
+        Some(_) => ApprovedImageStatusPcrSource::Computed,
+        None => ApprovedImageStatusPcrSource::Label,
     };
+    json!({
+        "pcrs": values(&image_pcr.pcrs),
+        "firmwareProfilePcrs": profiles(&image_pcr.firmware_profile_pcrs),
+        "platformPcrs": (!platforms.is_empty()).then_some(platforms),
+        "pcrSource": source,
+        "computeJob": computation.map(|c| &c.job),
+        "computeImage": computation.map(|c| &c.image),
+        "computeToolVersion": computation.map(|c| &c.tool_version),
+        "firstSeen": Time(image_pcr.first_seen),
+        // Cleared for label PCRs, which were never computed
+        "lastComputed": computation.map(|_| Time(Utc::now())),
+    })
 }
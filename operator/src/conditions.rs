@@ -6,9 +6,18 @@ use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
 use k8s_openapi::chrono::Utc;
 use trusted_cluster_operator_lib::{condition_status, conditions::*};
 
-pub fn known_trustee_address_condition(known: bool, generation: Option<i64>) -> Condition {
-    let err = "No publicTrusteeAddr specified. Components can deploy, \
-               but register-server will not be able to point to Trustee until you add an address";
+pub fn known_trustee_address_condition(
+    known: bool,
+    exposed: bool,
+    generation: Option<i64>,
+) -> Condition {
+    let err = match exposed {
+        true => "Waiting for an address to be assigned to the Trustee endpoint",
+        false => {
+            "No publicTrusteeAddr specified. Components can deploy, \
+             but register-server will not be able to point to Trustee until you add an address"
+        }
+    };
     let (reason, message) = match known {
         true => (KNOWN_TRUSTEE_ADDRESS_REASON, ""),
         false => (UNKNOWN_TRUSTEE_ADDRESS_REASON, err),
@@ -0,0 +1,476 @@
+// SPDX-FileCopyrightText: Jakob Naucke <jnaucke@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+use anyhow::{Context, Result, anyhow};
+use futures_util::StreamExt;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+use k8s_openapi::chrono::{DateTime, SecondsFormat, Utc};
+use kube::api::{DeleteParams, ListParams, ObjectMeta};
+use kube::runtime::controller::{Action, Controller};
+use kube::{Api, Resource};
+use log::{info, warn};
+use oci_client::Reference;
+use serde::Deserialize;
+use std::{cmp::Reverse, collections::BTreeMap, sync::Arc, time::Duration};
+
+use crate::registry;
+use operator::{
+    ControllerError, RvContextData, controller_error_policy, controller_info,
+    create_or_info_if_exists, elapsed_since,
+};
+use trusted_cluster_operator_lib::{conditions::*, *};
+
+/// ApprovedImageStream that an ApprovedImage was created by
+const STREAM_LABEL_KEY: &str = "trusted-execution-clusters.io/approved-image-stream";
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(3600);
+const FAILED_POLL_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_ARCHITECTURE: &str = "x86_64";
+
+/// Subset of CoreOS stream metadata, see
+/// https://github.com/coreos/stream-metadata-go
+#[derive(Deserialize)]
+struct StreamMetadata {
+    architectures: BTreeMap<String, StreamArchitecture>,
+}
+
+#[derive(Deserialize)]
+struct StreamArchitecture {
+    #[serde(default)]
+    images: BTreeMap<String, StreamImage>,
+}
+
+#[derive(Deserialize)]
+struct StreamImage {
+    #[serde(rename = "digest-ref")]
+    digest_ref: Option<String>,
+}
+
+fn conditions(stream: &ApprovedImageStream) -> impl Iterator<Item = &Condition> {
+    let conditions = stream.status.as_ref().and_then(|s| s.conditions.as_ref());
+    conditions.into_iter().flatten()
+}
+
+/// Polled condition for the stream. Its transition time is kept from the
+/// previous condition if the status did not change.
+fn polled_condition(stream: &ApprovedImageStream, reason: &str, message: String) -> Condition {
+    let status = condition_status(reason == POLLED_REASON);
+    let previous = conditions(stream).find(|c| c.type_ == POLLED_CONDITION);
+    let previous = previous.filter(|c| c.status == status);
+    let last_transition_time = previous.map(|c| c.last_transition_time.clone());
+    Condition {
+        type_: POLLED_CONDITION.to_string(),
+        status,
+        reason: reason.to_string(),
+        message,
+        last_transition_time: last_transition_time.unwrap_or(Time(Utc::now())),
+        observed_generation: stream.metadata.generation,
+    }
+}
+
+fn last_polled(stream: &ApprovedImageStream) -> Option<Time> {
+    let last_polled = stream.status.as_ref()?.last_polled.as_deref()?;
+    let last_polled = DateTime::parse_from_rfc3339(last_polled).ok()?;
+    Some(Time(last_polled.with_timezone(&Utc)))
+}
+
+/// Time until the stream is to be polled next. Status updates trigger
+/// reconciliation too, so this spaces polls by the last one.
+fn until_next_poll(stream: &ApprovedImageStream) -> Duration {
+    let last_poll = conditions(stream).find(|c| {
+        c.type_ == POLLED_CONDITION && c.observed_generation == stream.metadata.generation
+    });
+    let (Some(last_poll), Some(last_polled)) = (last_poll, last_polled(stream)) else {
+        return Duration::ZERO;
+    };
+    let interval = match last_poll.reason.as_str() {
+        POLLED_REASON => poll_interval(stream),
+        _ => FAILED_POLL_INTERVAL,
+    };
+    interval.saturating_sub(elapsed_since(Some(&last_polled)))
+}
+
+/// The CRD only admits positive intervals
+fn poll_interval(stream: &ApprovedImageStream) -> Duration {
+    let secs = stream.spec.poll_interval_seconds;
+    secs.map(|s| Duration::from_secs(s as u64))
+        .unwrap_or(DEFAULT_POLL_INTERVAL)
+}
+
+/// Digest reference of the image that a tag currently points to
+async fn latest_tagged(ctx: &RvContextData, tag: &str, pull_secrets: &[String]) -> Result<String> {
+    let image_ref: Reference = tag.parse()?;
+    let secrets = registry::fetch_pull_secrets(ctx.client.clone(), pull_secrets).await?;
+    let auth = registry::registry_auth(&secrets, &image_ref)?;
+    let registry_client = registry::oci_client(&ctx.insecure_registries);
+    let digest = registry_client
+        .fetch_manifest_digest(&image_ref, &auth)
+        .await?;
+    let registry = image_ref.registry().to_string();
+    let repository = image_ref.repository().to_string();
+    Ok(Reference::with_digest(registry, repository, digest).whole())
+}
+
+/// Digest reference of the image that stream metadata currently lists
+async fn latest_in_metadata(
+    ctx: &RvContextData,
+    metadata: &ApprovedImageStreamStreamMetadata,
+) -> Result<String> {
+    let client = registry::http_client(&metadata.url, &ctx.insecure_registries)?;
+    let response = client.get(&metadata.url).send().await?;
+    let response = response.error_for_status()?;
+    let stream: StreamMetadata = response.json().await?;
+    let arch = metadata.architecture.as_deref();
+    let arch = arch.unwrap_or(DEFAULT_ARCHITECTURE);
+    let err = format!("Stream metadata had no architecture {arch}");
+    let images = &stream.architectures.get(arch).context(err)?.images;
+    let err = format!("Stream metadata had no image {} for {arch}", metadata.image);
+    let image = images.get(&metadata.image).context(err)?;
+    let err = format!("Stream metadata image {} had no digest-ref", metadata.image);
+    image.digest_ref.clone().context(err)
+}
+
+/// Name for an image of a stream, disambiguated by digest
+fn image_name(stream_name: &str, image: &str) -> Result<String> {
+    let image_ref: Reference = image.parse()?;
+    let err = format!("Image {image} had no digest");
+    let digest = image_ref.digest().context(err)?;
+    let hex = digest.split_once(':').map(|(_, hex)| hex).unwrap_or(digest);
+    let stream_name: String = stream_name.chars().take(240).collect();
+    Ok(format!("{stream_name}-{}", &hex[..hex.len().min(12)]))
+}
+
+fn is_committed(image: &ApprovedImage) -> bool {
+    let conditions = image.status.as_ref().and_then(|s| s.conditions.as_ref());
+    conditions
+        .into_iter()
+        .flatten()
+        .any(|c| c.type_ == COMMITTED_CONDITION && c.status == "True")
+}
+
+/// Images of a stream to retire. An image is retired if it is beyond the
+/// number of images to keep, or if a newer image has appeared long enough
+/// ago. Only images older than the newest committed image are retired, so
+/// that an image remains approved until its successor can take over.
+fn images_to_retire(
+    images: &[ApprovedImage],
+    keep_latest: Option<usize>,
+    retire_after: Option<Duration>,
+) -> Vec<String> {
+    let mut images: Vec<_> = images.iter().collect();
+    images.sort_by_key(|i| Reverse(i.metadata.creation_timestamp.as_ref().map(|t| t.0)));
+    let Some(newest_committed) = images.iter().position(|i| is_committed(i)) else {
+        return vec![];
+    };
+    let retire = |idx: usize| {
+        let superseded_for = elapsed_since(images[idx - 1].metadata.creation_timestamp.as_ref());
+        keep_latest.is_some_and(|n| idx >= n) || retire_after.is_some_and(|d| superseded_for >= d)
+    };
+    let indices = newest_committed + 1..images.len();
+    let retired = indices.filter(|idx| retire(*idx));
+    let names = retired.filter_map(|idx| images[idx].metadata.name.clone());
+    names.collect()
+}
+
+/// Poll a stream, approve its latest image if it is new, and retire older
+/// images. Returns the latest image.
+async fn poll_stream(ctx: &RvContextData, stream: &ApprovedImageStream) -> Result<String> {
+    let name = stream.metadata.name.as_deref().unwrap_or_default();
+    let stream_secrets = stream.spec.image_pull_secrets.iter().flatten();
+    let stream_secrets: Vec<_> = stream_secrets.filter_map(|s| s.name.clone()).collect();
+    let pull_secrets: Vec<_> = stream_secrets
+        .iter()
+        .cloned()
+        .chain(ctx.image_pull_secrets.clone())
+        .collect();
+    let latest = match (&stream.spec.tag, &stream.spec.stream_metadata) {
+        (Some(tag), _) => latest_tagged(ctx, tag, &pull_secrets).await?,
+        (None, Some(metadata)) => latest_in_metadata(ctx, metadata).await?,
+        (None, None) => return Err(anyhow!("Stream {name} had neither tag nor streamMetadata")),
+    };
+
+    let images: Api<ApprovedImage> = Api::default_namespaced(ctx.client.clone());
+    let params = ListParams::default().labels(&format!("{STREAM_LABEL_KEY}={name}"));
+    let stream_images = images.list(&params).await?.items;
+    if !stream_images.iter().any(|i| i.spec.image == latest) {
+        let secret_refs = stream_secrets
+            .iter()
+            .map(|s| ApprovedImageImagePullSecrets {
+                name: Some(s.clone()),
+            });
+        let image = ApprovedImage {
+            metadata: ObjectMeta {
+                name: Some(image_name(name, &latest)?),
+                labels: Some(BTreeMap::from([(
+                    STREAM_LABEL_KEY.to_string(),
+                    name.to_string(),
+                )])),
+                ..Default::default()
+            },
+            spec: ApprovedImageSpec {
+                image: latest.clone(),
+                pcr_selection: None,
+                firmware_profiles: None,
+                image_pull_secrets: Some(secret_refs.collect()),
+                resolve_tag: None,
+                drain_seconds: None,
+            },
+            status: None,
+        };
+        create_or_info_if_exists!(ctx.client.clone(), ApprovedImage, image);
+    }
+
+    let keep_latest = stream.spec.keep_latest.map(|n| n as usize);
+    let retire_after = stream.spec.retire_after_seconds;
+    let retire_after = retire_after.map(|s| Duration::from_secs(s as u64));
+    for retired in images_to_retire(&stream_images, keep_latest, retire_after) {
+        info!("Retiring image {retired} of stream {name}");
+        images.delete(&retired, &DeleteParams::default()).await?;
+    }
+    Ok(latest)
+}
+
+async fn stream_reconcile(
+    stream: Arc<ApprovedImageStream>,
+    ctx: Arc<RvContextData>,
+) -> Result<Action, ControllerError> {
+    let err = "ApprovedImageStream had no name";
+    let name = stream.metadata.name.clone().context(err)?;
+    let until_next_poll = until_next_poll(&stream);
+    if !until_next_poll.is_zero() {
+        return Ok(Action::requeue(until_next_poll));
+    }
+
+    let (condition, latest_image, action) = match poll_stream(&ctx, &stream).await {
+        Ok(latest) => {
+            let message = format!("Stream pointed to {latest}");
+            let condition = polled_condition(&stream, POLLED_REASON, message);
+            let action = Action::requeue(poll_interval(&stream));
+            (condition, Some(latest), action)
+        }
+        Err(e) => {
+            warn!("Polling stream {name} failed: {e}");
+            let message = format!("Polling failed: {e:#}");
+            let condition = polled_condition(&stream, NOT_POLLED_REASON, message);
+            (condition, None, Action::requeue(FAILED_POLL_INTERVAL))
+        }
+    };
+    let last_polled = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+    let status = ApprovedImageStreamStatus {
+        conditions: Some(vec![condition]),
+        latest_image,
+        last_polled: Some(last_polled),
+    };
+    let streams: Api<ApprovedImageStream> = Api::default_namespaced(ctx.client.clone());
+    update_status!(streams, &name, status)?;
+    Ok(action)
+}
+
+pub async fn launch_image_stream_controller(ctx: RvContextData) {
+    let streams: Api<ApprovedImageStream> = Api::default_namespaced(ctx.client.clone());
+    tokio::spawn(
+        Controller::new(streams, Default::default())
+            .run(stream_reconcile, controller_error_policy, Arc::new(ctx))
+            .for_each(controller_info),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use http::{Method, Request};
+    use kube::{api::ObjectList, client::Body};
+    use trusted_cluster_operator_test_utils::mock_client::*;
+    use trusted_cluster_operator_test_utils::registry::*;
+
+    fn dummy_stream(tag: Option<String>) -> ApprovedImageStream {
+        let mut stream = ApprovedImageStream::new(
+            "stream",
+            ApprovedImageStreamSpec {
+                tag,
+                stream_metadata: None,
+                poll_interval_seconds: None,
+                keep_latest: None,
+                retire_after_seconds: None,
+                image_pull_secrets: None,
+            },
+        );
+        stream.metadata.generation = Some(1);
+        stream
+    }
+
+    fn dummy_stream_image(name: &str, age_secs: u64, committed: bool) -> ApprovedImage {
+        let reason = match committed {
+            true => COMMITTED_REASON,
+            false => NOT_COMMITTED_REASON_COMPUTING,
+        };
+        let mut image = ApprovedImage::new(
+            name,
+            ApprovedImageSpec {
+                image: format!("quay.io/org/os@sha256:{name}"),
+                pcr_selection: None,
+                firmware_profiles: None,
+                image_pull_secrets: None,
+                resolve_tag: None,
+                drain_seconds: None,
+            },
+        );
+        let created = Utc::now() - Duration::from_secs(age_secs);
+        image.metadata.creation_timestamp = Some(Time(created));
+        image.status = Some(committed_status(committed_condition(reason, None)));
+        image
+    }
+
+    #[test]
+    fn test_images_to_retire_keep_latest() {
+        let images = [
+            dummy_stream_image("a", 400, true),
+            dummy_stream_image("c", 200, true),
+            dummy_stream_image("d", 100, true),
+            dummy_stream_image("b", 300, true),
+        ];
+        let retired = images_to_retire(&images, Some(2), None);
+        assert_eq!(retired, vec!["b", "a"]);
+    }
+
+    #[test]
+    fn test_images_to_retire_after() {
+        let images = [
+            dummy_stream_image("a", 4000, true),
+            dummy_stream_image("b", 3000, true),
+            dummy_stream_image("c", 100, true),
+        ];
+        let retired = images_to_retire(&images, None, Some(Duration::from_secs(1800)));
+        assert_eq!(retired, vec!["a"]);
+    }
+
+    #[test]
+    fn test_images_to_retire_uncommitted() {
+        let images = [
+            dummy_stream_image("a", 300, true),
+            dummy_stream_image("b", 200, true),
+            dummy_stream_image("c", 100, false),
+        ];
+        let retired = images_to_retire(&images, Some(1), Some(Duration::ZERO));
+        assert_eq!(retired, vec!["a"]);
+        assert!(images_to_retire(&images[2..], Some(1), None).is_empty());
+    }
+
+    #[test]
+    fn test_image_name() {
+        let digest = "0123456789abcdef".repeat(4);
+        let image = format!("quay.io/org/os@sha256:{digest}");
+        assert_eq!(image_name("os", &image).unwrap(), "os-0123456789ab");
+        assert!(image_name("os", "quay.io/org/os:latest").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_latest_in_metadata() {
+        let mut content = RegistryContent::default();
+        let metadata = serde_json::json!({
+            "architectures": {
+                "x86_64": {
+                    "images": {
+                        "kubevirt": {
+                            "image": "quay.io/org/os:stable",
+                            "digest-ref": "quay.io/org/os@sha256:0123",
+                        },
+                    },
+                },
+            },
+        });
+        let data = metadata.to_string().into_bytes();
+        content.add_file("/streams/stable.json", "application/json", &data);
+        let addr = serve_registry(content).await;
+        let clos = async |req: Request<_>, _| panic!("unexpected API interaction: {req:?}");
+        count_check!(0, clos, |client| {
+            let mut ctx = generate_rv_ctx(client);
+            let mut metadata = ApprovedImageStreamStreamMetadata {
+                url: format!("http://{addr}/streams/stable.json"),
+                architecture: None,
+                image: "kubevirt".to_string(),
+            };
+            // Plain HTTP only for insecure registries
+            assert!(latest_in_metadata(&ctx, &metadata).await.is_err());
+            ctx.insecure_registries = vec![addr.clone()];
+            let latest = latest_in_metadata(&ctx, &metadata).await.unwrap();
+            assert_eq!(latest, "quay.io/org/os@sha256:0123");
+            metadata.architecture = Some("aarch64".to_string());
+            assert!(latest_in_metadata(&ctx, &metadata).await.is_err());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_stream_reconcile_new_image() {
+        let mut content = RegistryContent::default();
+        let manifest = br#"{"schemaVersion":2}"#;
+        content.add_manifest("os", "stable", manifest);
+        let addr = serve_registry(content).await;
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => {
+                assert!(req.uri().query().unwrap().contains("approved-image-stream"));
+                let images = ObjectList::<ApprovedImage> {
+                    types: Default::default(),
+                    metadata: Default::default(),
+                    items: vec![dummy_stream_image("old", 100, true)],
+                };
+                Ok(serde_json::to_string(&images).unwrap())
+            }
+            (1, &Method::POST) => {
+                assert_body_contains(req, "/os@sha256:").await;
+                Ok(serde_json::to_string(&dummy_stream_image("new", 0, false)).unwrap())
+            }
+            (2, &Method::PATCH) => {
+                let body = req.into_body().collect_bytes().await.unwrap();
+                let body = String::from_utf8_lossy(&body);
+                assert!(body.contains(POLLED_REASON) && body.contains("lastPolled"));
+                Ok(serde_json::to_string(&dummy_stream(None)).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(3, clos, |client| {
+            let mut ctx = generate_rv_ctx(client);
+            ctx.insecure_registries = vec![addr.clone()];
+            let stream = dummy_stream(Some(format!("{addr}/os:stable")));
+            let result = stream_reconcile(Arc::new(stream), Arc::new(ctx)).await;
+            assert_eq!(result.unwrap(), Action::requeue(DEFAULT_POLL_INTERVAL));
+        });
+    }
+
+    #[tokio::test]
+    async fn test_stream_reconcile_recently_polled() {
+        let clos = async |req: Request<_>, _| panic!("unexpected API interaction: {req:?}");
+        count_check!(0, clos, |client| {
+            let ctx = generate_rv_ctx(client);
+            let mut stream = dummy_stream(Some("quay.io/org/os:stable".to_string()));
+            let condition = polled_condition(&stream, POLLED_REASON, String::new());
+            stream.status = Some(ApprovedImageStreamStatus {
+                conditions: Some(vec![condition]),
+                latest_image: None,
+                last_polled: Some(Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)),
+            });
+            let result = stream_reconcile(Arc::new(stream), Arc::new(ctx)).await;
+            assert_ne!(result.unwrap(), Action::await_change());
+        });
+    }
+
+    #[test]
+    fn test_polled_condition_transition() {
+        let mut stream = dummy_stream(None);
+        let mut condition = polled_condition(&stream, POLLED_REASON, String::new());
+        let transitioned = Utc::now() - Duration::from_secs(600);
+        condition.last_transition_time = Time(transitioned);
+        stream.status = Some(ApprovedImageStreamStatus {
+            conditions: Some(vec![condition]),
+            latest_image: None,
+            last_polled: None,
+        });
+        // No recorded poll time, so poll right away
+        assert!(until_next_poll(&stream).is_zero());
+        let repolled = polled_condition(&stream, POLLED_REASON, String::new());
+        assert_eq!(repolled.last_transition_time, Time(transitioned));
+        let failed = polled_condition(&stream, NOT_POLLED_REASON, String::new());
+        assert_ne!(failed.last_transition_time, Time(transitioned));
+    }
+}
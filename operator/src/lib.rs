@@ -9,17 +9,322 @@
 // Use in other crates is not an intended purpose.
 
 use anyhow::Context;
-use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
-use kube::{Client, Resource, runtime::controller::Action};
+use k8s_openapi::api::core::v1::{
+    Affinity, Capabilities, EmptyDirVolumeSource, LocalObjectReference, PodSpec,
+    ResourceRequirements, SeccompProfile, SecurityContext, ServiceAccount, Toleration, Volume,
+    VolumeMount,
+};
+use k8s_openapi::api::networking::v1::{
+    IPBlock, NetworkPolicy, NetworkPolicyEgressRule, NetworkPolicyIngressRule, NetworkPolicyPeer,
+    NetworkPolicyPort, NetworkPolicySpec,
+};
+use k8s_openapi::api::rbac::v1::{PolicyRule, Role, RoleBinding, RoleRef, Subject};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{
+    LabelSelector, ObjectMeta, OwnerReference, Time,
+};
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use k8s_openapi::chrono::Utc;
+use kube::{Api, Client, Resource, runtime::controller::Action};
 use log::info;
+use oci_client::{Reference, manifest::OciImageManifest};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::fmt::{Debug, Display};
-use std::{sync::Arc, time::Duration};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use trusted_cluster_operator_lib::reference_values::COMPUTABLE_PCRS;
+use trusted_cluster_operator_lib::{
+    TrustedExecutionClusterImageVerification, TrustedExecutionClusterPcrLabelTrust,
+    TrustedExecutionClusterPcrsComputeJobSettings,
+};
 
 #[derive(Clone)]
 pub struct RvContextData {
     pub client: Client,
     pub owner_reference: OwnerReference,
-    pub pcrs_compute_image: String,
+    pub pcrs_compute_image: ComputeImage,
+    pub pcrs_compute_pod_settings: PodSettings,
+    pub pcrs_compute_job_settings: JobSettings,
+    pub pcr_selection: PcrSelection,
+    pub image_pull_secrets: Vec<String>,
+    pub image_verification: Option<TrustedExecutionClusterImageVerification>,
+    pub pcr_label_trust: TrustedExecutionClusterPcrLabelTrust,
+    pub insecure_registries: Vec<String>,
+    /// Whether compute-pcrs jobs can mount images as image volumes, or must
+    /// pull them themselves. Shared between clones of a context, since it
+    /// is switched off once jobs turn out unable to mount image volumes.
+    pub image_volumes: Arc<AtomicBool>,
+    /// How long deleted images stay trusted unless they set their own
+    pub image_drain: Duration,
+    pub reference_value_updates: Coalescer,
+    pub registry_cache: RegistryCache,
+}
+
+/// compute-pcrs image that jobs run, both as given by pcrsComputeImage and
+/// pinned to a digest. Shared between clones of a context, since changes of
+/// pcrsComputeImage are followed while the operator runs.
+#[derive(Clone, Default)]
+pub struct ComputeImage(Arc<RwLock<ComputeImageState>>);
+
+#[derive(Default)]
+struct ComputeImageState {
+    given: String,
+    pinned: String,
+}
+
+impl ComputeImage {
+    pub fn new(given: String, pinned: String) -> Self {
+        Self(Arc::new(RwLock::new(ComputeImageState { given, pinned })))
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, ComputeImageState> {
+        self.0.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn given(&self) -> String {
+        self.read().given.clone()
+    }
+
+    pub fn pinned(&self) -> String {
+        self.read().pinned.clone()
+    }
+
+    /// Returns whether the pinned image changed
+    pub fn set(&self, given: String, pinned: String) -> bool {
+        let mut state = self.0.write().unwrap_or_else(|e| e.into_inner());
+        state.given = given;
+        std::mem::replace(&mut state.pinned, pinned) != state.pinned
+    }
+}
+
+/// How long a lookup that found no image is cached, since the image may
+/// still be pushed
+const REGISTRY_NOT_FOUND_TTL: Duration = Duration::from_secs(600);
+/// Registry cache size, beyond which the oldest lookups are evicted
+const REGISTRY_CACHE_SIZE: usize = 1024;
+
+/// Manifest and config of an image
+pub struct ImageMetadata {
+    pub manifest: OciImageManifest,
+    pub config: String,
+}
+
+#[derive(Clone)]
+pub enum CachedLookup {
+    Found(Arc<ImageMetadata>),
+    NotFound,
+    /// Manifests of an image index by architecture, or None if the image
+    /// is not an image index
+    Platforms(Option<BTreeMap<String, Reference>>),
+}
+
+/// Registry lookups of images that are pinned by digest, which cannot
+/// change, by their pinned reference, the credentials used and the
+/// architecture looked up. Shared between clones of a context,
+/// so that all reconcilers look up an image only once.
+#[derive(Clone, Default)]
+pub struct RegistryCache(Arc<RwLock<RegistryCacheState>>);
+
+#[derive(Default)]
+struct RegistryCacheState {
+    lookups: HashMap<String, (CachedLookup, Instant)>,
+    /// Cached references in the order they were added, for eviction
+    order: VecDeque<String>,
+}
+
+impl RegistryCache {
+    pub fn get(&self, image: &str) -> Option<CachedLookup> {
+        self.get_at(image, Instant::now())
+    }
+
+    fn get_at(&self, image: &str, now: Instant) -> Option<CachedLookup> {
+        let state = self.0.read().unwrap_or_else(|e| e.into_inner());
+        let (lookup, at) = state.lookups.get(image)?;
+        let expired = matches!(lookup, CachedLookup::NotFound)
+            && now.duration_since(*at) >= REGISTRY_NOT_FOUND_TTL;
+        (!expired).then(|| lookup.clone())
+    }
+
+    pub fn insert(&self, image: String, lookup: CachedLookup) {
+        let mut state = self.0.write().unwrap_or_else(|e| e.into_inner());
+        if state
+            .lookups
+            .insert(image.clone(), (lookup, Instant::now()))
+            .is_none()
+        {
+            state.order.push_back(image);
+        }
+        while state.order.len() > REGISTRY_CACHE_SIZE {
+            if let Some(oldest) = state.order.pop_front() {
+                state.lookups.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// PCRs to compute and attest, and the firmware profiles to compute
+/// firmware-dependent PCRs for
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PcrSelection {
+    pub pcrs: Vec<u64>,
+    pub firmware_profiles: Vec<String>,
+}
+
+impl PcrSelection {
+    /// Override with the selection of a TrustedExecutionCluster or
+    /// ApprovedImage where it has one. The CRDs only admit non-negative PCR
+    /// indices.
+    pub fn with_overrides(
+        &self,
+        pcrs: Option<&Vec<i32>>,
+        firmware_profiles: Option<&Vec<String>>,
+    ) -> Self {
+        let pcrs = pcrs.map(|ids| ids.iter().map(|&id| id as u64).collect());
+        Self {
+            pcrs: pcrs.unwrap_or(self.pcrs.clone()),
+            firmware_profiles: firmware_profiles
+                .cloned()
+                .unwrap_or(self.firmware_profiles.clone()),
+        }
+    }
+
+    /// Selected PCRs that compute-pcrs cannot compute
+    pub fn uncomputable_pcrs(&self) -> Vec<u64> {
+        let pcrs = self.pcrs.iter().copied();
+        pcrs.filter(|id| !COMPUTABLE_PCRS.contains(id)).collect()
+    }
+}
+
+/// Failure handling for a generated job.
+/// Synchronize defaults with JobSettings in api/v1alpha1/crds.go
+#[derive(Clone, Debug, PartialEq)]
+pub struct JobSettings {
+    pub backoff_limit: i32,
+    pub pending_timeout: Duration,
+    pub failed_job_retention: Duration,
+}
+
+impl Default for JobSettings {
+    fn default() -> Self {
+        Self {
+            backoff_limit: 2,
+            pending_timeout: Duration::from_secs(600),
+            failed_job_retention: Duration::from_secs(3600),
+        }
+    }
+}
+
+impl JobSettings {
+    /// Apply the settings of a TrustedExecutionCluster where it has them.
+    /// The CRD only admits non-negative values.
+    pub fn from_crd(settings: Option<&TrustedExecutionClusterPcrsComputeJobSettings>) -> Self {
+        let default = Self::default();
+        let Some(settings) = settings else {
+            return default;
+        };
+        let secs = |s: Option<i64>| s.map(|s| Duration::from_secs(s as u64));
+        Self {
+            backoff_limit: settings.backoff_limit.unwrap_or(default.backoff_limit),
+            pending_timeout: secs(settings.pending_timeout_seconds)
+                .unwrap_or(default.pending_timeout),
+            failed_job_retention: secs(settings.failed_job_retention_seconds)
+                .unwrap_or(default.failed_job_retention),
+        }
+    }
+}
+
+/// Scheduling and resource settings for a generated pod.
+/// Synchronize with PodSettings in api/v1alpha1/crds.go
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PodSettings {
+    pub resources: Option<ResourceRequirements>,
+    pub node_selector: Option<BTreeMap<String, String>>,
+    pub tolerations: Option<Vec<Toleration>>,
+    pub affinity: Option<Affinity>,
+    pub priority_class_name: Option<String>,
+    pub image_pull_secrets: Option<Vec<LocalObjectReference>>,
+    pub run_as_user: Option<i64>,
+}
+
+impl PodSettings {
+    /// Convert from the settings translated from the CRD, which does not
+    /// use the k8s-openapi types
+    pub fn from_crd<T: Serialize>(settings: Option<&T>) -> anyhow::Result<Self> {
+        let Some(settings) = settings else {
+            return Ok(Self::default());
+        };
+        let mut value = serde_json::to_value(settings)?;
+        // Quantities are int-or-string in the CRD, but always strings in k8s-openapi
+        if let Some(resources) = value.get_mut("resources") {
+            for key in ["limits", "requests"] {
+                let Some(map) = resources.get_mut(key).and_then(|m| m.as_object_mut()) else {
+                    continue;
+                };
+                for quantity in map.values_mut().filter(|q| q.is_number()) {
+                    *quantity = serde_json::Value::String(quantity.to_string());
+                }
+            }
+        }
+        serde_json::from_value(value).map_err(Into::into)
+    }
+
+    /// Apply to a pod spec. Resources are applied to every container.
+    pub fn apply(&self, pod_spec: &mut PodSpec) {
+        for container in pod_spec.containers.iter_mut() {
+            container.resources = self.resources.clone();
+        }
+        pod_spec.node_selector = self.node_selector.clone();
+        pod_spec.tolerations = self.tolerations.clone();
+        pod_spec.affinity = self.affinity.clone();
+        pod_spec.priority_class_name = self.priority_class_name.clone();
+        pod_spec.image_pull_secrets = self.image_pull_secrets.clone();
+        let pod_ctx = pod_spec.security_context.get_or_insert_default();
+        pod_ctx.run_as_user = self.run_as_user;
+    }
+}
+
+const TMP_VOLUME_NAME: &str = "tmp";
+
+/// Make a pod spec conform to the "restricted" Pod Security Standard.
+/// The root filesystem becomes read-only, so every container is given
+/// a writable /tmp. Other writable paths must be explicit volumes. User and
+/// group IDs are left to the platform or the pod settings, since e.g.
+/// OpenShift only admits those from the range it assigned to the namespace.
+pub fn harden_pod_spec(pod_spec: &mut PodSpec) {
+    let pod_ctx = pod_spec.security_context.get_or_insert_default();
+    pod_ctx.run_as_non_root = Some(true);
+    pod_ctx.seccomp_profile = Some(SeccompProfile {
+        type_: "RuntimeDefault".to_string(),
+        ..Default::default()
+    });
+    for container in pod_spec.containers.iter_mut() {
+        container.security_context = Some(SecurityContext {
+            allow_privilege_escalation: Some(false),
+            read_only_root_filesystem: Some(true),
+            capabilities: Some(Capabilities {
+                drop: Some(vec!["ALL".to_string()]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let tmp_mount = VolumeMount {
+            name: TMP_VOLUME_NAME.to_string(),
+            mount_path: "/tmp".to_string(),
+            ..Default::default()
+        };
+        container
+            .volume_mounts
+            .get_or_insert_default()
+            .push(tmp_mount);
+    }
+    pod_spec.volumes.get_or_insert_default().push(Volume {
+        name: TMP_VOLUME_NAME.to_string(),
+        empty_dir: Some(EmptyDirVolumeSource::default()),
+        ..Default::default()
+    });
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -33,6 +338,44 @@ pub fn controller_error_policy<R, E: Display, C>(_obj: Arc<R>, error: &E, _ctx:
     Action::requeue(Duration::from_secs(60))
 }
 
+/// Time elapsed since the given time, or zero if it lies ahead or is unknown
+pub fn elapsed_since(time: Option<&Time>) -> Duration {
+    let time = time.map(|t| t.0).unwrap_or(Utc::now());
+    (Utc::now() - time).to_std().unwrap_or_default()
+}
+
+/// Coalesces concurrent requests for the same idempotent work. Requests
+/// that arrive while the work runs are all served by a single follow-up
+/// run, so a burst of requests causes at most two runs.
+#[derive(Clone, Default)]
+pub struct Coalescer(Arc<CoalescerState>);
+
+#[derive(Default)]
+struct CoalescerState {
+    requested: AtomicU64,
+    /// Latest request that a completed run covered
+    completed: Mutex<u64>,
+}
+
+impl Coalescer {
+    pub async fn run<F, Fut>(&self, work: F) -> anyhow::Result<()>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anyhow::Result<()>>,
+    {
+        let request = self.0.requested.fetch_add(1, Ordering::AcqRel) + 1;
+        let mut completed = self.0.completed.lock().await;
+        if *completed >= request {
+            return Ok(());
+        }
+        // Requests up to here were made before the work starts
+        let covered = self.0.requested.load(Ordering::Acquire);
+        work().await?;
+        *completed = covered;
+        Ok(())
+    }
+}
+
 pub async fn controller_info<T: Debug, E: Debug>(res: Result<T, E>) {
     match res {
         Ok(o) => info!("reconciled {o:?}"),
@@ -55,6 +398,136 @@ macro_rules! create_or_info_if_exists {
     };
 }
 
+pub fn policy_rule(api_group: &str, resource: &str, verbs: &[&str]) -> PolicyRule {
+    PolicyRule {
+        api_groups: Some(vec![api_group.to_string()]),
+        resources: Some(vec![resource.to_string()]),
+        verbs: verbs.iter().map(|v| v.to_string()).collect(),
+        ..Default::default()
+    }
+}
+
+/// Create a ServiceAccount for a component, and a Role with the given
+/// rules bound to it. All three share the same name.
+pub async fn create_service_account_with_role(
+    client: Client,
+    owner_reference: OwnerReference,
+    name: &str,
+    rules: Vec<PolicyRule>,
+) -> anyhow::Result<()> {
+    let namespace = client.default_namespace().to_string();
+    let metadata = ObjectMeta {
+        name: Some(name.to_string()),
+        owner_references: Some(vec![owner_reference]),
+        ..Default::default()
+    };
+
+    let service_account = ServiceAccount {
+        metadata: metadata.clone(),
+        ..Default::default()
+    };
+    create_or_info_if_exists!(client.clone(), ServiceAccount, service_account);
+
+    let role = Role {
+        metadata: metadata.clone(),
+        rules: Some(rules),
+    };
+    create_or_info_if_exists!(client.clone(), Role, role);
+
+    let role_binding = RoleBinding {
+        metadata,
+        role_ref: RoleRef {
+            api_group: Role::group(&()).to_string(),
+            kind: Role::kind(&()).to_string(),
+            name: name.to_string(),
+        },
+        subjects: Some(vec![Subject {
+            kind: ServiceAccount::kind(&()).to_string(),
+            name: name.to_string(),
+            namespace: Some(namespace),
+            ..Default::default()
+        }]),
+    };
+    create_or_info_if_exists!(client, RoleBinding, role_binding);
+    Ok(())
+}
+
+/// Delete a service account and its role and role binding, as created by
+/// `create_service_account_with_role`. Those that do not exist are skipped.
+pub async fn delete_service_account_with_role(client: Client, name: &str) -> anyhow::Result<()> {
+    let params = Default::default();
+    let role_bindings: Api<RoleBinding> = Api::default_namespaced(client.clone());
+    skip_not_found(role_bindings.delete(name, &params).await)?;
+    let roles: Api<Role> = Api::default_namespaced(client.clone());
+    skip_not_found(roles.delete(name, &params).await)?;
+    let service_accounts: Api<ServiceAccount> = Api::default_namespaced(client);
+    skip_not_found(service_accounts.delete(name, &params).await)?;
+    Ok(())
+}
+
+fn skip_not_found<T>(result: Result<T, kube::Error>) -> anyhow::Result<()> {
+    match result {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(e)) if e.code == 404 => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn network_policy_port(port: i32) -> NetworkPolicyPort {
+    NetworkPolicyPort {
+        port: Some(IntOrString::Int(port)),
+        protocol: Some("TCP".to_string()),
+        ..Default::default()
+    }
+}
+
+/// Allow ingress on a pod's port from the given CIDRs, or from anywhere if none are given
+pub fn cidr_ingress_rule(port: i32, cidrs: &[String]) -> NetworkPolicyIngressRule {
+    let peers = cidrs.iter().map(|cidr| NetworkPolicyPeer {
+        ip_block: Some(IPBlock {
+            cidr: cidr.clone(),
+            except: None,
+        }),
+        ..Default::default()
+    });
+    NetworkPolicyIngressRule {
+        from: (!cidrs.is_empty()).then(|| peers.collect()),
+        ports: Some(vec![network_policy_port(port)]),
+    }
+}
+
+/// Generate a NetworkPolicy for the pods matching the labels. Ingress is
+/// always restricted to the given rules, meaning no ingress if there are
+/// none. Egress is only restricted if rules are given.
+pub fn generate_network_policy(
+    name: &str,
+    owner_reference: OwnerReference,
+    pod_labels: BTreeMap<String, String>,
+    ingress: Vec<NetworkPolicyIngressRule>,
+    egress: Option<Vec<NetworkPolicyEgressRule>>,
+) -> NetworkPolicy {
+    let mut policy_types = vec!["Ingress".to_string()];
+    if egress.is_some() {
+        policy_types.push("Egress".to_string());
+    }
+    NetworkPolicy {
+        metadata: ObjectMeta {
+            name: Some(name.to_string()),
+            owner_references: Some(vec![owner_reference]),
+            ..Default::default()
+        },
+        spec: Some(NetworkPolicySpec {
+            pod_selector: Some(LabelSelector {
+                match_labels: Some(pod_labels),
+                ..Default::default()
+            }),
+            ingress: Some(ingress),
+            egress,
+            policy_types: Some(policy_types),
+        }),
+    }
+}
+
 pub fn generate_owner_reference<T: Resource<DynamicType = ()>>(
     object: &T,
 ) -> anyhow::Result<OwnerReference> {
@@ -70,3 +543,251 @@ pub fn generate_owner_reference<T: Resource<DynamicType = ()>>(
         kind,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::{Method, Request, StatusCode};
+    use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+    use trusted_cluster_operator_test_utils::mock_client::*;
+
+    #[test]
+    fn test_uncomputable_pcrs() {
+        let selection = PcrSelection {
+            pcrs: vec![0, 4, 7, 11, 14],
+            firmware_profiles: vec![],
+        };
+        assert_eq!(selection.uncomputable_pcrs(), vec![0, 11]);
+    }
+
+    #[test]
+    fn test_pod_settings_from_crd_quantities() {
+        let crd_settings = serde_json::json!({
+            "resources": {"limits": {"cpu": 1, "memory": "1Gi"}},
+            "priorityClassName": "high",
+        });
+        let settings = PodSettings::from_crd(Some(&crd_settings)).unwrap();
+        let limits = settings.resources.unwrap().limits.unwrap();
+        assert_eq!(limits["cpu"], Quantity("1".to_string()));
+        assert_eq!(limits["memory"], Quantity("1Gi".to_string()));
+        assert_eq!(settings.priority_class_name, Some("high".to_string()));
+    }
+
+    fn dummy_metadata() -> ImageMetadata {
+        ImageMetadata {
+            manifest: Default::default(),
+            config: "{}".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_registry_cache_found() {
+        let cache = RegistryCache::default();
+        assert!(cache.get("image").is_none());
+        let found = CachedLookup::Found(Arc::new(dummy_metadata()));
+        cache.insert("image".to_string(), found);
+        let later = Instant::now() + REGISTRY_NOT_FOUND_TTL * 2;
+        let lookup = cache.get_at("image", later);
+        assert!(matches!(lookup, Some(CachedLookup::Found(m)) if m.config == "{}"));
+    }
+
+    #[test]
+    fn test_registry_cache_not_found_expires() {
+        let cache = RegistryCache::default();
+        cache.insert("image".to_string(), CachedLookup::NotFound);
+        assert!(matches!(cache.get("image"), Some(CachedLookup::NotFound)));
+        let later = Instant::now() + REGISTRY_NOT_FOUND_TTL;
+        assert!(cache.get_at("image", later).is_none());
+    }
+
+    #[test]
+    fn test_registry_cache_evicts_oldest() {
+        let cache = RegistryCache::default();
+        for i in 0..=REGISTRY_CACHE_SIZE {
+            cache.insert(i.to_string(), CachedLookup::NotFound);
+        }
+        assert!(cache.get("0").is_none());
+        assert!(cache.get("1").is_some());
+        assert!(cache.get(&REGISTRY_CACHE_SIZE.to_string()).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_coalescer() {
+        let coalescer = Coalescer::default();
+        let runs = Arc::new(AtomicU64::new(0));
+        let run = || {
+            let runs = runs.clone();
+            coalescer.run(move || async move {
+                tokio::task::yield_now().await;
+                runs.fetch_add(1, Ordering::AcqRel);
+                Ok(())
+            })
+        };
+        let results = futures_util::future::join_all((0..10).map(|_| run())).await;
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(runs.load(Ordering::Acquire), 2);
+    }
+
+    #[test]
+    fn test_pod_settings_from_crd_none() {
+        let settings = PodSettings::from_crd::<serde_json::Value>(None).unwrap();
+        assert_eq!(settings, PodSettings::default());
+    }
+
+    #[test]
+    fn test_harden_pod_spec() {
+        let mut pod_spec = PodSpec {
+            containers: vec![Default::default()],
+            ..Default::default()
+        };
+        harden_pod_spec(&mut pod_spec);
+        let pod_ctx = pod_spec.security_context.unwrap();
+        assert_eq!(pod_ctx.run_as_non_root, Some(true));
+        assert_eq!(pod_ctx.run_as_user, None);
+        assert_eq!(pod_ctx.fs_group, None);
+        let seccomp = pod_ctx.seccomp_profile.unwrap();
+        assert_eq!(seccomp.type_, "RuntimeDefault");
+        let ctx = pod_spec.containers[0].security_context.clone().unwrap();
+        assert_eq!(ctx.allow_privilege_escalation, Some(false));
+        assert_eq!(ctx.read_only_root_filesystem, Some(true));
+        let mounts = pod_spec.containers[0].volume_mounts.clone().unwrap();
+        assert!(mounts.iter().any(|m| m.mount_path == "/tmp"));
+        let volumes = pod_spec.volumes.unwrap();
+        assert!(volumes.iter().any(|v| v.empty_dir.is_some()));
+    }
+
+    #[test]
+    fn test_pod_settings_apply() {
+        let settings = PodSettings::from_crd(Some(&serde_json::json!({
+            "resources": {"requests": {"memory": "2Gi"}},
+            "nodeSelector": {"dedicated": "kbs"},
+            "runAsUser": 1000,
+        })))
+        .unwrap();
+        let mut pod_spec = PodSpec {
+            containers: vec![Default::default(), Default::default()],
+            ..Default::default()
+        };
+        settings.apply(&mut pod_spec);
+        harden_pod_spec(&mut pod_spec);
+        assert!(pod_spec.containers.iter().all(|c| c.resources.is_some()));
+        assert_eq!(pod_spec.node_selector, settings.node_selector);
+        let pod_ctx = pod_spec.security_context.unwrap();
+        assert_eq!(pod_ctx.run_as_user, Some(1000));
+        assert_eq!(pod_ctx.run_as_non_root, Some(true));
+    }
+
+    #[test]
+    fn test_cidr_ingress_rule() {
+        let rule = cidr_ingress_rule(8080, &["10.0.0.0/8".to_string()]);
+        let from = rule.from.unwrap();
+        assert_eq!(from[0].ip_block.as_ref().unwrap().cidr, "10.0.0.0/8");
+        assert_eq!(rule.ports.unwrap()[0].port, Some(IntOrString::Int(8080)));
+    }
+
+    #[test]
+    fn test_cidr_ingress_rule_any_source() {
+        let rule = cidr_ingress_rule(8080, &[]);
+        assert!(rule.from.is_none());
+    }
+
+    #[test]
+    fn test_generate_network_policy_deny_ingress() {
+        let policy =
+            generate_network_policy("np", Default::default(), BTreeMap::new(), vec![], None);
+        let spec = policy.spec.unwrap();
+        assert_eq!(spec.ingress, Some(vec![]));
+        assert_eq!(spec.policy_types, Some(vec!["Ingress".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn test_create_service_account_with_role() {
+        let clos = async |req: Request<_>, ctr| match (ctr, req.method()) {
+            (0, &Method::POST) => {
+                assert!(req.uri().path().contains("serviceaccounts"));
+                Ok(serde_json::to_string(&ServiceAccount::default()).unwrap())
+            }
+            (1, &Method::POST) => {
+                assert!(req.uri().path().ends_with("/roles"));
+                Ok(serde_json::to_string(&Role::default()).unwrap())
+            }
+            (2, &Method::POST) => {
+                assert!(req.uri().path().contains("rolebindings"));
+                Ok(serde_json::to_string(&RoleBinding::default()).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(3, clos, |client| {
+            let rules = vec![policy_rule("", "configmaps", &["get"])];
+            let result = create_service_account_with_role(client, Default::default(), "sa", rules);
+            assert!(result.await.is_ok());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_create_service_account_with_role_exists() {
+        let clos = async |req: Request<_>, _| match req.method() {
+            &Method::POST => Err(StatusCode::CONFLICT),
+            _ => panic!("unexpected API interaction: {req:?}"),
+        };
+        count_check!(3, clos, |client| {
+            let result = create_service_account_with_role(client, Default::default(), "sa", vec![]);
+            assert!(result.await.is_ok());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_create_service_account_with_role_error() {
+        let clos =
+            |client| create_service_account_with_role(client, Default::default(), "", vec![]);
+        test_create_error(clos).await;
+    }
+
+    #[tokio::test]
+    async fn test_delete_service_account_with_role() {
+        let clos = async |req: Request<_>, ctr| match (ctr, req.method()) {
+            (0, &Method::DELETE) => {
+                assert!(req.uri().path().ends_with("/rolebindings/sa"));
+                Ok(serde_json::to_string(&RoleBinding::default()).unwrap())
+            }
+            (1, &Method::DELETE) => {
+                assert!(req.uri().path().ends_with("/roles/sa"));
+                Ok(serde_json::to_string(&Role::default()).unwrap())
+            }
+            (2, &Method::DELETE) => {
+                assert!(req.uri().path().ends_with("/serviceaccounts/sa"));
+                Ok(serde_json::to_string(&ServiceAccount::default()).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(3, clos, |client| {
+            assert!(delete_service_account_with_role(client, "sa").await.is_ok());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_delete_service_account_with_role_not_found() {
+        let clos = async |req: Request<_>, _| match req.method() {
+            &Method::DELETE => Err(StatusCode::NOT_FOUND),
+            _ => panic!("unexpected API interaction: {req:?}"),
+        };
+        count_check!(3, clos, |client| {
+            assert!(delete_service_account_with_role(client, "sa").await.is_ok());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_delete_service_account_with_role_error() {
+        let clos = async |req: Request<_>, _| match req.method() {
+            &Method::DELETE => Err(StatusCode::INTERNAL_SERVER_ERROR),
+            _ => panic!("unexpected API interaction: {req:?}"),
+        };
+        count_check!(1, clos, |client| {
+            assert!(
+                delete_service_account_with_role(client, "sa")
+                    .await
+                    .is_err()
+            );
+        });
+    }
+}
@@ -4,6 +4,7 @@
 // SPDX-License-Identifier: MIT
 
 use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 use std::time::Duration;
 
 use anyhow::Result;
@@ -16,12 +17,18 @@ use kube::{Api, Client};
 use log::{error, info, warn};
 
 use operator::generate_owner_reference;
-use trusted_cluster_operator_lib::{TrustedExecutionCluster, TrustedExecutionClusterStatus};
+use trusted_cluster_operator_lib::reference_values::DEFAULT_PCR_SELECTION;
+use trusted_cluster_operator_lib::{
+    TrustedExecutionCluster, TrustedExecutionClusterPcrLabelTrust, TrustedExecutionClusterStatus,
+};
 use trusted_cluster_operator_lib::{conditions::*, update_status};
 
 mod conditions;
+mod image_stream;
 mod reference_values;
 mod register_server;
+mod registry;
+mod signature;
 #[cfg(test)]
 mod test_utils;
 mod trustee;
@@ -37,15 +44,29 @@ fn is_installed(status: Option<TrustedExecutionClusterStatus>) -> bool {
         .unwrap_or(false)
 }
 
+/// publicTrusteeAddr takes precedence over the address assigned to an
+/// exposed Trustee endpoint
+async fn effective_trustee_addr(
+    client: Client,
+    cluster: &TrustedExecutionCluster,
+) -> Result<Option<String>> {
+    if let Some(addr) = &cluster.spec.public_trustee_addr {
+        return Ok(Some(addr.clone()));
+    }
+    match &cluster.spec.trustee_exposure {
+        Some(exposure) => {
+            let port = cluster.spec.trustee_kbs_port;
+            trustee::discover_kbs_addr(client, exposure, port).await
+        }
+        None => Ok(None),
+    }
+}
+
 async fn reconcile(
     cluster: Arc<TrustedExecutionCluster>,
     client: Arc<Client>,
 ) -> Result<Action, ControllerError> {
     let generation = cluster.metadata.generation;
-    let known_address = cluster.spec.public_trustee_addr.is_some();
-    let address_condition = known_trustee_address_condition(known_address, generation);
-    let mut conditions = Some(vec![address_condition]);
-
     let kube_client = Arc::unwrap_or_clone(client);
     let err = "trusted execution cluster had no name";
     let name = &cluster.metadata.name.clone().expect(err);
@@ -53,14 +74,49 @@ async fn reconcile(
 
     if cluster.metadata.deletion_timestamp.is_some() {
         info!("Registered deletion of TrustedExecutionCluster {name}");
-        let condition = installed_condition(NOT_INSTALLED_REASON_UNINSTALLING, generation);
-        conditions.as_mut().unwrap().push(condition);
-        update_status!(clusters, name, TrustedExecutionClusterStatus { conditions })?;
+        // Keep the last known address rather than discovering it again
+        let status_addr = cluster.status.as_ref().and_then(|s| s.trustee_addr.clone());
+        let trustee_addr = status_addr.or(cluster.spec.public_trustee_addr.clone());
+        let exposed = cluster.spec.trustee_exposure.is_some();
+        let known_address = trustee_addr.is_some();
+        let conditions = vec![
+            known_trustee_address_condition(known_address, exposed, generation),
+            installed_condition(NOT_INSTALLED_REASON_UNINSTALLING, generation),
+        ];
+        let status = TrustedExecutionClusterStatus {
+            conditions: Some(conditions),
+            trustee_addr,
+            reference_values: None,
+        };
+        update_status!(clusters, name, status)?;
         return Ok(Action::await_change());
     }
 
+    let trustee_addr = effective_trustee_addr(kube_client.clone(), &cluster).await?;
+    let exposed = cluster.spec.trustee_exposure.is_some();
+    let known_address = trustee_addr.is_some();
+    let address_condition = known_trustee_address_condition(known_address, exposed, generation);
+    let mut conditions = Some(vec![address_condition]);
+    // Poll the exposed endpoint until it is assigned an address
+    let done = match !known_address && exposed {
+        true => Action::requeue(Duration::from_secs(30)),
+        false => Action::await_change(),
+    };
+
     if is_installed(cluster.status.clone()) {
-        return Ok(Action::await_change());
+        let status_addr = cluster.status.as_ref().and_then(|s| s.trustee_addr.clone());
+        if status_addr != trustee_addr {
+            info!("Trustee address of TrustedExecutionCluster {name} is now {trustee_addr:?}");
+            let condition = installed_condition(INSTALLED_REASON, generation);
+            conditions.as_mut().unwrap().push(condition);
+            let status = TrustedExecutionClusterStatus {
+                conditions,
+                trustee_addr,
+                reference_values: None,
+            };
+            update_status!(clusters, name, status)?;
+        }
+        return Ok(done);
     }
 
     let list = clusters.list(&Default::default()).await;
@@ -73,7 +129,12 @@ async fn reconcile(
         );
         let condition = installed_condition(NOT_INSTALLED_REASON_NON_UNIQUE, generation);
         conditions.as_mut().unwrap().push(condition);
-        update_status!(clusters, name, TrustedExecutionClusterStatus { conditions })?;
+        let status = TrustedExecutionClusterStatus {
+            conditions,
+            trustee_addr,
+            reference_values: None,
+        };
+        update_status!(clusters, name, status)?;
         return Ok(Action::requeue(Duration::from_secs(60)));
     }
 
@@ -83,6 +144,8 @@ async fn reconcile(
     installing.as_mut().unwrap().push(condition);
     let status = TrustedExecutionClusterStatus {
         conditions: installing,
+        trustee_addr: trustee_addr.clone(),
+        reference_values: None,
     };
     update_status!(clusters, name, status)?;
 
@@ -90,8 +153,13 @@ async fn reconcile(
     install_register_server(kube_client, &cluster).await?;
     let condition = installed_condition(INSTALLED_REASON, generation);
     conditions.as_mut().unwrap().push(condition);
-    update_status!(clusters, name, TrustedExecutionClusterStatus { conditions })?;
-    Ok(Action::await_change())
+    let status = TrustedExecutionClusterStatus {
+        conditions,
+        trustee_addr,
+        reference_values: None,
+    };
+    update_status!(clusters, name, status)?;
+    Ok(done)
 }
 
 async fn install_trustee_configuration(
@@ -105,17 +173,72 @@ async fn install_trustee_configuration(
         Err(e) => error!("Failed to create the KBS configuration configmap: {e}"),
     }
 
+    let pcrs_compute_settings = cluster.spec.pcrs_compute_pod_settings.as_ref();
+    let job_settings = cluster.spec.pcrs_compute_job_settings.as_ref();
+    let default_selection = PcrSelection {
+        pcrs: DEFAULT_PCR_SELECTION.to_vec(),
+        firmware_profiles: vec![],
+    };
+    let pcr_selection = default_selection.with_overrides(
+        cluster.spec.pcr_selection.as_ref(),
+        cluster.spec.firmware_profiles.as_ref(),
+    );
+    let pull_secrets = cluster.spec.image_pull_secrets.iter().flatten();
+    let image_volumes = match reference_values::image_volumes_supported(client.clone()).await {
+        Ok(true) => true,
+        Ok(false) => {
+            info!("Image volumes are not supported, compute-pcrs jobs will pull images");
+            false
+        }
+        Err(e) => {
+            warn!("Failed to probe for image volume support, assuming it: {e}");
+            true
+        }
+    };
     let rv_ctx = RvContextData {
         client: client.clone(),
         owner_reference: owner_reference.clone(),
-        pcrs_compute_image: cluster.spec.pcrs_compute_image.clone(),
+        pcrs_compute_image: ComputeImage::default(),
+        pcrs_compute_pod_settings: PodSettings::from_crd(pcrs_compute_settings)?,
+        pcrs_compute_job_settings: JobSettings::from_crd(job_settings),
+        pcr_selection,
+        image_pull_secrets: pull_secrets.filter_map(|s| s.name.clone()).collect(),
+        image_verification: cluster.spec.image_verification.clone(),
+        pcr_label_trust: cluster
+            .spec
+            .pcr_label_trust
+            .clone()
+            .unwrap_or(TrustedExecutionClusterPcrLabelTrust::Trust),
+        insecure_registries: cluster.spec.insecure_registries.clone().unwrap_or_default(),
+        image_volumes: Arc::new(AtomicBool::new(image_volumes)),
+        image_drain: Duration::from_secs(cluster.spec.image_drain_seconds.unwrap_or(0) as u64),
+        reference_value_updates: Default::default(),
+        registry_cache: Default::default(),
     };
+    let compute_image = cluster.spec.pcrs_compute_image.clone();
+    let pinned = reference_values::pin_compute_image(&rv_ctx, &compute_image).await;
+    rv_ctx.pcrs_compute_image.set(compute_image, pinned);
+    let owner_ref = owner_reference.clone();
+    match reference_values::create_compute_pcrs_network_policy(client.clone(), owner_ref).await {
+        Ok(_) => info!("Created network policy for compute-pcrs jobs"),
+        Err(e) => error!("Failed to create the compute-pcrs network policy: {e}"),
+    }
+    match reference_values::delete_compute_pcrs_rbac(client.clone()).await {
+        Ok(_) => info!("Deleted former service account of compute-pcrs jobs, if any"),
+        Err(e) => error!("Failed to delete the former compute-pcrs service account: {e}"),
+    }
     reference_values::launch_rv_image_controller(rv_ctx.clone()).await;
     reference_values::launch_rv_job_controller(rv_ctx.clone()).await;
+    image_stream::launch_image_stream_controller(rv_ctx.clone()).await;
+    reference_values::launch_orphan_sweep(rv_ctx.clone()).await;
     match reference_values::create_pcrs_config_map(client.clone(), owner_reference.clone()).await {
         Ok(_) => info!("Created bare configmap for PCRs"),
         Err(e) => error!("Failed to create the PCRs configmap: {e}"),
     }
+    match reference_values::migrate_unsharded_pcrs(rv_ctx.clone()).await {
+        Ok(_) => info!("Checked for image PCRs from before sharding"),
+        Err(e) => error!("Failed to migrate image PCRs from before sharding: {e}"),
+    }
 
     match trustee::generate_attestation_policy(client.clone(), owner_reference.clone()).await {
         Ok(_) => info!("Generate configmap for the attestation policy",),
@@ -128,8 +251,27 @@ async fn install_trustee_configuration(
         Err(e) => error!("Failed to create the KBS service: {e}"),
     }
 
+    if let Some(exposure) = &cluster.spec.trustee_exposure {
+        let owner_ref = owner_reference.clone();
+        match trustee::generate_kbs_exposure(client.clone(), owner_ref, exposure, kbs_port).await {
+            Ok(_) => info!("Generate the external KBS endpoint"),
+            Err(e) => error!("Failed to create the external KBS endpoint: {e}"),
+        }
+    }
+
+    let node_cidrs = cluster.spec.node_network_cidrs.clone().unwrap_or_default();
+    let owner_ref = owner_reference.clone();
+    let exposure = cluster.spec.trustee_exposure.as_ref();
+    match trustee::generate_kbs_network_policy(client.clone(), owner_ref, &node_cidrs, exposure)
+        .await
+    {
+        Ok(_) => info!("Generate the KBS network policy"),
+        Err(e) => error!("Failed to create the KBS network policy: {e}"),
+    }
+
     let trustee_image = &cluster.spec.trustee_image;
-    match trustee::generate_kbs_deployment(client, owner_reference, trustee_image).await {
+    let settings = PodSettings::from_crd(cluster.spec.trustee_pod_settings.as_ref())?;
+    match trustee::generate_kbs_deployment(client, owner_reference, trustee_image, settings).await {
         Ok(_) => info!("Generate the KBS deployment"),
         Err(e) => error!("Failed to create the KBS deployment: {e}"),
     }
@@ -140,10 +282,32 @@ async fn install_trustee_configuration(
 async fn install_register_server(client: Client, cluster: &TrustedExecutionCluster) -> Result<()> {
     let owner_reference = generate_owner_reference(cluster)?;
 
+    match register_server::create_register_server_rbac(client.clone(), owner_reference.clone())
+        .await
+    {
+        Ok(_) => info!("Register server service account created successfully"),
+        Err(e) => error!("Failed to create register server service account: {e}"),
+    }
+
+    let node_cidrs = cluster.spec.node_network_cidrs.clone().unwrap_or_default();
+    match register_server::create_register_server_network_policy(
+        client.clone(),
+        owner_reference.clone(),
+        &node_cidrs,
+    )
+    .await
+    {
+        Ok(_) => info!("Register server network policy created successfully"),
+        Err(e) => error!("Failed to create register server network policy: {e}"),
+    }
+    register_server::launch_api_server_egress_refresh(client.clone()).await;
+
+    let settings = PodSettings::from_crd(cluster.spec.register_server_pod_settings.as_ref())?;
     match register_server::create_register_server_deployment(
         client.clone(),
         owner_reference.clone(),
         &cluster.spec.register_server_image,
+        settings,
     )
     .await
     {
@@ -189,6 +353,9 @@ mod tests {
     use kube::client::Body;
 
     use super::*;
+    use trusted_cluster_operator_lib::{
+        TrustedExecutionClusterTrusteeExposure, TrustedExecutionClusterTrusteeExposureType,
+    };
     use trusted_cluster_operator_test_utils::mock_client::*;
 
     #[tokio::test]
@@ -232,6 +399,44 @@ mod tests {
         });
     }
 
+    #[tokio::test]
+    async fn test_reconcile_installed_discovers_addr() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => {
+                let route = serde_json::json!({
+                    "apiVersion": "route.openshift.io/v1",
+                    "kind": "Route",
+                    "metadata": {"name": "kbs-external"},
+                    "status": {"ingress": [{"host": "kbs.apps.example.com"}]},
+                });
+                Ok(route.to_string())
+            }
+            (1, &Method::PATCH) => {
+                assert_body_contains(req, "kbs.apps.example.com").await;
+                Ok(serde_json::to_string(&dummy_cluster()).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(2, clos, |client| {
+            let mut cluster = dummy_cluster();
+            cluster.spec.public_trustee_addr = None;
+            cluster.spec.trustee_exposure = Some(TrustedExecutionClusterTrusteeExposure {
+                r#type: TrustedExecutionClusterTrusteeExposureType::Route,
+                host: None,
+                ingress_class_name: None,
+                ingress_controller_namespace: None,
+            });
+            let condition = installed_condition(INSTALLED_REASON, None);
+            cluster.status = Some(TrustedExecutionClusterStatus {
+                conditions: Some(vec![condition]),
+                trustee_addr: None,
+                reference_values: None,
+            });
+            let result = reconcile(Arc::new(cluster), Arc::new(client)).await;
+            assert_eq!(result.unwrap(), Action::await_change());
+        });
+    }
+
     #[tokio::test]
     async fn test_reconcile_error() {
         let clos = async |req: Request<_>, _| match req {
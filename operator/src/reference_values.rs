@@ -3,78 +3,160 @@
 //
 // SPDX-License-Identifier: MIT
 
-use anyhow::{Context, Result, anyhow};
+use anyhow::{Context, Result, anyhow, bail};
 use compute_pcrs_lib::Pcr;
-use futures_util::StreamExt;
+use futures_util::{StreamExt, stream};
 use k8s_openapi::{
     api::{
         batch::v1::{Job, JobSpec},
         core::v1::{
-            ConfigMap, ConfigMapVolumeSource, Container, ImageVolumeSource, KeyToPath, PodSpec,
-            PodTemplateSpec, Volume, VolumeMount,
+            ConfigMap, ConfigMapVolumeSource, Container, Event as CoreEvent, ImageVolumeSource,
+            KeyToPath, LocalObjectReference, Node, Pod, PodSpec, PodTemplateSpec,
+            SecretVolumeSource, Volume, VolumeMount,
         },
+        networking::v1::NetworkPolicy,
     },
-    apimachinery::pkg::apis::meta::v1::OwnerReference,
-    chrono::Utc,
+    apimachinery::pkg::apis::meta::v1::{Condition, OwnerReference, Time},
+    chrono::{DateTime, TimeDelta, Utc},
 };
-use kube::api::{DeleteParams, ObjectMeta};
+use kube::api::{DeleteParams, ListParams, LogParams, ObjectMeta, PostParams};
 use kube::runtime::{
+    WatchStreamExt,
     controller::{Action, Controller},
     finalizer,
     finalizer::Event,
+    reflector::ObjectRef,
     watcher,
 };
-use kube::{Api, Client, Resource};
+use kube::{Api, Client, Resource, ResourceExt};
 use log::{info, warn};
 use oci_client::secrets::RegistryAuth;
 use oci_spec::image::ImageConfiguration;
 use openssl::hash::{MessageDigest, hash};
-use serde::Deserialize;
-use std::{collections::BTreeMap, path::PathBuf, sync::Arc, time::Duration};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+    sync::{Arc, atomic::Ordering},
+    time::Duration,
+};
+use tokio::sync::watch;
 
-use crate::trustee::{self, get_image_pcrs};
+use crate::trustee;
+use crate::{registry, signature};
 use operator::{
-    ControllerError, RvContextData, controller_error_policy, controller_info,
-    create_or_info_if_exists,
+    ControllerError, PcrSelection, PodSettings, RvContextData, controller_error_policy,
+    controller_info, create_or_info_if_exists, delete_service_account_with_role, elapsed_since,
+    generate_network_policy, harden_pod_spec,
 };
 use trusted_cluster_operator_lib::{conditions::*, reference_values::*, *};
 
 const JOB_LABEL_KEY: &str = "kind";
+const JOB_NAME_LABEL_KEY: &str = "batch.kubernetes.io/job-name";
+/// Node label that schedules a job onto the architecture it computes for
+const ARCH_LABEL_KEY: &str = "kubernetes.io/arch";
+/// ApprovedImage that a job computes PCRs for
+const IMAGE_ANNOTATION_KEY: &str = "trusted-execution-clusters.io/approved-image";
+/// Node annotation in which the Machine Config Operator records the OS
+/// image that a node currently boots
+const NODE_IMAGE_ANNOTATION_KEY: &str = "machineconfiguration.openshift.io/currentImage";
+/// What a job was started to compute, to validate its result against
+const REQUEST_ANNOTATION_KEY: &str = "trusted-execution-clusters.io/compute-request";
+const FIRMWARE_PROFILES_DIR: &str = "/firmware-profiles";
+/// Where pull secrets are mounted when compute-pcrs pulls the image itself
+const PULL_SECRETS_DIR: &str = "/pull-secrets";
+/// Volume that a job mounts the image it computes for as
+const IMAGE_VOLUME_NAME: &str = "image";
+/// Image of the volume that probes for image volume support. It is never
+/// pulled, since the probe is a dry run.
+const PROBE_IMAGE: &str = "quay.io/fedora/fedora:latest";
 const PCR_COMMAND_NAME: &str = "compute-pcrs";
 const PCR_LABEL: &str = "org.coreos.pcrs";
 /// Finalizer name to discard reference values when an image is no longer approved
 const APPROVED_IMAGE_FINALIZER: &str = "finalizer.approved-image.trusted-execution-clusters.io";
+/// How often to look for image PCRs that no ApprovedImage approves
+const ORPHAN_SWEEP_INTERVAL: Duration = Duration::from_secs(600);
+/// How often to update which Machines still boot a draining image
+const DRAIN_RECHECK_INTERVAL: Duration = Duration::from_secs(60);
 
-/// Synchronize with compute_pcrs_cli::Output
-#[derive(Deserialize)]
-struct ComputePcrsOutput {
-    pcrs: Vec<Pcr>,
-}
-
+/// Create all image PCRs shards, so that reading them can tell a missing
+/// shard from one without images
 pub async fn create_pcrs_config_map(client: Client, owner_reference: OwnerReference) -> Result<()> {
     let empty_data = BTreeMap::from([(
         PCR_CONFIG_FILE.to_string(),
         serde_json::to_string(&ImagePcrs::default())?,
     )]);
-    let config_map = ConfigMap {
-        metadata: ObjectMeta {
-            name: Some(PCR_CONFIG_MAP.to_string()),
-            owner_references: Some(vec![owner_reference]),
+    for shard in 0..PCR_SHARDS {
+        let config_map = ConfigMap {
+            metadata: ObjectMeta {
+                name: Some(pcrs_shard_name(shard)),
+                labels: Some(BTreeMap::from([(
+                    PCR_SHARD_LABEL.to_string(),
+                    shard.to_string(),
+                )])),
+                owner_references: Some(vec![owner_reference.clone()]),
+                ..Default::default()
+            },
+            data: Some(empty_data.clone()),
             ..Default::default()
-        },
-        data: Some(empty_data),
-        ..Default::default()
-    };
-    create_or_info_if_exists!(client, ConfigMap, config_map);
+        };
+        create_or_info_if_exists!(client.clone(), ConfigMap, config_map);
+    }
     Ok(())
 }
 
-async fn fetch_pcr_label(image_ref: &oci_client::Reference) -> Result<Option<Vec<Pcr>>> {
-    let client = oci_client::Client::new(Default::default());
-    let (_, _, raw_config) = client
-        .pull_manifest_and_config(image_ref, &RegistryAuth::Anonymous)
-        .await?;
-    let config: ImageConfiguration = serde_json::from_str(&raw_config)?;
+/// Move the image PCRs from the map that stored them before sharding into
+/// the shards. The map is only deleted once the reference values were
+/// published from the shards, so that no image is untrusted in between.
+pub async fn migrate_unsharded_pcrs(ctx: RvContextData) -> Result<()> {
+    let store = ImagePcrsStore::new(ctx.client.clone());
+    if !store.import_unsharded().await? {
+        return Ok(());
+    }
+    info!("Moved image PCRs from {PCR_CONFIG_MAP} into its shards");
+    trustee::update_reference_values(ctx).await?;
+    store.delete_unsharded().await
+}
+
+/// What a compute-pcrs job computes, as recorded on the job
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ComputeRequest {
+    reference: String,
+    pcrs: Vec<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    firmware_profiles: Vec<String>,
+    /// PCR values from the image's label that the result must match
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    expected_pcrs: BTreeMap<u64, String>,
+    /// Architecture of a multi-architecture image to compute for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    architecture: Option<String>,
+    /// All architectures of a multi-architecture image, one job each
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    architectures: Vec<String>,
+}
+
+/// Architecture of a multi-architecture image that a job computes PCRs for
+struct Platform<'a> {
+    architecture: &'a str,
+    /// Manifest of the architecture, pinned by digest
+    image: &'a str,
+    /// All architectures of the image
+    architectures: &'a [String],
+}
+
+async fn fetch_pcr_label(
+    ctx: &RvContextData,
+    client: &oci_client::Client,
+    image_ref: &oci_client::Reference,
+    auth: &RegistryAuth,
+    architecture: Option<&str>,
+) -> Result<Option<Vec<Pcr>>> {
+    let cache = &ctx.registry_cache;
+    let pull = registry::pull_manifest_and_config(cache, client, image_ref, auth, architecture);
+    let metadata = pull.await?;
+    let config: ImageConfiguration = serde_json::from_str(&metadata.config)?;
     config
         .labels_of_config()
         .and_then(|m| m.get(PCR_LABEL))
@@ -83,83 +165,612 @@ async fn fetch_pcr_label(image_ref: &oci_client::Reference) -> Result<Option<Vec
         .map_err(Into::into)
 }
 
+/// PCRs of an image's label that can be used for the selected PCRs, unless
+/// labels are not read. `architecture` is given for the manifest of one
+/// architecture of a multi-architecture image.
+async fn label_pcrs(
+    ctx: &RvContextData,
+    client: &oci_client::Client,
+    image_ref: &oci_client::Reference,
+    auth: &RegistryAuth,
+    architecture: Option<&str>,
+    selection: &PcrSelection,
+) -> Result<Option<Vec<Pcr>>> {
+    let label = match ctx.pcr_label_trust {
+        TrustedExecutionClusterPcrLabelTrust::Recompute => None,
+        _ => fetch_pcr_label(ctx, client, image_ref, auth, architecture).await?,
+    };
+    // Labels are computed for a single firmware, so they cannot be used
+    // for firmware-dependent PCRs of other profiles
+    let firmware_dependent = selection
+        .pcrs
+        .iter()
+        .any(|id| FIRMWARE_PROFILE_PCRS.contains(id));
+    Ok(
+        match !selection.firmware_profiles.is_empty() && firmware_dependent {
+            true => None,
+            false => label.and_then(|pcrs| select_pcrs(pcrs, &selection.pcrs)),
+        },
+    )
+}
+
+/// Only keep the selected PCRs. None if any selected PCR is missing.
+fn select_pcrs(pcrs: Vec<Pcr>, selection: &[u64]) -> Option<Vec<Pcr>> {
+    let selected: Vec<_> = pcrs
+        .into_iter()
+        .filter(|p| selection.contains(&p.id))
+        .collect();
+    let complete = selection
+        .iter()
+        .all(|id| selected.iter().any(|p| p.id == *id));
+    complete.then_some(selected)
+}
+
+/// Whether PCRs were stored for the given PCRs and firmware profiles, and
+/// for all architectures
+fn matches_selection(image_pcr: &ImagePcr, selection: &PcrSelection) -> bool {
+    let profiles: BTreeSet<_> = selection.firmware_profiles.iter().collect();
+    let profiles_match =
+        |stored: &BTreeMap<String, Vec<Pcr>>| profiles == stored.keys().collect::<BTreeSet<_>>();
+    let mut platforms = image_pcr.platforms.values();
+    let stored_profiles_match = match image_pcr.platforms.is_empty() {
+        true => profiles_match(&image_pcr.firmware_profile_pcrs),
+        false => platforms.all(|p| {
+            p.as_ref()
+                .is_some_and(|p| profiles_match(&p.firmware_profile_pcrs))
+        }),
+    };
+    let pcrs = &selection.pcrs;
+    let sets_match = image_pcr.pcr_sets().iter().all(|set| {
+        set.len() == pcrs.len() && pcrs.iter().all(|id| set.iter().any(|p| p.id == *id))
+    });
+    stored_profiles_match && sets_match
+}
+
+async fn fetch_firmware_profiles(client: Client, names: &[String]) -> Result<Vec<ConfigMap>> {
+    let config_maps: Api<ConfigMap> = Api::default_namespaced(client);
+    let mut profiles = vec![];
+    for name in names.iter() {
+        let profile = config_maps.get(name).await?;
+        let labels = profile.metadata.labels.as_ref();
+        if !labels.is_some_and(|l| l.contains_key(FIRMWARE_PROFILE_LABEL)) {
+            return Err(anyhow!(
+                "ConfigMap {name} is not a firmware profile. \
+                 Firmware profiles must be labeled {FIRMWARE_PROFILE_LABEL}."
+            ));
+        }
+        profiles.push(profile);
+    }
+    Ok(profiles)
+}
+
+/// Mount a firmware profile as a directory with efivars and mokvars
+/// subdirectories
+fn firmware_profile_volume(index: usize, profile: &ConfigMap) -> (Volume, VolumeMount) {
+    let name = profile.metadata.name.clone().unwrap_or_default();
+    let volume_name = format!("firmware-profile-{index}");
+    let dirs = [
+        ("efivars", FIRMWARE_PROFILE_EFIVARS_PREFIX),
+        ("mokvars", FIRMWARE_PROFILE_MOKVARS_PREFIX),
+    ];
+    let keys = profile.binary_data.iter().flat_map(|d| d.keys());
+    let items = keys.filter_map(|key| {
+        let mut paths = dirs.iter();
+        let path = paths.find_map(|(dir, prefix)| {
+            let file = key.strip_prefix(prefix)?;
+            Some(format!("{dir}/{file}"))
+        })?;
+        Some(KeyToPath {
+            key: key.clone(),
+            path,
+            mode: None,
+        })
+    });
+    let volume = Volume {
+        name: volume_name.clone(),
+        config_map: Some(ConfigMapVolumeSource {
+            name: name.clone(),
+            items: Some(items.collect()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let volume_mount = VolumeMount {
+        name: volume_name,
+        mount_path: format!("{FIRMWARE_PROFILES_DIR}/{name}"),
+        read_only: Some(true),
+        ..Default::default()
+    };
+    (volume, volume_mount)
+}
+
+/// Pod spec of a compute-pcrs job. With `image_volume`, the image is mounted
+/// as an image volume; otherwise, compute-pcrs pulls it.
 fn build_compute_pcrs_pod_spec(
     resource_name: &str,
     boot_image: &str,
     pcrs_compute_image: &str,
+    settings: &PodSettings,
+    selection: &[u64],
+    firmware_profiles: &[ConfigMap],
+    image_volume: bool,
 ) -> PodSpec {
-    let image_volume_name = "image";
-    let image_mountpoint = PathBuf::from(format!("/{image_volume_name}"));
-    let pcrs_volume_name = "pcrs";
-    let pcrs_mountpoint = PathBuf::from(format!("/{pcrs_volume_name}"));
+    let image_mountpoint = PathBuf::from(format!("/{IMAGE_VOLUME_NAME}"));
 
-    let mut cmd = vec![PCR_COMMAND_NAME.to_string()];
+    let selection_arg: Vec<_> = selection.iter().map(ToString::to_string).collect();
+    let selection_arg = selection_arg.join(",");
+    let mut cmd = vec![PCR_COMMAND_NAME.to_string(), "cluster".to_string()];
     let mut add_flag = |flag: &str, value: &str| {
         cmd.push(format!("--{flag}"));
         cmd.push(value.to_string());
     };
-    for (flag, path_suffix) in [
-        ("kernels", "usr/lib/modules"),
-        ("esp", "usr/lib/bootupd/updates"),
-    ] {
-        let full_path = image_mountpoint.clone().join(path_suffix);
-        add_flag(flag, full_path.to_str().unwrap());
+    if image_volume {
+        for (flag, path_suffix) in [("kernels", IMAGE_KERNELS_DIR), ("esp", IMAGE_ESP_DIR)] {
+            let full_path = image_mountpoint.clone().join(path_suffix);
+            add_flag(flag, full_path.to_str().unwrap());
+        }
     }
     for (flag, value) in [
         ("efivars", "/reference-values/efivars/qemu-ovmf/fedora-42"),
         ("mokvars", "/reference-values/mok-variables/fedora-42"),
         ("image", boot_image),
         ("resource-name", resource_name),
+        ("pcrs", &selection_arg),
     ] {
         add_flag(flag, value);
     }
+    if !firmware_profiles.is_empty() {
+        add_flag("firmware-profiles", FIRMWARE_PROFILES_DIR);
+    }
+    if !image_volume {
+        cmd.push("--pull".to_string());
+    }
 
-    PodSpec {
-        service_account_name: Some("trusted-cluster-operator".to_string()),
+    let mut pod_spec = PodSpec {
+        // Jobs only report their result, and do not access the API
+        automount_service_account_token: Some(false),
         containers: vec![Container {
             name: PCR_COMMAND_NAME.to_string(),
             image: Some(pcrs_compute_image.to_string()),
             command: Some(cmd),
-            volume_mounts: Some(vec![
-                VolumeMount {
-                    name: image_volume_name.to_string(),
-                    mount_path: image_mountpoint.to_str().unwrap().to_string(),
-                    ..Default::default()
-                },
-                VolumeMount {
-                    name: pcrs_volume_name.to_string(),
-                    mount_path: pcrs_mountpoint.to_str().unwrap().to_string(),
-                    ..Default::default()
-                },
-            ]),
+            // Panics are reported in logs
+            termination_message_policy: Some("FallbackToLogsOnError".to_string()),
             ..Default::default()
         }],
-        volumes: Some(vec![
-            Volume {
-                name: image_volume_name.to_string(),
-                image: Some(ImageVolumeSource {
-                    reference: Some(boot_image.to_string()),
-                    ..Default::default()
-                }),
+        restart_policy: Some("Never".to_string()),
+        ..Default::default()
+    };
+    if image_volume {
+        pod_spec.containers[0].volume_mounts = Some(vec![VolumeMount {
+            name: IMAGE_VOLUME_NAME.to_string(),
+            mount_path: image_mountpoint.to_str().unwrap().to_string(),
+            ..Default::default()
+        }]);
+        pod_spec.volumes = Some(vec![Volume {
+            name: IMAGE_VOLUME_NAME.to_string(),
+            image: Some(ImageVolumeSource {
+                reference: Some(boot_image.to_string()),
                 ..Default::default()
-            },
-            Volume {
-                name: pcrs_volume_name.to_string(),
-                config_map: Some(ConfigMapVolumeSource {
-                    name: PCR_CONFIG_MAP.to_string(),
-                    items: Some(vec![KeyToPath {
-                        key: PCR_CONFIG_FILE.to_string(),
-                        path: PCR_CONFIG_FILE.to_string(),
+            }),
+            ..Default::default()
+        }]);
+    }
+    for (i, profile) in firmware_profiles.iter().enumerate() {
+        let (volume, volume_mount) = firmware_profile_volume(i, profile);
+        pod_spec.volumes.get_or_insert_default().push(volume);
+        let mounts = &mut pod_spec.containers[0].volume_mounts;
+        mounts.get_or_insert_default().push(volume_mount);
+    }
+    settings.apply(&mut pod_spec);
+    harden_pod_spec(&mut pod_spec);
+    pod_spec
+}
+
+/// Mount pull secrets for compute-pcrs to pull an image with, named by
+/// their precedence
+fn mount_pull_secrets(pod_spec: &mut PodSpec, pull_secrets: &[String]) {
+    for (i, name) in pull_secrets.iter().enumerate() {
+        let volume_name = format!("pull-secret-{i}");
+        pod_spec.volumes.get_or_insert_default().push(Volume {
+            name: volume_name.clone(),
+            secret: Some(SecretVolumeSource {
+                secret_name: Some(name.clone()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let mounts = &mut pod_spec.containers[0].volume_mounts;
+        mounts.get_or_insert_default().push(VolumeMount {
+            name: volume_name,
+            mount_path: format!("{PULL_SECRETS_DIR}/{i}"),
+            read_only: Some(true),
+            ..Default::default()
+        });
+    }
+}
+
+/// Whether the cluster supports image volumes, judged by whether a dry run
+/// of a job with one keeps it. API servers without the ImageVolume feature
+/// drop or reject the volume source.
+pub async fn image_volumes_supported(client: Client) -> Result<bool> {
+    let volume = Volume {
+        name: "image".to_string(),
+        image: Some(ImageVolumeSource {
+            reference: Some(PROBE_IMAGE.to_string()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let job = Job {
+        metadata: ObjectMeta {
+            generate_name: Some(format!("{PCR_COMMAND_NAME}-probe-")),
+            ..Default::default()
+        },
+        spec: Some(JobSpec {
+            template: PodTemplateSpec {
+                metadata: None,
+                spec: Some(PodSpec {
+                    containers: vec![Container {
+                        name: PCR_COMMAND_NAME.to_string(),
+                        image: Some(PROBE_IMAGE.to_string()),
                         ..Default::default()
-                    }]),
+                    }],
+                    volumes: Some(vec![volume]),
+                    restart_policy: Some("Never".to_string()),
                     ..Default::default()
                 }),
-                ..Default::default()
             },
-        ]),
-        restart_policy: Some("Never".to_string()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let jobs: Api<Job> = Api::default_namespaced(client);
+    let params = PostParams {
+        dry_run: true,
+        ..Default::default()
+    };
+    let job = match jobs.create(&params, &job).await {
+        Ok(job) => job,
+        Err(kube::Error::Api(e)) if e.code == 422 => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    let spec = job.spec.and_then(|s| s.template.spec);
+    let volumes = spec.and_then(|s| s.volumes).unwrap_or_default();
+    Ok(volumes.iter().any(|v| v.image.is_some()))
+}
+
+/// Termination message of a pod's container, or why it is waiting
+fn container_message(pod: &Pod) -> Option<String> {
+    let statuses = pod.status.as_ref()?.container_statuses.as_ref()?;
+    let state = statuses.first()?.state.as_ref()?;
+    if let Some(terminated) = &state.terminated {
+        return terminated.message.clone().or(terminated.reason.clone());
+    }
+    let waiting = state.waiting.as_ref()?;
+    let reason = waiting.reason.as_deref().unwrap_or("Waiting");
+    match &waiting.message {
+        Some(message) => Some(format!("{reason}: {message}")),
+        None => Some(reason.to_string()),
+    }
+}
+
+/// Whether an error of a pod is about mounting its image volume
+fn is_image_volume_error(message: &str) -> bool {
+    let message = message.to_lowercase();
+    message.contains(&format!("volume \"{IMAGE_VOLUME_NAME}\"")) || message.contains("image volume")
+}
+
+/// Why the pods of a job that mounts its image as an image volume cannot
+/// mount it, if they cannot. The API server may accept image volumes while
+/// the kubelet or container runtime does not support them, which the probe
+/// at startup cannot tell.
+async fn image_volume_failure(job: &Job, ctx: &RvContextData) -> Result<Option<String>> {
+    let spec = job.spec.as_ref().and_then(|s| s.template.spec.as_ref());
+    let volumes = spec.and_then(|s| s.volumes.as_ref());
+    if !volumes.into_iter().flatten().any(|v| v.image.is_some()) {
+        return Ok(None);
+    }
+    let name = job.metadata.name.as_deref().unwrap_or_default();
+    let pods: Api<Pod> = Api::default_namespaced(ctx.client.clone());
+    let params = ListParams::default().labels(&format!("{JOB_NAME_LABEL_KEY}={name}"));
+    let pods = pods.list(&params).await?.items;
+    let events: Api<CoreEvent> = Api::default_namespaced(ctx.client.clone());
+    let pending = pods.iter().filter(|p| {
+        let phase = p.status.as_ref().and_then(|s| s.phase.as_deref());
+        phase == Some("Pending")
+    });
+    for pod in pending {
+        let message = container_message(pod).filter(|m| is_image_volume_error(m));
+        if message.is_some() {
+            return Ok(message);
+        }
+        let pod_name = pod.metadata.name.as_deref().unwrap_or_default();
+        let selector = format!("involvedObject.kind=Pod,involvedObject.name={pod_name}");
+        let pod_events = events
+            .list(&ListParams::default().fields(&selector))
+            .await?;
+        let failed_mount = pod_events
+            .items
+            .into_iter()
+            .filter(|e| e.reason.as_deref() == Some("FailedMount"))
+            .filter_map(|e| e.message)
+            .find(|m| is_image_volume_error(m));
+        if failed_mount.is_some() {
+            return Ok(failed_mount);
+        }
+    }
+    Ok(None)
+}
+
+/// How long ago and why a job failed, if it did. A job fails when it has
+/// exhausted its retries, or when its pod has been pending for too long.
+async fn job_failure(job: &Job, ctx: &RvContextData) -> Result<Option<(Duration, String)>> {
+    let name = job.metadata.name.as_deref().unwrap_or_default();
+    let conditions = job.status.as_ref().and_then(|s| s.conditions.as_ref());
+    let failed = conditions
+        .into_iter()
+        .flatten()
+        .find(|c| c.type_ == "Failed" && c.status == "True");
+    let timeout = ctx.pcrs_compute_job_settings.pending_timeout;
+    let job_age = elapsed_since(job.metadata.creation_timestamp.as_ref());
+    if failed.is_none() && job_age < timeout {
+        return Ok(None);
+    }
+
+    let pods: Api<Pod> = Api::default_namespaced(ctx.client.clone());
+    let params = ListParams::default().labels(&format!("{JOB_NAME_LABEL_KEY}={name}"));
+    let mut pods = pods.list(&params).await?.items;
+    pods.sort_by_key(|p| p.metadata.creation_timestamp.as_ref().map(|t| t.0));
+    if let Some(condition) = failed {
+        let since = elapsed_since(condition.last_transition_time.as_ref());
+        let message = pods.last().and_then(container_message);
+        let message = message.or(condition.message.clone());
+        let message = message.unwrap_or("Job failed".to_string());
+        return Ok(Some((since, message)));
+    }
+    let pending = pods.iter().filter(|p| {
+        let phase = p.status.as_ref().and_then(|s| s.phase.as_deref());
+        phase == Some("Pending")
+    });
+    for pod in pending {
+        let pod_age = elapsed_since(pod.metadata.creation_timestamp.as_ref());
+        if pod_age >= timeout {
+            let message = container_message(pod).unwrap_or("no status".to_string());
+            let message = format!("Pod was pending for over {}s: {message}", timeout.as_secs());
+            return Ok(Some((pod_age - timeout, message)));
+        }
+    }
+    Ok(None)
+}
+
+/// Whether an image has a Committed condition with the given reason for
+/// its current generation
+fn has_current_reason(image: &ApprovedImage, reason: &str) -> bool {
+    let conditions = image.status.as_ref().and_then(|s| s.conditions.as_ref());
+    conditions
+        .into_iter()
+        .flatten()
+        .any(|c| c.reason == reason && c.observed_generation == image.metadata.generation)
+}
+
+/// Whether a job computes PCRs for an image, for any architecture
+async fn job_exists(client: Client, boot_image: &str) -> Result<bool> {
+    let jobs: Api<Job> = Api::default_namespaced(client);
+    let architectures = registry::SUPPORTED_ARCHITECTURES.map(Some);
+    for architecture in [None].into_iter().chain(architectures) {
+        let name = get_job_name(boot_image, architecture)?;
+        if jobs.get_opt(&name).await?.is_some() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+async fn set_image_failed(job: &Job, ctx: &RvContextData, message: &str) -> Result<()> {
+    let annotations = job.metadata.annotations.as_ref();
+    let Some(name) = annotations.and_then(|a| a.get(IMAGE_ANNOTATION_KEY)) else {
+        return Ok(());
+    };
+    let images: Api<ApprovedImage> = Api::default_namespaced(ctx.client.clone());
+    let Some(image) = images.get_opt(name).await? else {
+        return Ok(());
+    };
+    if has_current_reason(&image, NOT_COMMITTED_REASON_FAILED) {
+        return Ok(());
+    }
+    let mut committed = committed_condition(NOT_COMMITTED_REASON_FAILED, image.metadata.generation);
+    committed.message = format!("Computation failed: {message}");
+    update_status!(images, name, committed_status(committed))?;
+    if ImagePcrsStore::new(ctx.client.clone())
+        .discard_incomplete(name)
+        .await?
+    {
+        info!("Discarded PCRs of image {name} whose other architectures were pending");
+        trustee::update_reference_values(ctx.clone()).await?;
+    }
+    Ok(())
+}
+
+/// Log of the succeeded pod of a job, which compute-pcrs reports its result
+/// in
+async fn job_output(job: &Job, ctx: &RvContextData) -> Result<Option<String>> {
+    let name = job.metadata.name.as_deref().unwrap_or_default();
+    let pods: Api<Pod> = Api::default_namespaced(ctx.client.clone());
+    let params = ListParams::default().labels(&format!("{JOB_NAME_LABEL_KEY}={name}"));
+    let list = pods.list(&params).await?.items;
+    let succeeded = list.iter().find(|p| {
+        let phase = p.status.as_ref().and_then(|s| s.phase.as_deref());
+        phase == Some("Succeeded")
+    });
+    let Some(pod_name) = succeeded.and_then(|p| p.metadata.name.as_deref()) else {
+        return Ok(None);
+    };
+    let params = LogParams {
+        container: Some(PCR_COMMAND_NAME.to_string()),
         ..Default::default()
+    };
+    Ok(Some(pods.logs(pod_name, &params).await?))
+}
+
+/// Check that PCRs with exactly the given IDs and well-formed values were
+/// computed
+fn check_pcrs(pcrs: &[Pcr], ids: &[u64]) -> Result<()> {
+    let computed: Vec<_> = pcrs.iter().map(|p| p.id).collect();
+    let complete = ids.iter().all(|id| computed.contains(id));
+    if computed.len() != ids.len() || !complete {
+        bail!("PCRs {computed:?} were computed, but {ids:?} were requested");
+    }
+    for pcr in pcrs.iter() {
+        let hex = pcr.value.chars().all(|c| c.is_ascii_hexdigit());
+        if pcr.value.len() != 64 || !hex {
+            bail!("PCR{} had invalid value {}", pcr.id, pcr.value);
+        }
+    }
+    Ok(())
+}
+
+/// Check that a job computed what it was requested to
+fn validate_result(request: &ComputeRequest, result: &ComputePcrsResult) -> Result<()> {
+    let per_profile = !request.firmware_profiles.is_empty();
+    let (profile_ids, ids): (Vec<u64>, Vec<u64>) = request
+        .pcrs
+        .iter()
+        .partition(|id| per_profile && FIRMWARE_PROFILE_PCRS.contains(id));
+    check_pcrs(&result.pcrs, &ids)?;
+    let requested: BTreeSet<_> = request.firmware_profiles.iter().collect();
+    let computed: BTreeSet<_> = result.firmware_profile_pcrs.keys().collect();
+    if requested != computed {
+        bail!("Firmware profiles {computed:?} were computed, but {requested:?} were requested");
+    }
+    for (profile, pcrs) in result.firmware_profile_pcrs.iter() {
+        let check = check_pcrs(pcrs, &profile_ids);
+        check.with_context(|| format!("Firmware profile {profile} was invalid"))?;
+    }
+    Ok(())
+}
+
+/// Describe how computed PCRs differ from the expected ones
+fn pcr_mismatches(expected: &BTreeMap<u64, String>, computed: &[Pcr]) -> Vec<String> {
+    let mismatch = |(id, value): (&u64, &String)| {
+        let actual = computed
+            .iter()
+            .find(|p| p.id == *id)
+            .map(|p| p.value.as_str());
+        let actual_str = actual.unwrap_or("nothing");
+        let message = format!("PCR{id} was labeled {value}, but computed {actual_str}");
+        (actual != Some(value.as_str())).then_some(message)
+    };
+    expected.iter().filter_map(mismatch).collect()
+}
+
+/// Validate the result of a completed job, and store its PCRs and the
+/// status of the ApprovedImage it computed them for
+async fn store_job_result(job: &Job, ctx: &RvContextData) -> Result<()> {
+    let name = job.metadata.name.as_deref().unwrap_or_default();
+    let annotations = job.metadata.annotations.as_ref();
+    let annotation = |key| annotations.and_then(|a| a.get(key));
+    let Some((resource_name, request)) =
+        annotation(IMAGE_ANNOTATION_KEY).zip(annotation(REQUEST_ANNOTATION_KEY))
+    else {
+        info!("Job {name} completed, but did not record what it computed");
+        return Ok(());
+    };
+    let request: ComputeRequest = serde_json::from_str(request)?;
+    let images: Api<ApprovedImage> = Api::default_namespaced(ctx.client.clone());
+    let Some(image) = images.get_opt(resource_name).await? else {
+        info!("Job {name} completed, but image {resource_name} was deleted");
+        return Ok(());
+    };
+    if pinned_image(&image) != request.reference {
+        info!(
+            "Job {name} computed PCRs for {}, but image {resource_name} approves {} now",
+            request.reference,
+            pinned_image(&image)
+        );
+        return Ok(());
+    }
+    let generation = image.metadata.generation;
+    // PCRs of one architecture must not be trusted once another failed
+    let failed = [NOT_COMMITTED_REASON_FAILED, NOT_COMMITTED_REASON_MISMATCH];
+    let failed = failed.iter().any(|r| has_current_reason(&image, r));
+    if request.architecture.is_some() && failed {
+        info!("Job {name} completed, but image {resource_name} failed for another architecture");
+        return Ok(());
+    }
+    let store = ImagePcrsStore::new(ctx.client.clone());
+
+    let output = job_output(job, ctx).await?;
+    let result = output
+        .context("Job had no succeeded pod")
+        .and_then(|o| ComputePcrsResult::from_log(&o))
+        .and_then(|r| validate_result(&request, &r).map(|_| r));
+    let result = match result {
+        Ok(result) => result,
+        Err(e) => {
+            warn!("Job {name} returned an invalid result: {e:#}");
+            let mut committed = committed_condition(NOT_COMMITTED_REASON_FAILED, generation);
+            committed.message = format!("Computation returned an invalid result: {e:#}");
+            update_status!(images, resource_name, committed_status(committed))?;
+            store.discard_incomplete(resource_name).await?;
+            return Ok(());
+        }
+    };
+    let mismatches = pcr_mismatches(&request.expected_pcrs, &result.pcrs);
+    if !mismatches.is_empty() {
+        let mut committed = committed_condition(NOT_COMMITTED_REASON_MISMATCH, generation);
+        committed.message = format!(
+            "PCRs computed from the image did not match its label: {}",
+            mismatches.join("; ")
+        );
+        update_status!(images, resource_name, committed_status(committed))?;
+        store.discard_incomplete(resource_name).await?;
+        return Ok(());
+    }
+
+    let pod_spec = job.spec.as_ref().and_then(|s| s.template.spec.as_ref());
+    let container = pod_spec.and_then(|s| s.containers.first());
+    let compute_image = container.and_then(|c| c.image.clone()).unwrap_or_default();
+    let (pcrs, firmware_profile_pcrs, platforms) = match &request.architecture {
+        None => (result.pcrs, result.firmware_profile_pcrs, BTreeMap::new()),
+        // Other architectures are pending unless they were already stored
+        Some(architecture) => {
+            let pending = request.architectures.iter().map(|a| (a.clone(), None));
+            let mut platforms: BTreeMap<_, _> = pending.collect();
+            let platform = PlatformPcrs {
+                pcrs: result.pcrs,
+                firmware_profile_pcrs: result.firmware_profile_pcrs,
+            };
+            platforms.insert(architecture.clone(), Some(platform));
+            (vec![], BTreeMap::new(), platforms)
+        }
+    };
+    let image_pcr = ImagePcr {
+        first_seen: Utc::now(),
+        reference: request.reference,
+        pcrs,
+        firmware_profile_pcrs,
+        source: ApprovedImageStatusPcrSource::Computed,
+        compute_image: Some(compute_image.clone()),
+        staged: None,
+        platforms,
+    };
+    let image_pcr = store.insert(resource_name, image_pcr).await?;
+    if !image_pcr.is_complete() {
+        info!(
+            "Job {name} computed PCRs for image {resource_name}, other architectures are pending"
+        );
+        return Ok(());
     }
+    let computation = PcrsComputation {
+        job: name.to_string(),
+        image: compute_image,
+        tool_version: result.tool_version,
+    };
+    let mut status = pcrs_status(&image_pcr, Some(&computation));
+    let committed = committed_condition(COMMITTED_REASON, generation);
+    status["conditions"] = serde_json::json!([committed]);
+    update_status!(images, resource_name, status)?;
+    Ok(())
 }
 
 async fn job_reconcile(job: Arc<Job>, ctx: Arc<RvContextData>) -> Result<Action, ControllerError> {
@@ -167,11 +778,37 @@ async fn job_reconcile(job: Arc<Job>, ctx: Arc<RvContextData>) -> Result<Action,
     let name = &job.metadata.name.clone().context(err)?;
     let err = format!("Job {name} changed, but had no status");
     let status = &job.status.clone().context(err)?;
+    let jobs: Api<Job> = Api::default_namespaced(ctx.client.clone());
     if status.completion_time.is_none() {
-        info!("Job {name} changed, but had not completed");
-        return Ok(Action::requeue(Duration::from_secs(300)));
+        if let Some(message) = image_volume_failure(&job, &ctx).await? {
+            warn!(
+                "Job {name} could not mount its image volume, compute-pcrs jobs will pull \
+                 images from now on: {message}"
+            );
+            ctx.image_volumes.store(false, Ordering::Release);
+            // Deleting the job has its image recomputed by a job that pulls
+            info!("Deleting job {name} to retry it in pull mode");
+            let delete = jobs.delete(name, &DeleteParams::foreground()).await;
+            delete.map_err(Into::<anyhow::Error>::into)?;
+            return Ok(Action::await_change());
+        }
+        let Some((failed_for, message)) = job_failure(&job, &ctx).await? else {
+            info!("Job {name} changed, but had not completed");
+            return Ok(Action::requeue(Duration::from_secs(300)));
+        };
+        warn!("Job {name} failed: {message}");
+        set_image_failed(&job, &ctx, &message).await?;
+        // Failed jobs are kept for inspection for a while
+        let retention = ctx.pcrs_compute_job_settings.failed_job_retention;
+        if failed_for < retention {
+            return Ok(Action::requeue(retention - failed_for));
+        }
+        info!("Deleting failed job {name}");
+        let delete = jobs.delete(name, &DeleteParams::foreground()).await;
+        delete.map_err(Into::<anyhow::Error>::into)?;
+        return Ok(Action::await_change());
     }
-    let jobs: Api<Job> = Api::default_namespaced(ctx.client.clone());
+    store_job_result(&job, &ctx).await?;
     // Foreground deletion: Delete the pod too
     let delete = jobs.delete(name, &DeleteParams::foreground()).await;
     delete.map_err(Into::<anyhow::Error>::into)?;
@@ -192,40 +829,133 @@ pub async fn launch_rv_job_controller(ctx: RvContextData) {
     );
 }
 
+/// compute-pcrs jobs serve nothing, so deny all ingress to them
+pub async fn create_compute_pcrs_network_policy(
+    client: Client,
+    owner_reference: OwnerReference,
+) -> Result<()> {
+    let labels = BTreeMap::from([(JOB_LABEL_KEY.to_string(), PCR_COMMAND_NAME.to_string())]);
+    let network_policy =
+        generate_network_policy(PCR_COMMAND_NAME, owner_reference, labels, vec![], None);
+    create_or_info_if_exists!(client, NetworkPolicy, network_policy);
+    Ok(())
+}
+
+/// compute-pcrs jobs used to store PCRs themselves with a service account
+/// of their own. Delete it from clusters that were upgraded since.
+pub async fn delete_compute_pcrs_rbac(client: Client) -> Result<()> {
+    delete_service_account_with_role(client, PCR_COMMAND_NAME).await
+}
+
 // Name job by sanitized image name, plus a hash to disambiguate
-// tags that differed only beyond the truncation limit
-fn get_job_name(boot_image: &str) -> Result<String> {
+// tags that differed only beyond the truncation limit, and the
+// architecture it computes for, if any
+fn get_job_name(boot_image: &str, architecture: Option<&str>) -> Result<String> {
     let rfc1035_boot_image = boot_image.replace(['.', ':', '/', '@', '_'], "-");
     let boot_image_hash = hash(MessageDigest::sha1(), boot_image.as_bytes())?;
     let mut boot_image_hash_str = hex::encode(boot_image_hash);
     boot_image_hash_str.truncate(10);
-    let job_name = format!("{PCR_COMMAND_NAME}-{boot_image_hash_str}-{rfc1035_boot_image}");
+    let architecture = architecture.map(|a| format!("{a}-")).unwrap_or_default();
+    let job_name =
+        format!("{PCR_COMMAND_NAME}-{architecture}{boot_image_hash_str}-{rfc1035_boot_image}");
     let trimmed: String = job_name.chars().take(63).collect();
     let trimmed = trimmed.trim_end_matches('-').to_string();
     Ok(trimmed)
 }
 
+/// Start a job that computes the PCRs of an image, or of one architecture
+/// of a multi-architecture image on a node of that architecture
 async fn compute_fresh_pcrs(
     ctx: RvContextData,
     resource_name: &str,
     boot_image: &str,
+    platform: Option<&Platform<'_>>,
+    selection: &PcrSelection,
+    pull_secrets: &[String],
+    expected_pcrs: Option<&[Pcr]>,
 ) -> anyhow::Result<()> {
-    let job_name = get_job_name(boot_image)?;
-    let pod_spec = build_compute_pcrs_pod_spec(resource_name, boot_image, &ctx.pcrs_compute_image);
+    let architecture = platform.map(|p| p.architecture);
+    let job_name = get_job_name(boot_image, architecture)?;
+    // The manifest of the architecture, so that it is not resolved for the
+    // architecture of the node or operator
+    let computed_image = platform.map_or(boot_image, |p| p.image);
+    let profile_names = &selection.firmware_profiles;
+    let firmware_profiles = fetch_firmware_profiles(ctx.client.clone(), profile_names).await?;
+    let labels = BTreeMap::from([(JOB_LABEL_KEY.to_string(), PCR_COMMAND_NAME.to_string())]);
+    let compute_image = ctx.pcrs_compute_image.pinned();
+    let image_volumes = ctx.image_volumes.load(Ordering::Acquire);
+    let mut pod_spec = build_compute_pcrs_pod_spec(
+        resource_name,
+        computed_image,
+        &compute_image,
+        &ctx.pcrs_compute_pod_settings,
+        &selection.pcrs,
+        &firmware_profiles,
+        image_volumes,
+    );
+    if let Some(architecture) = architecture {
+        let node_selector = pod_spec.node_selector.get_or_insert_default();
+        node_selector.insert(ARCH_LABEL_KEY.to_string(), architecture.to_string());
+    }
+    if image_volumes {
+        // The image volume is pulled with the pod's pull secrets
+        let secret_refs = pull_secrets
+            .iter()
+            .map(|name| LocalObjectReference { name: name.clone() });
+        let pod_secrets = pod_spec.image_pull_secrets.get_or_insert_default();
+        pod_secrets.extend(secret_refs);
+    } else {
+        mount_pull_secrets(&mut pod_spec, pull_secrets);
+    }
+    let cmd = pod_spec.containers[0].command.get_or_insert_default();
+    if !image_volumes {
+        if !pull_secrets.is_empty() {
+            cmd.extend(["--pull-secrets".to_string(), PULL_SECRETS_DIR.to_string()]);
+        }
+        if !ctx.insecure_registries.is_empty() {
+            let registries = ctx.insecure_registries.join(",");
+            cmd.extend(["--insecure-registries".to_string(), registries]);
+        }
+    }
+    let request = ComputeRequest {
+        reference: boot_image.to_string(),
+        pcrs: selection.pcrs.clone(),
+        firmware_profiles: firmware_profiles
+            .iter()
+            .filter_map(|p| p.metadata.name.clone())
+            .collect(),
+        expected_pcrs: expected_pcrs
+            .into_iter()
+            .flatten()
+            .map(|p| (p.id, p.value.clone()))
+            .collect(),
+        architecture: architecture.map(ToString::to_string),
+        architectures: platform
+            .map(|p| p.architectures.to_vec())
+            .unwrap_or_default(),
+    };
     let job = Job {
         metadata: ObjectMeta {
             name: Some(job_name.clone()),
-            labels: Some(BTreeMap::from([(
-                JOB_LABEL_KEY.to_string(),
-                PCR_COMMAND_NAME.to_string(),
-            )])),
+            labels: Some(labels.clone()),
+            annotations: Some(BTreeMap::from([
+                (IMAGE_ANNOTATION_KEY.to_string(), resource_name.to_string()),
+                (
+                    REQUEST_ANNOTATION_KEY.to_string(),
+                    serde_json::to_string(&request)?,
+                ),
+            ])),
             owner_references: Some(vec![ctx.owner_reference]),
             ..Default::default()
         },
         spec: Some(JobSpec {
+            backoff_limit: Some(ctx.pcrs_compute_job_settings.backoff_limit),
             template: PodTemplateSpec {
+                metadata: Some(ObjectMeta {
+                    labels: Some(labels),
+                    ..Default::default()
+                }),
                 spec: Some(pod_spec),
-                ..Default::default()
             },
             ..Default::default()
         }),
@@ -243,6 +973,13 @@ async fn image_reconcile(
     let err = "ApprovedImage had no name";
     let name = image.metadata.name.clone().expect(err);
 
+    let finalizers = image.finalizers();
+    let finalizing = finalizers.iter().any(|f| f == APPROVED_IMAGE_FINALIZER);
+    if let Some(deadline) = drain_deadline(&ctx, &image).filter(|_| finalizing) {
+        let drain = drain_image(&ctx, &image, deadline).await;
+        return drain.map_err(|e| anyhow!("failed to drain image {name}: {e}").into());
+    }
+
     let images: Api<ApprovedImage> = Api::default_namespaced(kube_client);
     let finalizer_ctx = Arc::unwrap_or_clone(ctx);
     finalizer(&images, APPROVED_IMAGE_FINALIZER, image, |ev| async {
@@ -258,32 +995,289 @@ async fn image_reconcile(
     .map_err(|e| anyhow!("failed to reconcile on image: {e}").into())
 }
 
+/// End of the drain window of an image that is being deleted, unless it
+/// has already passed
+fn drain_deadline(ctx: &RvContextData, image: &ApprovedImage) -> Option<DateTime<Utc>> {
+    let deleted = image.metadata.deletion_timestamp.as_ref()?;
+    let drain_seconds = image.spec.drain_seconds;
+    let drain = drain_seconds.map(|s| Duration::from_secs(s as u64));
+    let drain = TimeDelta::from_std(drain.unwrap_or(ctx.image_drain)).ok()?;
+    let deadline = deleted.0.checked_add_signed(drain)?;
+    (deadline > Utc::now()).then_some(deadline)
+}
+
+fn deprecated_condition(generation: Option<i64>) -> Condition {
+    Condition {
+        type_: DEPRECATED_CONDITION.to_string(),
+        status: condition_status(true),
+        reason: DRAINING_REASON.to_string(),
+        message: "Image was deleted and stays trusted until drainDeadline".to_string(),
+        last_transition_time: Time(Utc::now()),
+        observed_generation: generation,
+    }
+}
+
+/// Images that nodes currently boot, by the addresses of the nodes
+fn node_boot_images(nodes: &[Node]) -> BTreeMap<String, String> {
+    let mut images = BTreeMap::new();
+    for node in nodes {
+        let Some(image) = node.annotations().get(NODE_IMAGE_ANNOTATION_KEY) else {
+            continue;
+        };
+        let status = node.status.as_ref();
+        for address in status
+            .and_then(|s| s.addresses.as_ref())
+            .into_iter()
+            .flatten()
+        {
+            images.insert(address.address.clone(), image.clone());
+        }
+    }
+    images
+}
+
+/// Whether two references name the same image. A reference that pins a
+/// digest matches regardless of the tag next to it.
+fn same_image(a: &str, b: &str) -> bool {
+    let parse = |r: &str| r.parse::<oci_client::Reference>().ok();
+    match (parse(a), parse(b)) {
+        (Some(a), Some(b)) if a.digest().is_some() || b.digest().is_some() => {
+            a.resolve_registry() == b.resolve_registry()
+                && a.repository() == b.repository()
+                && a.digest() == b.digest()
+        }
+        _ => a == b,
+    }
+}
+
+/// Keep a deleted image trusted until its drain window ends, and show the
+/// Machines that still boot it in the meantime. A Machine boots what its
+/// status reports, or else what the node at its registration address boots.
+async fn drain_image(
+    ctx: &RvContextData,
+    image: &ApprovedImage,
+    deadline: DateTime<Utc>,
+) -> Result<Action> {
+    let name = image.metadata.name.as_deref().unwrap_or_default();
+    let machines: Api<Machine> = Api::default_namespaced(ctx.client.clone());
+    let machines = machines.list(&Default::default()).await?.items;
+    let nodes: Api<Node> = Api::all(ctx.client.clone());
+    let node_images = node_boot_images(&nodes.list(&Default::default()).await?.items);
+    let booted = pinned_image(image);
+    let draining: Vec<_> = machines
+        .iter()
+        .filter(|m| {
+            let status = m.status.as_ref();
+            let reported = status.and_then(|s| s.boot_image.as_deref());
+            let address = m.spec.registration_address.as_str();
+            let node_image = node_images.get(address).map(String::as_str);
+            reported
+                .or(node_image)
+                .is_some_and(|b| same_image(b, booted))
+        })
+        .filter_map(|m| m.metadata.name.clone())
+        .collect();
+
+    let deadline_json = serde_json::to_value(Time(deadline))?;
+    let status = image.status.as_ref();
+    let conditions = status.and_then(|s| s.conditions.clone());
+    let mut conditions = conditions.unwrap_or_default();
+    let deprecated = conditions.iter().any(|c| c.type_ == DEPRECATED_CONDITION);
+    // Status updates trigger reconciliation, so only update on changes
+    let unchanged = status.is_some_and(|s| {
+        s.drain_deadline.as_deref() == deadline_json.as_str()
+            && s.draining_machines.as_ref() == Some(&draining)
+    });
+    if !deprecated || !unchanged {
+        info!(
+            "Image {name} was deleted, keeping it trusted until {deadline} for {} Machines",
+            draining.len()
+        );
+        if !deprecated {
+            conditions.push(deprecated_condition(image.metadata.generation));
+        }
+        let status = serde_json::json!({
+            "conditions": conditions,
+            "drainDeadline": deadline_json,
+            "drainingMachines": draining,
+        });
+        let images: Api<ApprovedImage> = Api::default_namespaced(ctx.client.clone());
+        update_status!(images, name, status)?;
+    }
+    let remaining = (deadline - Utc::now()).to_std().unwrap_or_default();
+    Ok(Action::requeue(remaining.min(DRAIN_RECHECK_INTERVAL)))
+}
+
 async fn image_add_reconcile(
     ctx: RvContextData,
     image: &ApprovedImage,
 ) -> Result<Action, finalizer::Error<ControllerError>> {
     let kube_client = ctx.client.clone();
     let name = image.metadata.name.as_ref().unwrap();
-    let (action, reason) = match handle_new_image(ctx, name, &image.spec.image).await {
-        Ok(reason) => (Action::await_change(), reason),
+    if has_current_reason(image, NOT_COMMITTED_REASON_MISMATCH) {
+        info!("Image {name} did not match its PCR label, not recomputing");
+        return Ok(Action::await_change());
+    }
+    // Recompute only once a failed job is no longer retained
+    if has_current_reason(image, NOT_COMMITTED_REASON_FAILED) {
+        let exists = job_exists(kube_client.clone(), pinned_image(image)).await;
+        if exists.map_err(|e| finalizer::Error::ApplyFailed(e.into()))? {
+            info!("Computation for image {name} failed, not recomputing while its job exists");
+            return Ok(Action::await_change());
+        }
+    }
+    let selection = ctx.pcr_selection.with_overrides(
+        image.spec.pcr_selection.as_ref(),
+        image.spec.firmware_profiles.as_ref(),
+    );
+    let image_secrets = image.spec.image_pull_secrets.iter().flatten();
+    let image_secrets = image_secrets.filter_map(|s| s.name.clone());
+    let pull_secrets: Vec<_> = image_secrets
+        .chain(ctx.image_pull_secrets.clone())
+        .collect();
+    let images: Api<ApprovedImage> = Api::default_namespaced(kube_client);
+    let handle = async {
+        let boot_image = resolve_tag(&ctx, image, &pull_secrets).await?;
+        // Record a resolution right away, so that the tag is not resolved
+        // again, possibly to another digest, if anything below fails
+        if boot_image != pinned_image(image) {
+            let status = serde_json::json!({"resolvedImage": boot_image});
+            update_status!(images, name, status)?;
+        }
+        handle_new_image(ctx, name, &boot_image, &selection, &pull_secrets).await
+    };
+    let (action, reason, error) = match handle.await {
+        Ok(reason) => (Action::await_change(), reason, None),
         Err(e) => {
             warn!("PCR computation for {name} failed: {e}");
             let action = Action::requeue(Duration::from_secs(60));
-            (action, NOT_COMMITTED_REASON_FAILED)
+            (action, NOT_COMMITTED_REASON_FAILED, Some(e))
         }
     };
-    let committed = committed_condition(reason, image.metadata.generation);
-    let conditions = Some(vec![committed]);
-    let images: Api<ApprovedImage> = Api::default_namespaced(kube_client);
-    update_status!(images, &name, ApprovedImageStatus { conditions })
+    let mut committed = committed_condition(reason, image.metadata.generation);
+    if let Some(e) = error {
+        committed.message = format!("Computation failed: {e:#}");
+    }
+    let status = committed_status(committed);
+    update_status!(images, &name, status)
         .map_err(|e| finalizer::Error::<ControllerError>::ApplyFailed(e.into()))?;
     Ok(action)
 }
 
+/// Image to compute PCRs for, i.e. the resolved image if its tag was resolved
+fn pinned_image(image: &ApprovedImage) -> &str {
+    let resolved = image
+        .status
+        .as_ref()
+        .and_then(|s| s.resolved_image.as_ref());
+    resolved.unwrap_or(&image.spec.image)
+}
+
+/// Resolve the tag of an image that opts in to the digest it points to.
+/// A tag is only resolved once, so that its later changes are not followed.
+async fn resolve_tag(
+    ctx: &RvContextData,
+    image: &ApprovedImage,
+    pull_secrets: &[String],
+) -> Result<String> {
+    let pinned = pinned_image(image);
+    if image.spec.resolve_tag != Some(true) || pinned != image.spec.image {
+        return Ok(pinned.to_string());
+    }
+    resolve_digest(ctx, pinned, pull_secrets).await
+}
+
+/// Pin an image to the digest that its tag points to, unless it has one
+async fn resolve_digest(
+    ctx: &RvContextData,
+    image: &str,
+    pull_secrets: &[String],
+) -> Result<String> {
+    let image_ref: oci_client::Reference = image.parse()?;
+    if image_ref.digest().is_some() {
+        return Ok(image.to_string());
+    }
+    let secrets = registry::fetch_pull_secrets(ctx.client.clone(), pull_secrets).await?;
+    let auth = registry::registry_auth(&secrets, &image_ref)?;
+    let registry_client = registry::oci_client(&ctx.insecure_registries);
+    let digest = registry_client
+        .fetch_manifest_digest(&image_ref, &auth)
+        .await?;
+    info!("Resolved image {image} to digest {digest}");
+    Ok(format!("{image}@{digest}"))
+}
+
+/// Pin the compute-pcrs image to a digest, so that computed PCRs record
+/// exactly which image computed them. The image is used as given if it
+/// cannot be resolved, e.g. because only nodes can pull from its registry.
+pub async fn pin_compute_image(ctx: &RvContextData, image: &str) -> String {
+    match resolve_digest(ctx, image, &ctx.image_pull_secrets).await {
+        Ok(pinned) => pinned,
+        Err(e) => {
+            warn!("Failed to resolve compute-pcrs image {image}, using it as given: {e}");
+            image.to_string()
+        }
+    }
+}
+
+/// Follow pcrsComputeImage of the TrustedExecutionCluster, and signal when
+/// a change of it pinned a different compute-pcrs image
+async fn follow_compute_image(ctx: RvContextData, changed: watch::Sender<()>) {
+    let clusters: Api<TrustedExecutionCluster> = Api::default_namespaced(ctx.client.clone());
+    let name = &ctx.owner_reference.name;
+    let config = watcher::Config::default().fields(&format!("metadata.name={name}"));
+    let clusters = watcher::watcher(clusters, config).default_backoff();
+    let mut clusters = clusters.applied_objects().boxed();
+    while let Some(cluster) = clusters.next().await {
+        let cluster = match cluster {
+            Ok(cluster) => cluster,
+            Err(e) => {
+                warn!("Watching TrustedExecutionCluster {name} failed: {e}");
+                continue;
+            }
+        };
+        let given = cluster.spec.pcrs_compute_image;
+        if given == ctx.pcrs_compute_image.given() {
+            continue;
+        }
+        let pinned = pin_compute_image(&ctx, &given).await;
+        if ctx.pcrs_compute_image.set(given, pinned.clone()) {
+            info!("compute-pcrs image changed to {pinned}, recomputing PCRs of all images");
+            changed.send_replace(());
+        }
+    }
+}
+
+/// Image that a failed job computed for once the job is deleted, so that
+/// the image is recomputed when failed jobs are no longer retained or are
+/// retried in pull mode
+fn failed_job_image(job: Job) -> Option<ObjectRef<ApprovedImage>> {
+    let deleted = job.metadata.deletion_timestamp.is_some();
+    let status = job.status.as_ref();
+    let failed = status.is_none_or(|s| s.completion_time.is_none());
+    let annotations = job.metadata.annotations.as_ref()?;
+    let name = annotations.get(IMAGE_ANNOTATION_KEY)?;
+    let namespace = job.metadata.namespace.as_deref()?;
+    (deleted && failed).then(|| ObjectRef::new(name).within(namespace))
+}
+
 pub async fn launch_rv_image_controller(ctx: RvContextData) {
     let images: Api<ApprovedImage> = Api::default_namespaced(ctx.client.clone());
+    let jobs: Api<Job> = Api::default_namespaced(ctx.client.clone());
+    let job_watcher = watcher::Config {
+        label_selector: Some(format!("{JOB_LABEL_KEY}={PCR_COMMAND_NAME}")),
+        ..Default::default()
+    };
+    let (changed, compute_image_changes) = watch::channel(());
+    tokio::spawn(follow_compute_image(ctx.clone(), changed));
+    // Images are recomputed as they are reconciled after a change
+    let recompute_all = stream::unfold(compute_image_changes, async |mut changes| {
+        changes.changed().await.ok().map(|_| ((), changes))
+    });
     tokio::spawn(
         Controller::new(images, Default::default())
+            .watches(jobs, job_watcher, failed_job_image)
+            .reconcile_all_on(recompute_all)
             .run(image_reconcile, controller_error_policy, Arc::new(ctx))
             .for_each(controller_info),
     );
@@ -293,17 +1287,24 @@ pub async fn handle_new_image(
     ctx: RvContextData,
     resource_name: &str,
     boot_image: &str,
+    selection: &PcrSelection,
+    pull_secrets: &[String],
 ) -> Result<&'static str> {
-    let config_maps: Api<ConfigMap> = Api::default_namespaced(ctx.client.clone());
-    let mut image_pcrs_map = config_maps.get(PCR_CONFIG_MAP).await?;
-    let mut image_pcrs = get_image_pcrs(image_pcrs_map.clone())?;
-    if let Some(pcr) = image_pcrs.0.get(resource_name) {
-        if pcr.reference == boot_image {
-            info!("Image {boot_image} was to be allowed, but already was allowed");
-            return trustee::update_reference_values(ctx)
-                .await
-                .map(|_| COMMITTED_REASON);
-        }
+    let store = ImagePcrsStore::new(ctx.client.clone());
+    let compute_image = ctx.pcrs_compute_image.pinned();
+    let stored = store.get_image(resource_name).await?;
+    let current = |p: &ImagePcr| p.reference == boot_image && matches_selection(p, selection);
+    // PCRs computed by a previous compute-pcrs image are recomputed, but
+    // stay trusted until then
+    let recomputing = stored.as_ref().is_some_and(current);
+    let stored = stored
+        .as_ref()
+        .and_then(|p| p.by_compute_image(&compute_image));
+    if stored.is_some_and(current) {
+        info!("Image {boot_image} was to be allowed, but already was allowed");
+        return trustee::update_reference_values(ctx)
+            .await
+            .map(|_| COMMITTED_REASON);
     }
     let image_ref: oci_client::Reference = boot_image.parse()?;
     if image_ref.digest().is_none() {
@@ -313,55 +1314,320 @@ pub async fn handle_new_image(
         );
         return Ok(NOT_COMMITTED_REASON_NO_DIGEST);
     }
-    let label = fetch_pcr_label(&image_ref).await?;
-    if label.is_none() {
-        return compute_fresh_pcrs(ctx, resource_name, boot_image)
-            .await
-            .map(|_| NOT_COMMITTED_REASON_COMPUTING);
+    let secrets = registry::fetch_pull_secrets(ctx.client.clone(), pull_secrets).await?;
+    let auth = registry::registry_auth(&secrets, &image_ref)?;
+    let registry_client = registry::oci_client(&ctx.insecure_registries);
+    if let Some(policy) = &ctx.image_verification {
+        if !signature::verify_image(&registry_client, &image_ref, &auth, policy).await? {
+            warn!("Image {boot_image} carried no signature matching the verification policy");
+            return Ok(NOT_COMMITTED_REASON_UNVERIFIED);
+        }
     }
-
-    let image_pcr = ImagePcr {
-        first_seen: Utc::now(),
-        pcrs: label.unwrap(),
-        reference: boot_image.to_string(),
+    let trust = matches!(
+        ctx.pcr_label_trust,
+        TrustedExecutionClusterPcrLabelTrust::Trust
+    );
+    let compare = matches!(
+        ctx.pcr_label_trust,
+        TrustedExecutionClusterPcrLabelTrust::Compare
+    );
+    let computing = match recomputing {
+        true => COMMITTED_REASON,
+        false => NOT_COMMITTED_REASON_COMPUTING,
+    };
+    let profiles = || {
+        let profiles = selection.firmware_profiles.iter();
+        profiles.map(|p| (p.clone(), vec![])).collect()
+    };
+    let cache = &ctx.registry_cache;
+    let platforms = registry::image_platforms(cache, &registry_client, &image_ref, &auth).await?;
+    let Some(mut platforms) = platforms else {
+        let label = label_pcrs(&ctx, &registry_client, &image_ref, &auth, None, selection);
+        let label = label.await?;
+        let label = match (label, trust) {
+            (Some(pcrs), true) => pcrs,
+            // In compare mode, the label is what the computation must match
+            (label, _) => {
+                if compare && label.is_none() {
+                    warn_label_unusable(boot_image);
+                    return Ok(NOT_COMMITTED_REASON_LABEL_UNUSABLE);
+                }
+                if !computable(boot_image, selection) {
+                    return Ok(NOT_COMMITTED_REASON_UNCOMPUTABLE);
+                }
+                let expected = label.as_deref();
+                let compute = compute_fresh_pcrs(
+                    ctx,
+                    resource_name,
+                    boot_image,
+                    None,
+                    selection,
+                    pull_secrets,
+                    expected,
+                );
+                return compute.await.map(|_| computing);
+            }
+        };
+        let image_pcr = ImagePcr {
+            first_seen: Utc::now(),
+            pcrs: label,
+            reference: boot_image.to_string(),
+            firmware_profile_pcrs: profiles(),
+            source: ApprovedImageStatusPcrSource::Label,
+            compute_image: None,
+            staged: None,
+            platforms: BTreeMap::new(),
+        };
+        return store_label_pcrs(ctx, resource_name, image_pcr).await;
+    };
+    if platforms.is_empty() {
+        warn!(
+            "Image index {boot_image} had no manifest for any of the architectures {}",
+            registry::SUPPORTED_ARCHITECTURES.join(", ")
+        );
+        return Ok(NOT_COMMITTED_REASON_NO_ARCHITECTURE);
+    }
+
+    let mut labels = BTreeMap::new();
+    for (architecture, platform_ref) in platforms.iter() {
+        let arch = Some(architecture.as_str());
+        let label = label_pcrs(&ctx, &registry_client, platform_ref, &auth, arch, selection);
+        labels.insert(architecture.clone(), label.await?);
+    }
+    // Labels are only trusted if all architectures have them, so that the
+    // PCRs of an image are either all read or all computed
+    if !trust || labels.values().any(Option::is_none) {
+        if compare && labels.values().any(Option::is_none) {
+            warn_label_unusable(boot_image);
+            return Ok(NOT_COMMITTED_REASON_LABEL_UNUSABLE);
+        }
+        if !computable(boot_image, selection) {
+            return Ok(NOT_COMMITTED_REASON_UNCOMPUTABLE);
+        }
+        // Jobs for architectures without nodes would stay pending forever
+        let node_architectures = node_architectures(ctx.client.clone()).await?;
+        platforms.retain(|architecture, _| node_architectures.contains(architecture));
+        if platforms.is_empty() {
+            warn!(
+                "Image {boot_image} had no manifest for the architectures {node_architectures:?} \
+                 of the cluster's nodes"
+            );
+            return Ok(NOT_COMMITTED_REASON_NO_ARCHITECTURE);
+        }
+        let architectures: Vec<_> = platforms.keys().cloned().collect();
+        for (architecture, platform_ref) in platforms.iter() {
+            let image = platform_ref.whole();
+            let platform = Platform {
+                architecture,
+                image: &image,
+                architectures: &architectures,
+            };
+            let expected = labels.get(architecture).and_then(Option::as_deref);
+            compute_fresh_pcrs(
+                ctx.clone(),
+                resource_name,
+                boot_image,
+                Some(&platform),
+                selection,
+                pull_secrets,
+                expected,
+            )
+            .await?;
+        }
+        return Ok(computing);
+    }
+    let platforms = labels.into_iter().map(|(architecture, label)| {
+        let platform = label.map(|pcrs| PlatformPcrs {
+            pcrs,
+            firmware_profile_pcrs: profiles(),
+        });
+        (architecture, platform)
+    });
+    let image_pcr = ImagePcr {
+        first_seen: Utc::now(),
+        pcrs: vec![],
+        reference: boot_image.to_string(),
+        firmware_profile_pcrs: BTreeMap::new(),
+        source: ApprovedImageStatusPcrSource::Label,
+        compute_image: None,
+        staged: None,
+        platforms: platforms.collect(),
     };
-    image_pcrs.0.insert(resource_name.to_string(), image_pcr);
-    update_image_pcrs!(config_maps, image_pcrs_map, image_pcrs);
+    store_label_pcrs(ctx, resource_name, image_pcr).await
+}
+
+/// In compare mode, computed PCRs must not be committed unchecked
+fn warn_label_unusable(boot_image: &str) {
+    warn!(
+        "Image {boot_image} had no org.coreos.pcrs label with the selected PCRs to compare to, \
+         or firmware profiles were selected, whose PCRs a label cannot give"
+    );
+}
+
+/// Architectures of the cluster's nodes, which compute-pcrs jobs can be
+/// scheduled onto
+async fn node_architectures(client: Client) -> Result<BTreeSet<String>> {
+    let nodes: Api<Node> = Api::all(client);
+    let params = ListParams::default().labels(ARCH_LABEL_KEY);
+    let nodes = nodes.list(&params).await?;
+    let architectures = nodes.items.iter();
+    let architectures = architectures.filter_map(|n| n.labels().get(ARCH_LABEL_KEY).cloned());
+    Ok(architectures.collect())
+}
+
+/// Whether compute-pcrs can compute all selected PCRs
+fn computable(boot_image: &str, selection: &PcrSelection) -> bool {
+    let uncomputable = selection.uncomputable_pcrs();
+    if !uncomputable.is_empty() {
+        warn!(
+            "PCRs {uncomputable:?} of image {boot_image} cannot be computed. \
+             Provide them in the image's org.coreos.pcrs label instead."
+        );
+    }
+    uncomputable.is_empty()
+}
+
+/// Store PCRs that were read from an image's label
+async fn store_label_pcrs(
+    ctx: RvContextData,
+    resource_name: &str,
+    image_pcr: ImagePcr,
+) -> Result<&'static str> {
+    let store = ImagePcrsStore::new(ctx.client.clone());
+    let image_pcr = store.insert(resource_name, image_pcr).await?;
+    let images: Api<ApprovedImage> = Api::default_namespaced(ctx.client.clone());
+    update_status!(images, resource_name, pcrs_status(&image_pcr, None))?;
     trustee::update_reference_values(ctx)
         .await
         .map(|_| COMMITTED_REASON)
 }
 
 pub async fn disallow_image(ctx: RvContextData, resource_name: &str) -> Result<()> {
-    let config_maps: Api<ConfigMap> = Api::default_namespaced(ctx.client.clone());
-    let mut image_pcrs_map = config_maps.get(PCR_CONFIG_MAP).await?;
-    let mut image_pcrs = get_image_pcrs(image_pcrs_map.clone())?;
-    if image_pcrs.0.remove(resource_name).is_none() {
+    let store = ImagePcrsStore::new(ctx.client.clone());
+    if !store.remove(resource_name).await? {
         info!("Image {resource_name} was to be disallowed, but already was not allowed");
     }
-    update_image_pcrs!(config_maps, image_pcrs_map, image_pcrs);
     trustee::update_reference_values(ctx).await
 }
 
+/// Whether stored PCRs are of the image that an ApprovedImage currently
+/// approves
+fn approves(image: &ApprovedImage, image_pcr: &ImagePcr) -> bool {
+    let status = image.status.as_ref();
+    let resolved = status.and_then(|s| s.resolved_image.as_ref());
+    // A resolved tag is only recorded once its PCRs are stored
+    if image.spec.resolve_tag == Some(true) && resolved.is_none() {
+        return true;
+    }
+    image_pcr.reference == pinned_image(image)
+}
+
+/// Remove image PCRs whose ApprovedImage no longer exists or approves a
+/// different image, e.g. when it was deleted or changed while its PCRs were
+/// computed. Returns how many were removed.
+pub async fn sweep_orphans(ctx: RvContextData) -> Result<usize> {
+    let store = ImagePcrsStore::new(ctx.client.clone());
+    // Read before the images, so that PCRs stored in between are not
+    // mistaken for orphans
+    let image_pcrs = store.get().await?;
+    let images: Api<ApprovedImage> = Api::default_namespaced(ctx.client.clone());
+    let images = images.list(&Default::default()).await?.items;
+    let images: BTreeMap<_, _> = images
+        .iter()
+        .filter_map(|i| i.metadata.name.as_deref().map(|n| (n, i)))
+        .collect();
+    let mut removed = 0;
+    for (name, image_pcr) in image_pcrs.0.iter() {
+        let image = images.get(name.as_str());
+        if image.is_some_and(|i| approves(i, image_pcr)) {
+            continue;
+        }
+        let reference = &image_pcr.reference;
+        // Only remove the PCRs that were found to be orphaned, not any that
+        // were recomputed in the meantime
+        let removal = store.modify(name, |image_pcrs| {
+            let stale = image_pcrs.0.get(name);
+            let stale = stale.is_some_and(|p| p.reference == *reference);
+            stale && image_pcrs.0.remove(name).is_some()
+        });
+        if removal.await? {
+            warn!("Removed PCRs of image {reference}, which {name} no longer approves");
+            removed += 1;
+        }
+    }
+    if removed > 0 {
+        trustee::update_reference_values(ctx).await?;
+    }
+    Ok(removed)
+}
+
+pub async fn launch_orphan_sweep(ctx: RvContextData) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(ORPHAN_SWEEP_INTERVAL).await;
+            if let Err(e) = sweep_orphans(ctx.clone()).await {
+                warn!("Sweeping orphaned image PCRs failed: {e}");
+            }
+        }
+    });
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::test_utils::*;
-    use http::{Method, Request};
-    use k8s_openapi::api::batch::v1::JobStatus;
+    use http::{Method, Request, StatusCode};
+    use k8s_openapi::ByteString;
+    use k8s_openapi::api::batch::v1::{JobCondition, JobStatus};
+    use k8s_openapi::api::core::v1::{
+        ContainerState, ContainerStateTerminated, ContainerStateWaiting, ContainerStatus,
+        NodeAddress, NodeStatus, PodStatus,
+    };
     use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+    use kube::{api::ObjectList, client::Body};
     use trusted_cluster_operator_test_utils::mock_client::*;
+    use trusted_cluster_operator_test_utils::registry::*;
 
     #[tokio::test]
     async fn test_create_pcrs_cm_success() {
-        let clos = |client| create_pcrs_config_map(client, Default::default());
-        test_create_success::<_, _, ConfigMap>(clos).await;
+        let clos = async |req: Request<Body>, ctr: u32| {
+            assert_eq!(req.method(), Method::POST);
+            let shard = ctr.to_string();
+            assert_body_contains(req, &format!("\"{PCR_SHARD_LABEL}\":\"{shard}\"")).await;
+            Ok(serde_json::to_string(&ConfigMap::default()).unwrap())
+        };
+        count_check!(8, clos, |client| {
+            assert!(
+                create_pcrs_config_map(client, Default::default())
+                    .await
+                    .is_ok()
+            );
+        });
     }
 
     #[tokio::test]
     async fn test_create_pcrs_cm_exists() {
-        let clos = |client| create_pcrs_config_map(client, Default::default());
-        test_create_already_exists(clos).await;
+        let clos = async |req: Request<_>, _| match *req.method() {
+            Method::POST => Err(StatusCode::CONFLICT),
+            _ => panic!("unexpected API interaction: {req:?}"),
+        };
+        count_check!(8, clos, |client| {
+            assert!(
+                create_pcrs_config_map(client, Default::default())
+                    .await
+                    .is_ok()
+            );
+        });
+    }
+
+    #[test]
+    fn test_image_pcrs_shard_name() {
+        // Shards must not change, or stored PCRs would not be found
+        assert_eq!(image_pcrs_shard_name("cos"), pcrs_shard_name(4));
+        let shards: BTreeSet<_> = (0..100)
+            .map(|i| image_pcrs_shard_name(&format!("image-{i}")))
+            .collect();
+        assert_eq!(shards.len(), PCR_SHARDS);
     }
 
     #[tokio::test]
@@ -370,6 +1636,65 @@ mod tests {
         test_create_error(clos).await;
     }
 
+    #[tokio::test]
+    async fn test_migrate_unsharded_pcrs() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => {
+                assert!(req.uri().path().ends_with(&format!("/{PCR_CONFIG_MAP}")));
+                let legacy = pcrs_map(PCR_CONFIG_MAP.to_string(), &dummy_pcrs());
+                Ok(serde_json::to_string(&legacy).unwrap())
+            }
+            (1, &Method::GET) => {
+                let shard = image_pcrs_shard_name("cos");
+                Ok(serde_json::to_string(&pcrs_map(shard, &ImagePcrs::default())).unwrap())
+            }
+            (2, &Method::PUT) => {
+                assert_body_contains(req, "pcr0_val").await;
+                Ok(serde_json::to_string(&dummy_pcrs_map()).unwrap())
+            }
+            // all shards fetched for recomputation
+            (3, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_maps()).unwrap()),
+            (4, &Method::GET) | (5, &Method::PUT) => {
+                assert!(req.uri().path().contains(trustee::TRUSTEE_DATA_MAP));
+                Ok(serde_json::to_string(&dummy_trustee_map()).unwrap())
+            }
+            (6, &Method::PATCH) => Ok(serde_json::to_string(&dummy_cluster()).unwrap()),
+            (7, &Method::DELETE) => {
+                assert!(req.uri().path().ends_with(&format!("/{PCR_CONFIG_MAP}")));
+                Ok(serde_json::to_string(&ConfigMap::default()).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(8, clos, |client| {
+            let ctx = generate_rv_ctx(client);
+            migrate_unsharded_pcrs(ctx).await.unwrap();
+        });
+    }
+
+    #[tokio::test]
+    async fn test_migrate_unsharded_pcrs_none() {
+        let clos = async |req: Request<_>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => Err(StatusCode::NOT_FOUND),
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(1, clos, |client| {
+            let ctx = generate_rv_ctx(client);
+            migrate_unsharded_pcrs(ctx).await.unwrap();
+        });
+    }
+
+    #[tokio::test]
+    async fn test_create_compute_pcrs_network_policy_success() {
+        let clos = |client| create_compute_pcrs_network_policy(client, Default::default());
+        test_create_success::<_, _, NetworkPolicy>(clos).await;
+    }
+
+    #[tokio::test]
+    async fn test_create_compute_pcrs_network_policy_error() {
+        let clos = |client| create_compute_pcrs_network_policy(client, Default::default());
+        test_create_error(clos).await;
+    }
+
     fn dummy_job() -> Job {
         Job {
             metadata: ObjectMeta {
@@ -384,87 +1709,1614 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_failed_job_image() {
+        let mut job = dummy_job();
+        job.metadata.namespace = Some("trusted-execution-clusters".to_string());
+        let annotations = [(IMAGE_ANNOTATION_KEY.to_string(), "coreos".to_string())];
+        job.metadata.annotations = Some(BTreeMap::from(annotations));
+        job.metadata.deletion_timestamp = Some(Time(Utc::now()));
+        // Completed jobs are deleted once their PCRs are stored
+        assert!(failed_job_image(job.clone()).is_none());
+        job.status.as_mut().unwrap().completion_time = None;
+        let image = failed_job_image(job.clone()).unwrap();
+        assert_eq!(image.name, "coreos");
+        job.metadata.deletion_timestamp = None;
+        assert!(failed_job_image(job).is_none());
+    }
+
     #[tokio::test]
     async fn test_job_reconcile_success() {
         let clos = async |req: Request<_>, ctr| match (ctr, req.method()) {
             (0, &Method::DELETE) => Ok(serde_json::to_string(&Job::default()).unwrap()),
+            (1, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_maps()).unwrap()),
+            (2, &Method::GET) | (3, &Method::PUT) => {
+                assert!(req.uri().path().contains(trustee::TRUSTEE_DATA_MAP));
+                Ok(serde_json::to_string(&dummy_trustee_map()).unwrap())
+            }
+            (4, &Method::PATCH) => Ok(serde_json::to_string(&dummy_cluster()).unwrap()),
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(5, clos, |client| {
+            let ctx = Arc::new(generate_rv_ctx(client));
+            let job = Arc::new(dummy_job());
+            let result = job_reconcile(job, ctx).await.unwrap();
+            assert_eq!(result, Action::await_change());
+        });
+    }
+
+    fn dummy_hash(c: char) -> String {
+        c.to_string().repeat(64)
+    }
+
+    fn dummy_result() -> ComputePcrsResult {
+        let pcr = |id| Pcr {
+            id,
+            value: dummy_hash('a'),
+            parts: vec![],
+        };
+        ComputePcrsResult {
+            pcrs: vec![pcr(4), pcr(14)],
+            firmware_profile_pcrs: BTreeMap::new(),
+            tool_version: "0.1.0".to_string(),
+        }
+    }
+
+    fn dummy_request() -> ComputeRequest {
+        ComputeRequest {
+            reference: "image".to_string(),
+            pcrs: vec![4, 14],
+            firmware_profiles: vec![],
+            expected_pcrs: BTreeMap::new(),
+            architecture: None,
+            architectures: vec![],
+        }
+    }
+
+    /// Request for one architecture of a multi-architecture image
+    fn dummy_platform_request(architecture: &str) -> ComputeRequest {
+        ComputeRequest {
+            architecture: Some(architecture.to_string()),
+            architectures: vec!["amd64".to_string(), "arm64".to_string()],
+            ..dummy_request()
+        }
+    }
+
+    /// Shard with the amd64 PCRs of a multi-architecture image
+    fn dummy_platform_pcrs_map() -> ConfigMap {
+        let mut image_pcr = dummy_pcrs().0.remove("cos").unwrap();
+        let pcrs = std::mem::take(&mut image_pcr.pcrs);
+        image_pcr.reference = "image".to_string();
+        image_pcr.source = ApprovedImageStatusPcrSource::Computed;
+        image_pcr.compute_image = Some("compute-pcrs@sha256:abc".to_string());
+        let platform = PlatformPcrs {
+            pcrs,
+            firmware_profile_pcrs: BTreeMap::new(),
+        };
+        image_pcr.platforms = BTreeMap::from([
+            ("amd64".to_string(), Some(platform)),
+            ("arm64".to_string(), None),
+        ]);
+        let image_pcrs = ImagePcrs(BTreeMap::from([("image".to_string(), image_pcr)]));
+        pcrs_map(image_pcrs_shard_name("image"), &image_pcrs)
+    }
+
+    fn dummy_result_job(request: &ComputeRequest) -> Job {
+        let mut job = dummy_job();
+        job.metadata.annotations = Some(BTreeMap::from([
+            (IMAGE_ANNOTATION_KEY.to_string(), "image".to_string()),
+            (
+                REQUEST_ANNOTATION_KEY.to_string(),
+                serde_json::to_string(request).unwrap(),
+            ),
+        ]));
+        job.spec = Some(JobSpec {
+            template: PodTemplateSpec {
+                metadata: None,
+                spec: Some(PodSpec {
+                    containers: vec![Container {
+                        image: Some("compute-pcrs@sha256:abc".to_string()),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }),
+            },
+            ..Default::default()
+        });
+        job
+    }
+
+    fn succeeded_pods() -> ObjectList<Pod> {
+        let state = ContainerState {
+            terminated: Some(ContainerStateTerminated::default()),
+            ..Default::default()
+        };
+        let mut pods = dummy_pods("Succeeded", state, Duration::ZERO);
+        pods.items[0].metadata.name = Some("test-pod".to_string());
+        pods
+    }
+
+    /// Log of a job that reports the given result line
+    fn result_log(line: &str) -> String {
+        format!("Pulling image\n{line}\n")
+    }
+
+    #[tokio::test]
+    async fn test_job_reconcile_result() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => Ok(serde_json::to_string(&dummy_image(None)).unwrap()),
             (1, &Method::GET) => {
-                assert!(req.uri().path().contains(PCR_CONFIG_MAP));
+                assert!(req.uri().path().contains("pods"));
+                Ok(serde_json::to_string(&succeeded_pods()).unwrap())
+            }
+            (2, &Method::GET) => {
+                assert!(req.uri().path().ends_with("/pods/test-pod/log"));
+                Ok(result_log(&dummy_result().log_line().unwrap()))
+            }
+            (3, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_map()).unwrap()),
+            (4, &Method::PUT) => {
+                assert_body_contains(req, "compute-pcrs@sha256:abc").await;
                 Ok(serde_json::to_string(&dummy_pcrs_map()).unwrap())
             }
-            (2, &Method::GET) | (3, &Method::PUT) => {
+            (5, &Method::PATCH) => {
+                let body = req.into_body().collect_bytes().await.unwrap();
+                let body = String::from_utf8_lossy(&body);
+                assert!(body.contains(COMMITTED_REASON));
+                assert!(body.contains("0.1.0"));
+                Ok(serde_json::to_string(&dummy_image(None)).unwrap())
+            }
+            (6, &Method::DELETE) => Ok(serde_json::to_string(&Job::default()).unwrap()),
+            (7, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_maps()).unwrap()),
+            (8, &Method::GET) | (9, &Method::PUT) => {
                 assert!(req.uri().path().contains(trustee::TRUSTEE_DATA_MAP));
                 Ok(serde_json::to_string(&dummy_trustee_map()).unwrap())
             }
+            (10, &Method::PATCH) => Ok(serde_json::to_string(&dummy_cluster()).unwrap()),
             _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
         };
-        count_check!(4, clos, |client| {
+        count_check!(11, clos, |client| {
             let ctx = Arc::new(generate_rv_ctx(client));
-            let job = Arc::new(dummy_job());
+            let job = Arc::new(dummy_result_job(&dummy_request()));
             let result = job_reconcile(job, ctx).await.unwrap();
             assert_eq!(result, Action::await_change());
         });
     }
 
     #[tokio::test]
-    async fn test_job_reconcile_begun_deletion() {
-        let clos = async |req: Request<_>, _| panic!("unexpected API interaction: {req:?}");
-        count_check!(0, clos, |client| {
+    async fn test_job_reconcile_platform_pending() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => Ok(serde_json::to_string(&dummy_image(None)).unwrap()),
+            (1, &Method::GET) => Ok(serde_json::to_string(&succeeded_pods()).unwrap()),
+            (2, &Method::GET) => Ok(result_log(&dummy_result().log_line().unwrap())),
+            (3, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_map()).unwrap()),
+            (4, &Method::PUT) => {
+                let body = req.into_body().collect_bytes().await.unwrap();
+                let body = String::from_utf8_lossy(&body);
+                assert!(body.contains(r#"\"arm64\":{\"pcrs\""#));
+                assert!(body.contains(r#"\"amd64\":null"#));
+                Ok(serde_json::to_string(&dummy_pcrs_map()).unwrap())
+            }
+            // The image is not committed until all architectures are stored
+            (5, &Method::DELETE) => Ok(serde_json::to_string(&Job::default()).unwrap()),
+            (6, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_maps()).unwrap()),
+            (7, &Method::GET) | (8, &Method::PUT) => {
+                Ok(serde_json::to_string(&dummy_trustee_map()).unwrap())
+            }
+            (9, &Method::PATCH) => Ok(serde_json::to_string(&dummy_cluster()).unwrap()),
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(10, clos, |client| {
             let ctx = Arc::new(generate_rv_ctx(client));
-            let mut job = dummy_job();
-            let status = job.status.as_mut().unwrap();
-            status.completion_time = None;
-            let result = job_reconcile(Arc::new(job), ctx).await.unwrap();
-            assert_eq!(result, Action::requeue(Duration::from_secs(300)));
+            let job = Arc::new(dummy_result_job(&dummy_platform_request("arm64")));
+            assert!(job_reconcile(job, ctx).await.is_ok());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_job_reconcile_platform_complete() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => Ok(serde_json::to_string(&dummy_image(None)).unwrap()),
+            (1, &Method::GET) => Ok(serde_json::to_string(&succeeded_pods()).unwrap()),
+            (2, &Method::GET) => Ok(result_log(&dummy_result().log_line().unwrap())),
+            (3, &Method::GET) => Ok(serde_json::to_string(&dummy_platform_pcrs_map()).unwrap()),
+            (4, &Method::PUT) => {
+                let body = req.into_body().collect_bytes().await.unwrap();
+                let body = String::from_utf8_lossy(&body);
+                assert!(body.contains("pcr0_val"));
+                assert!(!body.contains("null"));
+                Ok(serde_json::to_string(&dummy_platform_pcrs_map()).unwrap())
+            }
+            (5, &Method::PATCH) => {
+                let body = req.into_body().collect_bytes().await.unwrap();
+                let body = String::from_utf8_lossy(&body);
+                assert!(body.contains(COMMITTED_REASON));
+                assert!(body.contains("platformPcrs"));
+                Ok(serde_json::to_string(&dummy_image(None)).unwrap())
+            }
+            (6, &Method::DELETE) => Ok(serde_json::to_string(&Job::default()).unwrap()),
+            (7, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_maps()).unwrap()),
+            (8, &Method::GET) | (9, &Method::PUT) => {
+                Ok(serde_json::to_string(&dummy_trustee_map()).unwrap())
+            }
+            (10, &Method::PATCH) => Ok(serde_json::to_string(&dummy_cluster()).unwrap()),
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(11, clos, |client| {
+            let ctx = Arc::new(generate_rv_ctx(client));
+            let job = Arc::new(dummy_result_job(&dummy_platform_request("arm64")));
+            assert!(job_reconcile(job, ctx).await.is_ok());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_job_reconcile_invalid_result() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => Ok(serde_json::to_string(&dummy_image(None)).unwrap()),
+            (1, &Method::GET) => Ok(serde_json::to_string(&succeeded_pods()).unwrap()),
+            (2, &Method::GET) => Ok(result_log("compute-pcrs-result: garbage")),
+            (3, &Method::PATCH) => {
+                assert_body_contains(req, NOT_COMMITTED_REASON_FAILED).await;
+                Ok(serde_json::to_string(&dummy_image(None)).unwrap())
+            }
+            // No PCRs of other architectures to discard
+            (4, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_map()).unwrap()),
+            (5, &Method::DELETE) => Ok(serde_json::to_string(&Job::default()).unwrap()),
+            (6, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_maps()).unwrap()),
+            (7, &Method::GET) | (8, &Method::PUT) => {
+                Ok(serde_json::to_string(&dummy_trustee_map()).unwrap())
+            }
+            (9, &Method::PATCH) => Ok(serde_json::to_string(&dummy_cluster()).unwrap()),
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(10, clos, |client| {
+            let ctx = Arc::new(generate_rv_ctx(client));
+            let job = Arc::new(dummy_result_job(&dummy_request()));
+            assert!(job_reconcile(job, ctx).await.is_ok());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_job_reconcile_label_mismatch() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => Ok(serde_json::to_string(&dummy_image(None)).unwrap()),
+            (1, &Method::GET) => Ok(serde_json::to_string(&succeeded_pods()).unwrap()),
+            (2, &Method::GET) => Ok(result_log(&dummy_result().log_line().unwrap())),
+            (3, &Method::PATCH) => {
+                assert_body_contains(req, NOT_COMMITTED_REASON_MISMATCH).await;
+                Ok(serde_json::to_string(&dummy_image(None)).unwrap())
+            }
+            // No PCRs of other architectures to discard
+            (4, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_map()).unwrap()),
+            (5, &Method::DELETE) => Ok(serde_json::to_string(&Job::default()).unwrap()),
+            (6, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_maps()).unwrap()),
+            (7, &Method::GET) | (8, &Method::PUT) => {
+                Ok(serde_json::to_string(&dummy_trustee_map()).unwrap())
+            }
+            (9, &Method::PATCH) => Ok(serde_json::to_string(&dummy_cluster()).unwrap()),
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(10, clos, |client| {
+            let ctx = Arc::new(generate_rv_ctx(client));
+            let mut request = dummy_request();
+            request.expected_pcrs = BTreeMap::from([(4, dummy_hash('b'))]);
+            let job = Arc::new(dummy_result_job(&request));
+            assert!(job_reconcile(job, ctx).await.is_ok());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_job_reconcile_image_changed() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => {
+                let mut image = dummy_image(None);
+                image.spec.image = "other".to_string();
+                Ok(serde_json::to_string(&image).unwrap())
+            }
+            (1, &Method::DELETE) => Ok(serde_json::to_string(&Job::default()).unwrap()),
+            (2, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_maps()).unwrap()),
+            (3, &Method::GET) | (4, &Method::PUT) => {
+                Ok(serde_json::to_string(&dummy_trustee_map()).unwrap())
+            }
+            (5, &Method::PATCH) => Ok(serde_json::to_string(&dummy_cluster()).unwrap()),
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(6, clos, |client| {
+            let ctx = Arc::new(generate_rv_ctx(client));
+            let job = Arc::new(dummy_result_job(&dummy_request()));
+            assert!(job_reconcile(job, ctx).await.is_ok());
         });
     }
 
     #[test]
-    fn test_get_job_name_trailing_dash() {
-        let name = get_job_name("quay.io/some_ref:some-tag-").unwrap();
-        assert_eq!(name, "compute-pcrs-105a7802d8-quay-io-some-ref-some-tag");
+    fn test_validate_result() {
+        let request = dummy_request();
+        assert!(validate_result(&request, &dummy_result()).is_ok());
+        let mut result = dummy_result();
+        result.pcrs.pop();
+        assert!(validate_result(&request, &result).is_err());
+        let mut result = dummy_result();
+        result.pcrs[0].value = "not a hash".to_string();
+        assert!(validate_result(&request, &result).is_err());
     }
 
     #[test]
-    fn test_get_job_name_sha() {
-        let name = get_job_name("quay.io/some-ref@sha256:e71dad00aa0e3d70540e726a0c66407e3004d96e045ab6c253186e327a2419e5").unwrap();
-        assert_eq!(
-            name,
-            "compute-pcrs-6c57e93939-quay-io-some-ref-sha256-e71dad00aa0e3d7"
-        );
+    fn test_validate_result_firmware_profiles() {
+        let mut request = dummy_request();
+        request.firmware_profiles = vec!["custom-kek".to_string()];
+        let mut result = dummy_result();
+        assert!(validate_result(&request, &result).is_err());
+        let profile_pcrs = result.pcrs.split_off(1);
+        result
+            .firmware_profile_pcrs
+            .insert("custom-kek".to_string(), profile_pcrs);
+        assert!(validate_result(&request, &result).is_ok());
     }
 
-    #[tokio::test]
-    async fn test_compute_fresh_pcrs_success() {
-        let clos = |client| compute_fresh_pcrs(generate_rv_ctx(client), "image", "registry");
-        test_create_success::<_, _, Job>(clos).await;
+    #[test]
+    fn test_result_from_log() {
+        // More firmware profiles than a termination message could hold
+        let mut result = dummy_result();
+        for i in 0..200u8 {
+            let digest = hash(MessageDigest::sha256(), &[i]).unwrap();
+            let pcrs = vec![Pcr {
+                id: 7,
+                value: digest.iter().map(|b| format!("{b:02x}")).collect(),
+                parts: vec![],
+            }];
+            result
+                .firmware_profile_pcrs
+                .insert(format!("profile-{i}"), pcrs);
+        }
+        let line = result.log_line().unwrap();
+        assert!(line.len() > 4096);
+        let decoded = ComputePcrsResult::from_log(&result_log(&line)).unwrap();
+        assert_eq!(decoded.firmware_profile_pcrs.len(), 200);
+        assert!(ComputePcrsResult::from_log("Pulling image\n").is_err());
+    }
+
+    #[test]
+    fn test_pcr_mismatches() {
+        let expected = BTreeMap::from([(4, dummy_hash('a')), (7, dummy_hash('b'))]);
+        let mismatches = pcr_mismatches(&expected, &dummy_result().pcrs);
+        assert_eq!(mismatches.len(), 1);
+        assert!(mismatches[0].contains("PCR7"));
+        assert!(mismatches[0].contains("computed nothing"));
     }
 
     #[tokio::test]
-    async fn test_compute_fresh_pcrs_error() {
-        let clos = |client| compute_fresh_pcrs(generate_rv_ctx(client), "image", "registry");
-        test_create_error(clos).await;
+    async fn test_job_reconcile_begun_deletion() {
+        let clos = async |req: Request<_>, _| panic!("unexpected API interaction: {req:?}");
+        count_check!(0, clos, |client| {
+            let ctx = Arc::new(generate_rv_ctx(client));
+            let mut job = dummy_job();
+            let status = job.status.as_mut().unwrap();
+            status.completion_time = None;
+            let result = job_reconcile(Arc::new(job), ctx).await.unwrap();
+            assert_eq!(result, Action::requeue(Duration::from_secs(300)));
+        });
     }
 
-    // handle_new_image is an inherently online function and not tested here.
+    fn dummy_failed_job(failed_for: Duration) -> Job {
+        let mut job = dummy_job();
+        let annotations = BTreeMap::from([(IMAGE_ANNOTATION_KEY.to_string(), "image".to_string())]);
+        job.metadata.annotations = Some(annotations);
+        let status = job.status.as_mut().unwrap();
+        status.completion_time = None;
+        status.conditions = Some(vec![JobCondition {
+            type_: "Failed".to_string(),
+            status: "True".to_string(),
+            message: Some("Job has reached the specified backoff limit".to_string()),
+            last_transition_time: Some(Time(Utc::now() - failed_for)),
+            ..Default::default()
+        }]);
+        job
+    }
+
+    fn dummy_pods(phase: &str, state: ContainerState, age: Duration) -> ObjectList<Pod> {
+        let pod = Pod {
+            metadata: ObjectMeta {
+                creation_timestamp: Some(Time(Utc::now() - age)),
+                ..Default::default()
+            },
+            status: Some(PodStatus {
+                phase: Some(phase.to_string()),
+                container_statuses: Some(vec![ContainerStatus {
+                    state: Some(state),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        ObjectList {
+            types: Default::default(),
+            metadata: Default::default(),
+            items: vec![pod],
+        }
+    }
 
     #[tokio::test]
-    async fn test_disallow_image() {
-        let clos = async |req: Request<_>, ctr| match (ctr, req.method()) {
-            // fetched & updated for removal, then fetched for recomputation
-            (0, &Method::GET) | (1, &Method::PUT) | (2, &Method::GET) => {
-                assert!(req.uri().path().contains(PCR_CONFIG_MAP));
-                Ok(serde_json::to_string(&dummy_pcrs_map()).unwrap())
+    async fn test_job_reconcile_failed() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => {
+                assert!(req.uri().path().contains("pods"));
+                let state = ContainerState {
+                    terminated: Some(ContainerStateTerminated {
+                        message: Some("no kernel found".to_string()),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                };
+                let pods = dummy_pods("Failed", state, Duration::from_secs(7200));
+                Ok(serde_json::to_string(&pods).unwrap())
             }
-            (3, &Method::GET) | (4, &Method::PUT) => {
-                assert!(req.uri().path().contains(trustee::TRUSTEE_DATA_MAP));
-                Ok(serde_json::to_string(&dummy_trustee_map()).unwrap())
+            (1, &Method::GET) => Ok(serde_json::to_string(&dummy_image(None)).unwrap()),
+            (2, &Method::PATCH) => {
+                assert_body_contains(req, "no kernel found").await;
+                Ok(serde_json::to_string(&dummy_image(None)).unwrap())
             }
+            (3, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_map()).unwrap()),
+            (4, &Method::DELETE) => Ok(serde_json::to_string(&Job::default()).unwrap()),
             _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
         };
         count_check!(5, clos, |client| {
-            let ctx = generate_rv_ctx(client);
+            let ctx = Arc::new(generate_rv_ctx(client));
+            let job = dummy_failed_job(Duration::from_secs(7200));
+            let result = job_reconcile(Arc::new(job), ctx).await.unwrap();
+            assert_eq!(result, Action::await_change());
+        });
+    }
+
+    /// Pending job that mounts its image as an image volume
+    fn dummy_image_volume_job() -> Job {
+        let mut job = dummy_job();
+        job.status.as_mut().unwrap().completion_time = None;
+        let settings = PodSettings::default();
+        let pod_spec = build_compute_pcrs_pod_spec("image", "image", "", &settings, &[], &[], true);
+        job.spec = Some(JobSpec {
+            template: PodTemplateSpec {
+                metadata: None,
+                spec: Some(pod_spec),
+            },
+            ..Default::default()
+        });
+        job
+    }
+
+    #[test]
+    fn test_is_image_volume_error() {
+        let message = "MountVolume.SetUp failed for volume \"image\": no volume plugin matched";
+        assert!(is_image_volume_error(message));
+        assert!(is_image_volume_error("failed to mount Image Volume"));
+        assert!(!is_image_volume_error(
+            "MountVolume.SetUp failed for volume \"efivars\""
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_job_reconcile_image_volume_failure() {
+        let clos = async |req: Request<_>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => {
+                assert!(req.uri().path().contains("pods"));
+                let state = ContainerState {
+                    waiting: Some(ContainerStateWaiting {
+                        reason: Some("ContainerCreating".to_string()),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                };
+                let mut pods = dummy_pods("Pending", state, Duration::from_secs(60));
+                pods.items[0].metadata.name = Some("test-pod".to_string());
+                Ok(serde_json::to_string(&pods).unwrap())
+            }
+            (1, &Method::GET) => {
+                assert!(req.uri().path().contains("events"));
+                let query = req.uri().query().unwrap_or_default();
+                assert!(query.contains("test-pod"));
+                let event = CoreEvent {
+                    reason: Some("FailedMount".to_string()),
+                    message: Some(
+                        "MountVolume.SetUp failed for volume \"image\": unsupported".to_string(),
+                    ),
+                    ..Default::default()
+                };
+                let events = ObjectList {
+                    types: Default::default(),
+                    metadata: Default::default(),
+                    items: vec![event],
+                };
+                Ok(serde_json::to_string(&events).unwrap())
+            }
+            (2, &Method::DELETE) => Ok(serde_json::to_string(&Job::default()).unwrap()),
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(3, clos, |client| {
+            let ctx = Arc::new(generate_rv_ctx(client));
+            let image_volumes = ctx.image_volumes.clone();
+            let job = Arc::new(dummy_image_volume_job());
+            let result = job_reconcile(job, ctx).await.unwrap();
+            assert_eq!(result, Action::await_change());
+            assert!(!image_volumes.load(Ordering::Acquire));
+        });
+    }
+
+    #[tokio::test]
+    async fn test_job_reconcile_image_volume_pending() {
+        let clos = async |req: Request<_>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => {
+                let state = ContainerState {
+                    waiting: Some(ContainerStateWaiting {
+                        reason: Some("ContainerCreating".to_string()),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                };
+                let pods = dummy_pods("Pending", state, Duration::from_secs(60));
+                Ok(serde_json::to_string(&pods).unwrap())
+            }
+            (1, &Method::GET) => {
+                let events: ObjectList<CoreEvent> = ObjectList {
+                    types: Default::default(),
+                    metadata: Default::default(),
+                    items: vec![],
+                };
+                Ok(serde_json::to_string(&events).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(2, clos, |client| {
+            let ctx = Arc::new(generate_rv_ctx(client));
+            let image_volumes = ctx.image_volumes.clone();
+            let job = Arc::new(dummy_image_volume_job());
+            let result = job_reconcile(job, ctx).await.unwrap();
+            assert_eq!(result, Action::requeue(Duration::from_secs(300)));
+            assert!(image_volumes.load(Ordering::Acquire));
+        });
+    }
+
+    #[tokio::test]
+    async fn test_job_reconcile_failed_retained() {
+        let clos = async |req: Request<_>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => {
+                let pods = ObjectList::<Pod> {
+                    types: Default::default(),
+                    metadata: Default::default(),
+                    items: vec![],
+                };
+                Ok(serde_json::to_string(&pods).unwrap())
+            }
+            (1, &Method::GET) => {
+                let image = dummy_image(Some(NOT_COMMITTED_REASON_FAILED));
+                Ok(serde_json::to_string(&image).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(2, clos, |client| {
+            let ctx = Arc::new(generate_rv_ctx(client));
+            let job = dummy_failed_job(Duration::ZERO);
+            let result = job_reconcile(Arc::new(job), ctx).await.unwrap();
+            assert_ne!(result, Action::await_change());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_job_reconcile_failed_platform() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => {
+                let state = ContainerState {
+                    terminated: Some(ContainerStateTerminated::default()),
+                    ..Default::default()
+                };
+                let pods = dummy_pods("Failed", state, Duration::ZERO);
+                Ok(serde_json::to_string(&pods).unwrap())
+            }
+            (1, &Method::GET) => Ok(serde_json::to_string(&dummy_image(None)).unwrap()),
+            (2, &Method::PATCH) => Ok(serde_json::to_string(&dummy_image(None)).unwrap()),
+            (3, &Method::GET) => Ok(serde_json::to_string(&dummy_platform_pcrs_map()).unwrap()),
+            // Stored architectures are not trusted once another one failed
+            (4, &Method::PUT) => {
+                let body = req.into_body().collect_bytes().await.unwrap();
+                assert!(!String::from_utf8_lossy(&body).contains("amd64"));
+                Ok(serde_json::to_string(&dummy_pcrs_map()).unwrap())
+            }
+            (5, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_maps()).unwrap()),
+            (6, &Method::GET) | (7, &Method::PUT) => {
+                Ok(serde_json::to_string(&dummy_trustee_map()).unwrap())
+            }
+            (8, &Method::PATCH) => Ok(serde_json::to_string(&dummy_cluster()).unwrap()),
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(9, clos, |client| {
+            let ctx = Arc::new(generate_rv_ctx(client));
+            let job = dummy_failed_job(Duration::ZERO);
+            assert!(job_reconcile(Arc::new(job), ctx).await.is_ok());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_job_reconcile_platform_after_failure() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => {
+                let image = dummy_image(Some(NOT_COMMITTED_REASON_FAILED));
+                Ok(serde_json::to_string(&image).unwrap())
+            }
+            // No PCRs are stored
+            (1, &Method::DELETE) => Ok(serde_json::to_string(&Job::default()).unwrap()),
+            (2, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_maps()).unwrap()),
+            (3, &Method::GET) | (4, &Method::PUT) => {
+                Ok(serde_json::to_string(&dummy_trustee_map()).unwrap())
+            }
+            (5, &Method::PATCH) => Ok(serde_json::to_string(&dummy_cluster()).unwrap()),
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(6, clos, |client| {
+            let ctx = Arc::new(generate_rv_ctx(client));
+            let job = Arc::new(dummy_result_job(&dummy_platform_request("amd64")));
+            assert!(job_reconcile(job, ctx).await.is_ok());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_job_reconcile_pending() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => {
+                let state = ContainerState {
+                    waiting: Some(ContainerStateWaiting {
+                        reason: Some("ErrImagePull".to_string()),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                };
+                let pods = dummy_pods("Pending", state, Duration::from_secs(1200));
+                Ok(serde_json::to_string(&pods).unwrap())
+            }
+            (1, &Method::GET) => Ok(serde_json::to_string(&dummy_image(None)).unwrap()),
+            (2, &Method::PATCH) => {
+                assert_body_contains(req, "ErrImagePull").await;
+                Ok(serde_json::to_string(&dummy_image(None)).unwrap())
+            }
+            (3, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_map()).unwrap()),
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(4, clos, |client| {
+            let ctx = Arc::new(generate_rv_ctx(client));
+            let mut job = dummy_failed_job(Duration::ZERO);
+            job.metadata.creation_timestamp = Some(Time(Utc::now() - Duration::from_secs(1200)));
+            job.status.as_mut().unwrap().conditions = None;
+            let result = job_reconcile(Arc::new(job), ctx).await.unwrap();
+            assert_ne!(result, Action::await_change());
+        });
+    }
+
+    #[test]
+    fn test_get_job_name_trailing_dash() {
+        let name = get_job_name("quay.io/some_ref:some-tag-", None).unwrap();
+        assert_eq!(name, "compute-pcrs-105a7802d8-quay-io-some-ref-some-tag");
+    }
+
+    #[test]
+    fn test_get_job_name_sha() {
+        let name = get_job_name("quay.io/some-ref@sha256:e71dad00aa0e3d70540e726a0c66407e3004d96e045ab6c253186e327a2419e5", None).unwrap();
+        assert_eq!(
+            name,
+            "compute-pcrs-6c57e93939-quay-io-some-ref-sha256-e71dad00aa0e3d7"
+        );
+    }
+
+    #[test]
+    fn test_get_job_name_architecture() {
+        let image = "quay.io/some-ref@sha256:e71dad00aa0e3d70540e726a0c66407e3004d96e045ab6c253186e327a2419e5";
+        let amd64 = get_job_name(image, Some("amd64")).unwrap();
+        let arm64 = get_job_name(image, Some("arm64")).unwrap();
+        assert!(amd64.starts_with("compute-pcrs-amd64-6c57e93939-"));
+        assert_ne!(amd64, arm64);
+        assert!(arm64.len() <= 63);
+    }
+
+    #[test]
+    fn test_build_compute_pcrs_pod_spec_settings() {
+        let settings = PodSettings {
+            tolerations: Some(vec![Default::default()]),
+            ..Default::default()
+        };
+        let pod_spec =
+            build_compute_pcrs_pod_spec("name", "image", "compute", &settings, &[4], &[], true);
+        assert_eq!(pod_spec.tolerations.as_ref().map(|t| t.len()), Some(1));
+        assert_eq!(pod_spec.restart_policy, Some("Never".to_string()));
+        assert_hardened(&pod_spec);
+    }
+
+    #[test]
+    fn test_build_compute_pcrs_pod_spec_selection() {
+        let settings = Default::default();
+        let pod_spec =
+            build_compute_pcrs_pod_spec("name", "image", "compute", &settings, &[4, 7], &[], true);
+        let cmd = pod_spec.containers[0].command.as_ref().unwrap();
+        let pos = cmd.iter().position(|a| a == "--pcrs").unwrap();
+        assert_eq!(cmd[pos + 1], "4,7");
+    }
+
+    #[test]
+    fn test_select_pcrs() {
+        let pcrs = dummy_pcrs().0.remove("cos").unwrap().pcrs;
+        let selected = select_pcrs(pcrs, &[1]).unwrap();
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].value, "pcr1_val");
+    }
+
+    #[test]
+    fn test_select_pcrs_missing() {
+        let pcrs = dummy_pcrs().0.remove("cos").unwrap().pcrs;
+        assert!(select_pcrs(pcrs, &[1, 4]).is_none());
+    }
+
+    fn dummy_firmware_profile() -> ConfigMap {
+        let data = |d: &str| ByteString(d.as_bytes().to_vec());
+        ConfigMap {
+            metadata: ObjectMeta {
+                name: Some("custom-kek".to_string()),
+                labels: Some(BTreeMap::from([(
+                    FIRMWARE_PROFILE_LABEL.to_string(),
+                    String::new(),
+                )])),
+                ..Default::default()
+            },
+            binary_data: Some(BTreeMap::from([
+                ("efivars.db-d719b2cb".to_string(), data("db")),
+                ("mokvars.MokListRT".to_string(), data("mok")),
+                ("unrelated".to_string(), data("")),
+            ])),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_build_compute_pcrs_pod_spec_firmware_profile() {
+        let settings = Default::default();
+        let profiles = [dummy_firmware_profile()];
+        let pod_spec = build_compute_pcrs_pod_spec(
+            "name",
+            "image",
+            "compute",
+            &settings,
+            &[7],
+            &profiles,
+            true,
+        );
+        let cmd = pod_spec.containers[0].command.as_ref().unwrap();
+        assert!(cmd.contains(&"--firmware-profiles".to_string()));
+        let volumes = pod_spec.volumes.as_ref().unwrap();
+        let volume = volumes.iter().find(|v| v.name == "firmware-profile-0");
+        let items = volume.unwrap().config_map.as_ref().unwrap().items.as_ref();
+        let paths: Vec<_> = items.unwrap().iter().map(|i| i.path.as_str()).collect();
+        assert_eq!(paths, vec!["efivars/db-d719b2cb", "mokvars/MokListRT"]);
+        let mounts = pod_spec.containers[0].volume_mounts.as_ref().unwrap();
+        let mount = mounts.iter().find(|m| m.name == "firmware-profile-0");
+        assert_eq!(mount.unwrap().mount_path, "/firmware-profiles/custom-kek");
+    }
+
+    #[test]
+    fn test_build_compute_pcrs_pod_spec_pull() {
+        let settings = Default::default();
+        let pod_spec =
+            build_compute_pcrs_pod_spec("name", "image", "compute", &settings, &[4], &[], false);
+        let cmd = pod_spec.containers[0].command.as_ref().unwrap();
+        assert!(cmd.contains(&"--pull".to_string()));
+        assert!(!cmd.contains(&"--kernels".to_string()));
+        let volumes = pod_spec.volumes.as_ref().unwrap();
+        assert!(volumes.iter().all(|v| v.image.is_none()));
+        assert_hardened(&pod_spec);
+    }
+
+    #[test]
+    fn test_matches_selection() {
+        let image_pcrs = dummy_pcrs();
+        let image_pcr = &image_pcrs.0["cos"];
+        let mut selection = PcrSelection {
+            pcrs: vec![0, 1],
+            firmware_profiles: vec![],
+        };
+        assert!(matches_selection(image_pcr, &selection));
+        selection.pcrs = vec![0];
+        assert!(!matches_selection(image_pcr, &selection));
+        selection.pcrs = vec![0, 1];
+        selection.firmware_profiles = vec!["custom-kek".to_string()];
+        assert!(!matches_selection(image_pcr, &selection));
+    }
+
+    #[test]
+    fn test_matches_selection_platforms() {
+        let mut image_pcr = dummy_pcrs().0.remove("cos").unwrap();
+        let platform = || PlatformPcrs {
+            pcrs: dummy_pcrs().0.remove("cos").unwrap().pcrs,
+            firmware_profile_pcrs: BTreeMap::new(),
+        };
+        image_pcr.pcrs = vec![];
+        image_pcr.platforms = BTreeMap::from([
+            ("amd64".to_string(), Some(platform())),
+            ("arm64".to_string(), None),
+        ]);
+        let selection = PcrSelection {
+            pcrs: vec![0, 1],
+            firmware_profiles: vec![],
+        };
+        // PCRs of all architectures must be stored
+        assert!(!matches_selection(&image_pcr, &selection));
+        image_pcr
+            .platforms
+            .insert("arm64".to_string(), Some(platform()));
+        assert!(matches_selection(&image_pcr, &selection));
+    }
+
+    #[tokio::test]
+    async fn test_compute_fresh_pcrs_success() {
+        let selection = PcrSelection::default();
+        let clos = |client| {
+            let ctx = generate_rv_ctx(client);
+            compute_fresh_pcrs(ctx, "image", "registry", None, &selection, &[], None)
+        };
+        test_create_success::<_, _, Job>(clos).await;
+    }
+
+    #[tokio::test]
+    async fn test_compute_fresh_pcrs_platform() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::POST) => {
+                let body = req.into_body().collect_bytes().await.unwrap();
+                let job: Job = serde_json::from_slice(&body).unwrap();
+                assert!(job.metadata.name.unwrap().contains("-arm64-"));
+                let annotations = job.metadata.annotations.unwrap();
+                let request = &annotations[REQUEST_ANNOTATION_KEY];
+                let request: ComputeRequest = serde_json::from_str(request).unwrap();
+                assert_eq!(request.reference, "registry");
+                assert_eq!(request.architecture.as_deref(), Some("arm64"));
+                assert_eq!(request.architectures, ["amd64", "arm64"]);
+                let pod_spec = job.spec.unwrap().template.spec.unwrap();
+                let node_selector = pod_spec.node_selector.unwrap();
+                assert_eq!(node_selector[ARCH_LABEL_KEY], "arm64");
+                let volumes = pod_spec.volumes.unwrap();
+                let image = volumes[0].image.as_ref().unwrap();
+                assert_eq!(image.reference.as_deref(), Some("registry@sha256:arm64"));
+                Ok(serde_json::to_string(&Job::default()).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(1, clos, |client| {
+            let ctx = generate_rv_ctx(client);
+            let selection = PcrSelection::default();
+            let architectures = ["amd64".to_string(), "arm64".to_string()];
+            let platform = Platform {
+                architecture: "arm64",
+                image: "registry@sha256:arm64",
+                architectures: &architectures,
+            };
+            let platform = Some(&platform);
+            let result =
+                compute_fresh_pcrs(ctx, "image", "registry", platform, &selection, &[], None);
+            assert!(result.await.is_ok());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_compute_fresh_pcrs_firmware_profile() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => {
+                assert!(req.uri().path().ends_with("/custom-kek"));
+                Ok(serde_json::to_string(&dummy_firmware_profile()).unwrap())
+            }
+            (1, &Method::POST) => {
+                assert_body_contains(req, "firmware-profile-0").await;
+                Ok(serde_json::to_string(&Job::default()).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(2, clos, |client| {
+            let selection = PcrSelection {
+                pcrs: vec![7],
+                firmware_profiles: vec!["custom-kek".to_string()],
+            };
+            let ctx = generate_rv_ctx(client);
+            let result =
+                compute_fresh_pcrs(ctx, "image", "registry", None, &selection, &[], None).await;
+            assert!(result.is_ok());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_compute_fresh_pcrs_not_a_profile() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => {
+                let mut profile = dummy_firmware_profile();
+                profile.metadata.labels = None;
+                Ok(serde_json::to_string(&profile).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(1, clos, |client| {
+            let selection = PcrSelection {
+                pcrs: vec![7],
+                firmware_profiles: vec!["custom-kek".to_string()],
+            };
+            let ctx = generate_rv_ctx(client);
+            let result =
+                compute_fresh_pcrs(ctx, "image", "registry", None, &selection, &[], None).await;
+            assert!(
+                result
+                    .unwrap_err()
+                    .to_string()
+                    .contains("not a firmware profile")
+            );
+        });
+    }
+
+    #[tokio::test]
+    async fn test_compute_fresh_pcrs_pull_secrets() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::POST) => {
+                let body = req.into_body().collect_bytes().await.unwrap();
+                let job: Job = serde_json::from_slice(&body).unwrap();
+                let pod_spec = job.spec.unwrap().template.spec.unwrap();
+                let secrets = pod_spec.image_pull_secrets.unwrap();
+                assert_eq!(secrets[0].name, "pull-secret");
+                Ok(serde_json::to_string(&Job::default()).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(1, clos, |client| {
+            let ctx = generate_rv_ctx(client);
+            let selection = PcrSelection::default();
+            let secrets = ["pull-secret".to_string()];
+            let result =
+                compute_fresh_pcrs(ctx, "image", "registry", None, &selection, &secrets, None);
+            assert!(result.await.is_ok());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_compute_fresh_pcrs_pull() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::POST) => {
+                let body = req.into_body().collect_bytes().await.unwrap();
+                let job: Job = serde_json::from_slice(&body).unwrap();
+                let pod_spec = job.spec.unwrap().template.spec.unwrap();
+                assert!(pod_spec.image_pull_secrets.is_none());
+                let volumes = pod_spec.volumes.unwrap();
+                let volume = volumes.iter().find(|v| v.name == "pull-secret-0");
+                let secret = volume.unwrap().secret.as_ref().unwrap();
+                assert_eq!(secret.secret_name.as_deref(), Some("pull-secret"));
+                let cmd = pod_spec.containers[0].command.clone().unwrap();
+                let pos = cmd.iter().position(|a| a == "--pull-secrets").unwrap();
+                assert_eq!(cmd[pos + 1], PULL_SECRETS_DIR);
+                let pos = cmd
+                    .iter()
+                    .position(|a| a == "--insecure-registries")
+                    .unwrap();
+                assert_eq!(cmd[pos + 1], "localhost:5000");
+                Ok(serde_json::to_string(&Job::default()).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(1, clos, |client| {
+            let mut ctx = generate_rv_ctx(client);
+            ctx.image_volumes.store(false, Ordering::Release);
+            ctx.insecure_registries = vec!["localhost:5000".to_string()];
+            let selection = PcrSelection::default();
+            let secrets = ["pull-secret".to_string()];
+            let result =
+                compute_fresh_pcrs(ctx, "image", "registry", None, &selection, &secrets, None);
+            assert!(result.await.is_ok());
+        });
+    }
+
+    fn probe_response(req: &Request<Body>, image_volume: bool) -> String {
+        assert!(req.uri().query().unwrap().contains("dryRun=All"));
+        let volume = Volume {
+            name: "image".to_string(),
+            image: image_volume.then(Default::default),
+            ..Default::default()
+        };
+        let job = Job {
+            spec: Some(JobSpec {
+                template: PodTemplateSpec {
+                    metadata: None,
+                    spec: Some(PodSpec {
+                        volumes: Some(vec![volume]),
+                        ..Default::default()
+                    }),
+                },
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        serde_json::to_string(&job).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_image_volumes_supported() {
+        let clos = async |req: Request<Body>, _| match req.method() {
+            &Method::POST => Ok(probe_response(&req, true)),
+            _ => panic!("unexpected API interaction: {req:?}"),
+        };
+        count_check!(1, clos, |client| {
+            assert!(image_volumes_supported(client).await.unwrap());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_image_volumes_dropped() {
+        let clos = async |req: Request<Body>, _| match req.method() {
+            &Method::POST => Ok(probe_response(&req, false)),
+            _ => panic!("unexpected API interaction: {req:?}"),
+        };
+        count_check!(1, clos, |client| {
+            assert!(!image_volumes_supported(client).await.unwrap());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_image_volumes_rejected() {
+        let clos = async |req: Request<Body>, _| match req.method() {
+            &Method::POST => Err(StatusCode::UNPROCESSABLE_ENTITY),
+            _ => panic!("unexpected API interaction: {req:?}"),
+        };
+        count_check!(1, clos, |client| {
+            assert!(!image_volumes_supported(client).await.unwrap());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_image_volumes_probe_error() {
+        let clos = |client| async { image_volumes_supported(client).await.map(|_| ()) };
+        test_create_error(clos).await;
+    }
+
+    #[tokio::test]
+    async fn test_compute_fresh_pcrs_provenance() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::POST) => {
+                let body = req.into_body().collect_bytes().await.unwrap();
+                let job: Job = serde_json::from_slice(&body).unwrap();
+                let pod_spec = job.spec.unwrap().template.spec.unwrap();
+                assert_eq!(pod_spec.automount_service_account_token, Some(false));
+                let image = pod_spec.containers[0].image.as_deref();
+                assert_eq!(image, Some("compute-pcrs@sha256:abc"));
+                Ok(serde_json::to_string(&Job::default()).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(1, clos, |client| {
+            let ctx = generate_rv_ctx(client);
+            let compute_image = "compute-pcrs".to_string();
+            let pinned = "compute-pcrs@sha256:abc".to_string();
+            ctx.pcrs_compute_image.set(compute_image, pinned);
+            let selection = PcrSelection::default();
+            let result = compute_fresh_pcrs(ctx, "image", "registry", None, &selection, &[], None);
+            assert!(result.await.is_ok());
+        });
+    }
+
+    #[test]
+    fn test_pcrs_status_label() {
+        let image_pcr = dummy_pcrs().0.remove("cos").unwrap();
+        let status = pcrs_status(&image_pcr, None);
+        assert_eq!(status["pcrs"][1]["id"], 1);
+        assert_eq!(status["pcrs"][1]["value"], "pcr1_val");
+        assert_eq!(status["pcrSource"], "Label");
+        // Cleared in case the PCRs were computed before
+        assert!(status["computeJob"].is_null());
+        assert!(status["lastComputed"].is_null());
+        assert!(status["firmwareProfilePcrs"].is_null());
+    }
+
+    #[test]
+    fn test_pcrs_status_computed() {
+        let mut image_pcr = dummy_pcrs().0.remove("cos").unwrap();
+        let profile_pcrs = image_pcr.pcrs.split_off(1);
+        image_pcr.firmware_profile_pcrs = BTreeMap::from([("a".to_string(), profile_pcrs)]);
+        let computation = PcrsComputation {
+            job: "job".to_string(),
+            image: "compute-pcrs".to_string(),
+            tool_version: "0.1.0".to_string(),
+        };
+        let status = pcrs_status(&image_pcr, Some(&computation));
+        assert_eq!(status["pcrs"].as_array().unwrap().len(), 1);
+        assert_eq!(status["firmwareProfilePcrs"][0]["profile"], "a");
+        assert_eq!(status["firmwareProfilePcrs"][0]["pcrs"][0]["id"], 1);
+        assert_eq!(status["pcrSource"], "Computed");
+        assert_eq!(status["computeJob"], "job");
+        assert!(status["lastComputed"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_compute_fresh_pcrs_error() {
+        let selection = PcrSelection::default();
+        let clos = |client| {
+            let ctx = generate_rv_ctx(client);
+            compute_fresh_pcrs(ctx, "image", "registry", None, &selection, &[], None)
+        };
+        test_create_error(clos).await;
+    }
+
+    #[tokio::test]
+    async fn test_compute_fresh_pcrs_expected() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::POST) => {
+                let body = req.into_body().collect_bytes().await.unwrap();
+                let job: Job = serde_json::from_slice(&body).unwrap();
+                let annotations = job.metadata.annotations.unwrap();
+                let request = &annotations[REQUEST_ANNOTATION_KEY];
+                let request: ComputeRequest = serde_json::from_str(request).unwrap();
+                assert_eq!(request.reference, "registry");
+                assert_eq!(request.expected_pcrs[&4], "pcr4_val");
+                Ok(serde_json::to_string(&Job::default()).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(1, clos, |client| {
+            let ctx = generate_rv_ctx(client);
+            let selection = PcrSelection::default();
+            let expected = [Pcr {
+                id: 4,
+                value: "pcr4_val".to_string(),
+                parts: vec![],
+            }];
+            let expected = Some(expected.as_slice());
+            let result =
+                compute_fresh_pcrs(ctx, "image", "registry", None, &selection, &[], expected);
+            assert!(result.await.is_ok());
+        });
+    }
+
+    fn dummy_image(reason: Option<&str>) -> ApprovedImage {
+        let generation = Some(1);
+        ApprovedImage {
+            metadata: ObjectMeta {
+                name: Some("image".to_string()),
+                generation,
+                ..Default::default()
+            },
+            spec: ApprovedImageSpec {
+                image: "image".to_string(),
+                pcr_selection: None,
+                firmware_profiles: None,
+                image_pull_secrets: None,
+                resolve_tag: None,
+                drain_seconds: None,
+            },
+            status: reason.map(|r| committed_status(committed_condition(r, generation))),
+        }
+    }
+
+    fn dummy_deleted_image(drain_seconds: Option<i64>) -> ApprovedImage {
+        let mut image = dummy_image(Some(COMMITTED_REASON));
+        image.metadata.deletion_timestamp = Some(Time(Utc::now()));
+        image.metadata.finalizers = Some(vec![APPROVED_IMAGE_FINALIZER.to_string()]);
+        image.spec.drain_seconds = drain_seconds;
+        image
+    }
+
+    fn dummy_machines() -> ObjectList<Machine> {
+        let machine = |name: &str, address: &str, boot_image: Option<&str>| {
+            let spec = MachineSpec {
+                id: name.to_string(),
+                registration_address: address.to_string(),
+            };
+            let mut machine = Machine::new(name, spec);
+            machine.status = Some(MachineStatus {
+                conditions: None,
+                boot_image: boot_image.map(ToString::to_string),
+            });
+            machine
+        };
+        ObjectList {
+            types: Default::default(),
+            metadata: Default::default(),
+            items: vec![
+                machine("machine-a", "192.0.2.1", Some("image")),
+                machine("machine-b", "192.0.2.2", Some("other")),
+                machine("machine-c", "192.0.2.3", None),
+                machine("machine-d", "192.0.2.4", None),
+            ],
+        }
+    }
+
+    fn dummy_nodes() -> ObjectList<Node> {
+        let node = |address: &str, image: &str| {
+            let mut node = Node::default();
+            node.annotations_mut()
+                .insert(NODE_IMAGE_ANNOTATION_KEY.to_string(), image.to_string());
+            node.status = Some(NodeStatus {
+                addresses: Some(vec![NodeAddress {
+                    address: address.to_string(),
+                    type_: "InternalIP".to_string(),
+                }]),
+                ..Default::default()
+            });
+            node
+        };
+        ObjectList {
+            types: Default::default(),
+            metadata: Default::default(),
+            items: vec![node("192.0.2.1", "other"), node("192.0.2.3", "image")],
+        }
+    }
+
+    #[test]
+    fn test_same_image() {
+        let digest = format!("sha256:{}", "a".repeat(64));
+        let pinned = format!("quay.io/os:stable@{digest}");
+        assert!(same_image(&pinned, &format!("quay.io/os@{digest}")));
+        assert!(!same_image(&pinned, &format!("quay.io/other@{digest}")));
+        assert!(!same_image(&pinned, "quay.io/os:stable"));
+        assert!(same_image("quay.io/os:stable", "quay.io/os:stable"));
+    }
+
+    #[tokio::test]
+    async fn test_node_architectures() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => {
+                assert!(req.uri().path().ends_with("/nodes"));
+                let query = req.uri().query().unwrap_or_default();
+                assert!(query.contains("labelSelector=kubernetes.io%2Farch"));
+                let node = |architecture: &str| {
+                    let mut node = Node::default();
+                    let labels = node.labels_mut();
+                    labels.insert(ARCH_LABEL_KEY.to_string(), architecture.to_string());
+                    node
+                };
+                let nodes = ObjectList {
+                    types: Default::default(),
+                    metadata: Default::default(),
+                    items: vec![node("amd64"), node("amd64"), node("s390x")],
+                };
+                Ok(serde_json::to_string(&nodes).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(1, clos, |client| {
+            let architectures = node_architectures(client).await.unwrap();
+            assert_eq!(
+                architectures,
+                BTreeSet::from(["amd64".into(), "s390x".into()])
+            );
+        });
+    }
+
+    #[tokio::test]
+    async fn test_drain_deadline() {
+        let clos = async |req: Request<_>, _| panic!("unexpected API interaction: {req:?}");
+        count_check!(0, clos, |client| {
+            let mut ctx = generate_rv_ctx(client);
+            assert!(drain_deadline(&ctx, &dummy_image(None)).is_none());
+            assert!(drain_deadline(&ctx, &dummy_deleted_image(None)).is_none());
+            assert!(drain_deadline(&ctx, &dummy_deleted_image(Some(600))).is_some());
+            ctx.image_drain = Duration::from_secs(600);
+            assert!(drain_deadline(&ctx, &dummy_deleted_image(None)).is_some());
+            assert!(drain_deadline(&ctx, &dummy_deleted_image(Some(0))).is_none());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_image_reconcile_draining() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => {
+                assert!(req.uri().path().contains("machines"));
+                Ok(serde_json::to_string(&dummy_machines()).unwrap())
+            }
+            (1, &Method::GET) => {
+                assert!(req.uri().path().contains("nodes"));
+                Ok(serde_json::to_string(&dummy_nodes()).unwrap())
+            }
+            (2, &Method::PATCH) => {
+                let bytes = req.into_body().collect_bytes().await.unwrap();
+                let status: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+                let status = &status["status"];
+                let draining = serde_json::json!(["machine-a", "machine-c"]);
+                assert_eq!(status["drainingMachines"], draining);
+                let conditions = status["conditions"].as_array().unwrap();
+                assert!(conditions.iter().any(|c| c["reason"] == DRAINING_REASON));
+                assert!(conditions.iter().any(|c| c["reason"] == COMMITTED_REASON));
+                Ok(serde_json::to_string(&dummy_image(None)).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(3, clos, |client| {
+            let ctx = Arc::new(generate_rv_ctx(client));
+            let image = Arc::new(dummy_deleted_image(Some(600)));
+            let result = image_reconcile(image, ctx).await.unwrap();
+            assert_eq!(result, Action::requeue(DRAIN_RECHECK_INTERVAL));
+        });
+    }
+
+    #[tokio::test]
+    async fn test_image_reconcile_draining_unchanged() {
+        let clos = async |req: Request<_>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => Ok(serde_json::to_string(&dummy_machines()).unwrap()),
+            (1, &Method::GET) => Ok(serde_json::to_string(&dummy_nodes()).unwrap()),
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(2, clos, |client| {
+            let ctx = Arc::new(generate_rv_ctx(client));
+            let mut image = dummy_deleted_image(Some(600));
+            let deleted = image.metadata.deletion_timestamp.as_ref().unwrap().0;
+            let deadline = Time(deleted + TimeDelta::seconds(600));
+            let deadline = serde_json::to_value(deadline).unwrap();
+            let status = image.status.as_mut().unwrap();
+            let conditions = status.conditions.as_mut().unwrap();
+            conditions.push(deprecated_condition(image.metadata.generation));
+            status.drain_deadline = deadline.as_str().map(ToString::to_string);
+            let draining = ["machine-a", "machine-c"].map(ToString::to_string);
+            status.draining_machines = Some(draining.to_vec());
+            let result = image_reconcile(Arc::new(image), ctx).await.unwrap();
+            assert_eq!(result, Action::requeue(DRAIN_RECHECK_INTERVAL));
+        });
+    }
+
+    #[tokio::test]
+    async fn test_image_add_reconcile_mismatch() {
+        let clos = async |req: Request<_>, _| panic!("unexpected API interaction: {req:?}");
+        count_check!(0, clos, |client| {
+            let ctx = generate_rv_ctx(client);
+            let image = dummy_image(Some(NOT_COMMITTED_REASON_MISMATCH));
+            let result = image_add_reconcile(ctx, &image).await.unwrap();
+            assert_eq!(result, Action::await_change());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_image_add_reconcile_failed_job_retained() {
+        let clos = async |req: Request<_>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => {
+                assert!(req.uri().path().contains("jobs"));
+                Ok(serde_json::to_string(&dummy_job()).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(1, clos, |client| {
+            let ctx = generate_rv_ctx(client);
+            let image = dummy_image(Some(NOT_COMMITTED_REASON_FAILED));
+            let result = image_add_reconcile(ctx, &image).await.unwrap();
+            assert_eq!(result, Action::await_change());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_image_add_reconcile_records_resolution() {
+        const MANIFEST: &[u8] = br#"{"schemaVersion":2}"#;
+        let mut content = RegistryContent::default();
+        content.add_manifest("os", "stable", MANIFEST);
+        let addr = serve_registry(content).await;
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::PATCH) => {
+                let resolved = format!("os:stable@{}", sha256_digest(MANIFEST));
+                assert_body_contains(req, &resolved).await;
+                Ok(serde_json::to_string(&dummy_image(None)).unwrap())
+            }
+            // Failing to read the stored PCRs keeps the resolution
+            (1, &Method::GET) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+            (2, &Method::PATCH) => {
+                let bytes = req.into_body().collect_bytes().await.unwrap();
+                let status: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+                assert!(status["status"].get("resolvedImage").is_none());
+                let conditions = status["status"]["conditions"].to_string();
+                assert!(conditions.contains(NOT_COMMITTED_REASON_FAILED));
+                Ok(serde_json::to_string(&dummy_image(None)).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(3, clos, |client| {
+            let mut ctx = generate_rv_ctx(client);
+            ctx.insecure_registries = vec![addr.clone()];
+            let mut image = dummy_image(None);
+            image.spec.image = format!("{addr}/os:stable");
+            image.spec.resolve_tag = Some(true);
+            let result = image_add_reconcile(ctx, &image).await.unwrap();
+            assert_eq!(result, Action::requeue(Duration::from_secs(60)));
+        });
+    }
+
+    #[tokio::test]
+    async fn test_resolve_tag() {
+        let mut content = RegistryContent::default();
+        let manifest = br#"{"schemaVersion":2}"#;
+        content.add_manifest("os", "stable", manifest);
+        let addr = serve_registry(content).await;
+        let clos = async |req: Request<_>, _| panic!("unexpected API interaction: {req:?}");
+        count_check!(0, clos, |client| {
+            let mut ctx = generate_rv_ctx(client);
+            ctx.insecure_registries = vec![addr.clone()];
+            let mut image = dummy_image(None);
+            image.spec.image = format!("{addr}/os:stable");
+            image.spec.resolve_tag = Some(true);
+            let resolved = resolve_tag(&ctx, &image, &[]).await.unwrap();
+            let digest = sha256_digest(manifest);
+            assert_eq!(resolved, format!("{addr}/os:stable@{digest}"));
+        });
+    }
+
+    #[tokio::test]
+    async fn test_resolve_tag_once() {
+        let clos = async |req: Request<_>, _| panic!("unexpected API interaction: {req:?}");
+        count_check!(0, clos, |client| {
+            let ctx = generate_rv_ctx(client);
+            let mut image = dummy_image(Some(COMMITTED_REASON));
+            image.spec.image = "quay.io/org/os:stable".to_string();
+            image.spec.resolve_tag = Some(true);
+            let pinned = "quay.io/org/os:stable@sha256:0123".to_string();
+            image.status.as_mut().unwrap().resolved_image = Some(pinned.clone());
+            let resolved = resolve_tag(&ctx, &image, &[]).await.unwrap();
+            assert_eq!(resolved, pinned);
+        });
+    }
+
+    // handle_new_image is an inherently online function and not tested here.
+
+    #[tokio::test]
+    async fn test_disallow_image() {
+        let clos = async |req: Request<_>, ctr| match (ctr, req.method()) {
+            // shard fetched & updated for removal
+            (0, &Method::GET) | (1, &Method::PUT) => {
+                assert!(req.uri().path().ends_with(&image_pcrs_shard_name("cos")));
+                Ok(serde_json::to_string(&dummy_pcrs_map()).unwrap())
+            }
+            // all shards fetched for recomputation
+            (2, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_maps()).unwrap()),
+            (3, &Method::GET) | (4, &Method::PUT) => {
+                assert!(req.uri().path().contains(trustee::TRUSTEE_DATA_MAP));
+                Ok(serde_json::to_string(&dummy_trustee_map()).unwrap())
+            }
+            (5, &Method::PATCH) => Ok(serde_json::to_string(&dummy_cluster()).unwrap()),
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(6, clos, |client| {
+            let ctx = generate_rv_ctx(client);
+            assert!(disallow_image(ctx, "cos").await.is_ok());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_disallow_image_not_allowed() {
+        let clos = async |req: Request<_>, ctr| match (ctr, req.method()) {
+            // nothing to remove, so no update
+            (0, &Method::GET) => {
+                assert!(req.uri().path().contains(PCR_CONFIG_MAP));
+                Ok(serde_json::to_string(&dummy_pcrs_map()).unwrap())
+            }
+            (1, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_maps()).unwrap()),
+            (2, &Method::GET) | (3, &Method::PUT) => {
+                assert!(req.uri().path().contains(trustee::TRUSTEE_DATA_MAP));
+                Ok(serde_json::to_string(&dummy_trustee_map()).unwrap())
+            }
+            (4, &Method::PATCH) => Ok(serde_json::to_string(&dummy_cluster()).unwrap()),
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(5, clos, |client| {
+            let ctx = generate_rv_ctx(client);
             assert!(disallow_image(ctx, "registry").await.is_ok());
         });
     }
+
+    #[tokio::test]
+    async fn test_disallow_image_conflict() {
+        let clos = async |req: Request<_>, ctr| match (ctr, req.method()) {
+            // another writer changed the shard in between, so the removal is retried
+            (1, &Method::PUT) => Err(StatusCode::CONFLICT),
+            (0 | 2, &Method::GET) | (3, &Method::PUT) => {
+                assert!(req.uri().path().contains(PCR_CONFIG_MAP));
+                Ok(serde_json::to_string(&dummy_pcrs_map()).unwrap())
+            }
+            (4, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_maps()).unwrap()),
+            (5, &Method::GET) | (6, &Method::PUT) => {
+                assert!(req.uri().path().contains(trustee::TRUSTEE_DATA_MAP));
+                Ok(serde_json::to_string(&dummy_trustee_map()).unwrap())
+            }
+            (7, &Method::PATCH) => Ok(serde_json::to_string(&dummy_cluster()).unwrap()),
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(8, clos, |client| {
+            let ctx = generate_rv_ctx(client);
+            assert!(disallow_image(ctx, "cos").await.is_ok());
+        });
+    }
+
+    fn dummy_images(image: &str) -> ObjectList<ApprovedImage> {
+        let mut approved = dummy_image(Some(COMMITTED_REASON));
+        approved.metadata.name = Some("cos".to_string());
+        approved.spec.image = image.to_string();
+        ObjectList {
+            types: Default::default(),
+            metadata: Default::default(),
+            items: vec![approved],
+        }
+    }
+
+    #[test]
+    fn test_approves() {
+        let image_pcr = dummy_pcrs().0.remove("cos").unwrap();
+        let mut image = dummy_images("ref").items.remove(0);
+        assert!(approves(&image, &image_pcr));
+        image.spec.image = "other".to_string();
+        assert!(!approves(&image, &image_pcr));
+        // tag not resolved yet
+        image.spec.resolve_tag = Some(true);
+        assert!(approves(&image, &image_pcr));
+        let status = image.status.as_mut().unwrap();
+        status.resolved_image = Some("ref".to_string());
+        assert!(approves(&image, &image_pcr));
+    }
+
+    #[tokio::test]
+    async fn test_sweep_orphans() {
+        let clos = async |req: Request<_>, ctr| match (ctr, req.method()) {
+            (0 | 4, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_maps()).unwrap()),
+            (1, &Method::GET) => {
+                assert!(req.uri().path().contains("approvedimages"));
+                Ok(serde_json::to_string(&dummy_images("other")).unwrap())
+            }
+            (2, &Method::GET) | (3, &Method::PUT) => {
+                assert!(req.uri().path().ends_with(&image_pcrs_shard_name("cos")));
+                Ok(serde_json::to_string(&dummy_pcrs_map()).unwrap())
+            }
+            (5, &Method::GET) | (6, &Method::PUT) => {
+                assert!(req.uri().path().contains(trustee::TRUSTEE_DATA_MAP));
+                Ok(serde_json::to_string(&dummy_trustee_map()).unwrap())
+            }
+            (7, &Method::PATCH) => Ok(serde_json::to_string(&dummy_cluster()).unwrap()),
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(8, clos, |client| {
+            let ctx = generate_rv_ctx(client);
+            assert_eq!(sweep_orphans(ctx).await.unwrap(), 1);
+        });
+    }
+
+    #[tokio::test]
+    async fn test_sweep_orphans_none() {
+        let clos = async |req: Request<_>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_maps()).unwrap()),
+            (1, &Method::GET) => Ok(serde_json::to_string(&dummy_images("ref")).unwrap()),
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(2, clos, |client| {
+            let ctx = generate_rv_ctx(client);
+            assert_eq!(sweep_orphans(ctx).await.unwrap(), 0);
+        });
+    }
+
+    #[tokio::test]
+    async fn test_sweep_orphans_recomputed() {
+        let clos = async |req: Request<_>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_maps()).unwrap()),
+            (1, &Method::GET) => Ok(serde_json::to_string(&dummy_images("other")).unwrap()),
+            // recomputed for the changed image in the meantime
+            (2, &Method::GET) => {
+                let mut image_pcrs = dummy_pcrs();
+                image_pcrs.0.get_mut("cos").unwrap().reference = "other".to_string();
+                let mut map = dummy_pcrs_map();
+                let data = map.data.as_mut().unwrap();
+                let json = serde_json::to_string(&image_pcrs).unwrap();
+                data.insert(PCR_CONFIG_FILE.to_string(), json);
+                Ok(serde_json::to_string(&map).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(3, clos, |client| {
+            let ctx = generate_rv_ctx(client);
+            assert_eq!(sweep_orphans(ctx).await.unwrap(), 0);
+        });
+    }
+
+    #[tokio::test]
+    async fn test_disallow_image_persistent_conflict() {
+        let clos = async |req: Request<_>, _| match *req.method() {
+            Method::GET => Ok(serde_json::to_string(&dummy_pcrs_map()).unwrap()),
+            Method::PUT => Err(StatusCode::CONFLICT),
+            _ => panic!("unexpected API interaction: {req:?}"),
+        };
+        count_check!(22, clos, |client| {
+            let ctx = generate_rv_ctx(client);
+            let err = disallow_image(ctx, "cos").await.err().unwrap();
+            assert!(err.to_string().contains("changed concurrently"));
+        });
+    }
 }
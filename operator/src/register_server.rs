@@ -11,6 +11,8 @@ use k8s_openapi::{
         core::v1::{
             Container, ContainerPort, PodSpec, PodTemplateSpec, Service, ServicePort, ServiceSpec,
         },
+        discovery::v1::EndpointSlice,
+        networking::v1::{IPBlock, NetworkPolicy, NetworkPolicyEgressRule, NetworkPolicyPeer},
     },
     apimachinery::pkg::{
         apis::meta::v1::{LabelSelector, ObjectMeta, OwnerReference},
@@ -18,31 +20,78 @@ use k8s_openapi::{
     },
 };
 use kube::runtime::{
+    WatchStreamExt,
     controller::{Action, Controller},
     finalizer,
     finalizer::Event,
+    watcher,
 };
-use kube::{Api, Client, Resource};
-use log::info;
+use kube::{
+    Api, Client, Resource,
+    api::{ListParams, Patch, PatchParams},
+};
+use log::{info, warn};
 use std::{collections::BTreeMap, sync::Arc};
 
 use crate::trustee;
 use operator::*;
-use trusted_cluster_operator_lib::Machine;
+use trusted_cluster_operator_lib::{Machine, TrustedExecutionCluster};
 
 const INTERNAL_REGISTER_SERVER_PORT: i32 = 8000;
+const REGISTER_SERVER_NETWORK_POLICY: &str = "register-server";
+/// EndpointSlices of the default/kubernetes Service, i.e. of the API server
+const API_SERVER_SLICE_LABEL: &str = "kubernetes.io/service-name=kubernetes";
 /// Finalizer name to discard decryption keys when a machine is deleted
 const MACHINE_FINALIZER: &str = "finalizer.machine.trusted-execution-clusters.io";
 
+pub async fn create_register_server_rbac(
+    client: Client,
+    owner_reference: OwnerReference,
+) -> Result<()> {
+    let name = "register-server";
+    // Machines are re-created when a node re-registers from the same address
+    let machine_verbs = ["create", "list", "delete"];
+    let rules = vec![
+        policy_rule(&Machine::group(&()), &Machine::plural(&()), &machine_verbs),
+        policy_rule(
+            &TrustedExecutionCluster::group(&()),
+            &TrustedExecutionCluster::plural(&()),
+            &["list"],
+        ),
+    ];
+    create_service_account_with_role(client, owner_reference, name, rules).await
+}
+
 pub async fn create_register_server_deployment(
     client: Client,
     owner_reference: OwnerReference,
     image: &str,
+    settings: PodSettings,
 ) -> Result<()> {
     let name = "register-server";
     let app_label = "register-server";
     let labels = BTreeMap::from([("app".to_string(), app_label.to_string())]);
 
+    let mut pod_spec = PodSpec {
+        service_account_name: Some(name.to_string()),
+        containers: vec![Container {
+            name: name.to_string(),
+            image: Some(image.to_string()),
+            ports: Some(vec![ContainerPort {
+                container_port: INTERNAL_REGISTER_SERVER_PORT,
+                ..Default::default()
+            }]),
+            args: Some(vec![
+                "--port".to_string(),
+                INTERNAL_REGISTER_SERVER_PORT.to_string(),
+            ]),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    settings.apply(&mut pod_spec);
+    harden_pod_spec(&mut pod_spec);
+
     let deployment = Deployment {
         metadata: ObjectMeta {
             name: Some(name.to_string()),
@@ -60,23 +109,7 @@ pub async fn create_register_server_deployment(
                     labels: Some(labels.clone()),
                     ..Default::default()
                 }),
-                spec: Some(PodSpec {
-                    service_account_name: Some("trusted-cluster-operator".to_string()),
-                    containers: vec![Container {
-                        name: name.to_string(),
-                        image: Some(image.to_string()),
-                        ports: Some(vec![ContainerPort {
-                            container_port: INTERNAL_REGISTER_SERVER_PORT,
-                            ..Default::default()
-                        }]),
-                        args: Some(vec![
-                            "--port".to_string(),
-                            INTERNAL_REGISTER_SERVER_PORT.to_string(),
-                        ]),
-                        ..Default::default()
-                    }],
-                    ..Default::default()
-                }),
+                spec: Some(pod_spec),
             },
             ..Default::default()
         }),
@@ -124,6 +157,95 @@ pub async fn create_register_server_service(
     Ok(())
 }
 
+/// Determine where the API server can be reached from pods, i.e. the
+/// endpoints behind the default/kubernetes Service
+async fn api_server_egress_rule(client: Client) -> Result<NetworkPolicyEgressRule> {
+    let slices: Api<EndpointSlice> = Api::namespaced(client, "default");
+    let params = ListParams::default().labels(API_SERVER_SLICE_LABEL);
+    let slice_list = slices.list(&params).await?;
+    let mut peers = vec![];
+    let mut ports = vec![];
+    for slice in slice_list.items.iter() {
+        let prefix = match slice.address_type.as_str() {
+            "IPv6" => 128,
+            _ => 32,
+        };
+        for address in slice.endpoints.iter().flat_map(|e| &e.addresses) {
+            peers.push(NetworkPolicyPeer {
+                ip_block: Some(IPBlock {
+                    cidr: format!("{address}/{prefix}"),
+                    except: None,
+                }),
+                ..Default::default()
+            });
+        }
+        let slice_ports = slice.ports.iter().flatten().filter_map(|p| p.port);
+        ports.extend(slice_ports.map(network_policy_port));
+    }
+    if peers.is_empty() {
+        return Err(anyhow!("No endpoints found for the API server"));
+    }
+    ports.dedup();
+    Ok(NetworkPolicyEgressRule {
+        ports: Some(ports),
+        to: Some(peers),
+    })
+}
+
+/// Nodes register from the given CIDRs, or from anywhere if none are
+/// given. Egress is limited to the API server.
+pub async fn create_register_server_network_policy(
+    client: Client,
+    owner_reference: OwnerReference,
+    node_cidrs: &[String],
+) -> Result<()> {
+    let name = REGISTER_SERVER_NETWORK_POLICY;
+    let app_label = "register-server";
+    let labels = BTreeMap::from([("app".to_string(), app_label.to_string())]);
+
+    let ingress = vec![cidr_ingress_rule(INTERNAL_REGISTER_SERVER_PORT, node_cidrs)];
+    let egress = vec![api_server_egress_rule(client.clone()).await?];
+    let network_policy =
+        generate_network_policy(name, owner_reference, labels, ingress, Some(egress));
+    create_or_info_if_exists!(client, NetworkPolicy, network_policy);
+    Ok(())
+}
+
+/// Point the register-server's egress rule at the current endpoints of the
+/// API server
+async fn refresh_api_server_egress(client: Client) -> Result<()> {
+    let rule = api_server_egress_rule(client.clone()).await?;
+    let policies: Api<NetworkPolicy> = Api::default_namespaced(client);
+    // A merge patch replaces the list of rules as a whole
+    let patch = Patch::Merge(serde_json::json!({"spec": {"egress": [rule]}}));
+    let params = PatchParams::default();
+    policies
+        .patch(REGISTER_SERVER_NETWORK_POLICY, &params, &patch)
+        .await?;
+    Ok(())
+}
+
+/// Follow the endpoints of the API server, which change e.g. when control
+/// plane nodes are replaced, so that the register-server can still reach it
+pub async fn launch_api_server_egress_refresh(client: Client) {
+    let slices: Api<EndpointSlice> = Api::namespaced(client.clone(), "default");
+    let config = watcher::Config::default().labels(API_SERVER_SLICE_LABEL);
+    let slices = watcher::watcher(slices, config).default_backoff();
+    let mut changes = slices.touched_objects().boxed();
+    tokio::spawn(async move {
+        while let Some(change) = changes.next().await {
+            if let Err(e) = change {
+                warn!("Watching the API server endpoints failed: {e}");
+                continue;
+            }
+            match refresh_api_server_egress(client.clone()).await {
+                Ok(_) => info!("Updated the register server's egress to the API server"),
+                Err(e) => warn!("Failed to update the register server's egress: {e}"),
+            }
+        }
+    });
+}
+
 async fn keygen_reconcile(
     machine: Arc<Machine>,
     client: Arc<Client>,
@@ -169,20 +291,140 @@ pub async fn launch_keygen_controller(client: Client) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_utils::*;
+    use http::{Method, Request};
+    use k8s_openapi::api::discovery::v1::{Endpoint, EndpointPort};
+    use kube::{api::ObjectList, client::Body};
     use trusted_cluster_operator_test_utils::mock_client::*;
 
+    #[tokio::test]
+    async fn test_create_reg_server_rbac_error() {
+        let clos = |client| create_register_server_rbac(client, Default::default());
+        test_create_error(clos).await;
+    }
+
     #[tokio::test]
     async fn test_create_reg_server_depl_success() {
-        let clos = |client| create_register_server_deployment(client, Default::default(), "image");
+        let clos = |client| {
+            create_register_server_deployment(
+                client,
+                Default::default(),
+                "image",
+                Default::default(),
+            )
+        };
         test_create_success::<_, _, Deployment>(clos).await;
     }
 
     #[tokio::test]
     async fn test_create_reg_server_depl_error() {
-        let clos = |client| create_register_server_deployment(client, Default::default(), "image");
+        let clos = |client| {
+            create_register_server_deployment(
+                client,
+                Default::default(),
+                "image",
+                Default::default(),
+            )
+        };
         test_create_error(clos).await;
     }
 
+    #[tokio::test]
+    async fn test_create_reg_server_depl_hardened() {
+        let clos = async |req: Request<Body>, _| {
+            let bytes = req.into_body().collect_bytes().await.unwrap().to_vec();
+            let deployment: Deployment = serde_json::from_slice(&bytes).unwrap();
+            let pod_spec = deployment.spec.unwrap().template.spec.unwrap();
+            assert_hardened(&pod_spec);
+            Ok(serde_json::to_string(&Deployment::default()).unwrap())
+        };
+        count_check!(1, clos, |client| {
+            let settings = Default::default();
+            let create =
+                create_register_server_deployment(client, Default::default(), "", settings);
+            assert!(create.await.is_ok());
+        });
+    }
+
+    fn dummy_api_server_slices() -> ObjectList<EndpointSlice> {
+        let slice = EndpointSlice {
+            address_type: "IPv4".to_string(),
+            endpoints: vec![Endpoint {
+                addresses: vec!["172.18.0.2".to_string()],
+                ..Default::default()
+            }],
+            ports: Some(vec![EndpointPort {
+                port: Some(6443),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+        ObjectList {
+            types: Default::default(),
+            metadata: Default::default(),
+            items: vec![slice],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_reg_server_network_policy() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => {
+                assert!(req.uri().path().contains("endpointslices"));
+                Ok(serde_json::to_string(&dummy_api_server_slices()).unwrap())
+            }
+            (1, &Method::POST) => {
+                assert_body_contains(req, "172.18.0.2/32").await;
+                Ok(serde_json::to_string(&NetworkPolicy::default()).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(2, clos, |client| {
+            let create = create_register_server_network_policy(client, Default::default(), &[]);
+            assert!(create.await.is_ok());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_create_reg_server_network_policy_no_endpoints() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => {
+                let mut slices = dummy_api_server_slices();
+                slices.items.clear();
+                Ok(serde_json::to_string(&slices).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(1, clos, |client| {
+            let create = create_register_server_network_policy(client, Default::default(), &[]);
+            assert!(create.await.is_err());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_refresh_api_server_egress() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => Ok(serde_json::to_string(&dummy_api_server_slices()).unwrap()),
+            (1, &Method::PATCH) => {
+                assert!(req.uri().path().ends_with(REGISTER_SERVER_NETWORK_POLICY));
+                assert_body_contains(req, "172.18.0.2/32").await;
+                Ok(serde_json::to_string(&NetworkPolicy::default()).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(2, clos, |client| {
+            assert!(refresh_api_server_egress(client).await.is_ok());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_create_reg_server_network_policy_error() {
+        test_get_error(async |c| {
+            create_register_server_network_policy(c, Default::default(), &[]).await
+        })
+        .await;
+    }
+
     #[tokio::test]
     async fn test_create_reg_server_svc_success() {
         let clos = |client| create_register_server_service(client, Default::default(), None);
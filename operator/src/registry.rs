@@ -0,0 +1,464 @@
+// SPDX-FileCopyrightText: Jakob Naucke <jnaucke@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+use anyhow::{Result, anyhow};
+use k8s_openapi::api::core::v1::Secret;
+use kube::{Api, Client};
+use oci_client::client::{ClientConfig, ClientProtocol};
+use oci_client::errors::{OciDistributionError, OciErrorCode};
+use oci_client::manifest::OciManifest;
+use oci_client::{Reference, secrets::RegistryAuth};
+use openssl::hash::{MessageDigest, hash};
+use operator::{CachedLookup, ImageMetadata, RegistryCache};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use trusted_cluster_operator_lib::pull_secrets::{docker_config, registry_credentials};
+
+/// Timeout of requests that the HTTP client makes
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Registry credentials for an image from the given pull secrets. The most
+/// specific matching entry wins; secrets given first take precedence.
+pub fn registry_auth(secrets: &[Secret], image_ref: &Reference) -> Result<RegistryAuth> {
+    let mut configs = vec![];
+    for secret in secrets.iter() {
+        let name = secret.metadata.name.as_deref().unwrap_or("<no name>");
+        let data = secret.data.as_ref();
+        configs.push(docker_config(name, |key| {
+            data.and_then(|d| d.get(key)).map(|v| v.0.as_slice())
+        })?);
+    }
+    let registry = image_ref.registry();
+    let credentials = registry_credentials(configs, registry, image_ref.repository())?;
+    Ok(match credentials {
+        Some((username, password)) => RegistryAuth::Basic(username, password),
+        None => RegistryAuth::Anonymous,
+    })
+}
+
+/// Registry client that uses plain HTTP for the given registries
+pub fn oci_client(insecure_registries: &[String]) -> oci_client::Client {
+    oci_client::Client::new(ClientConfig {
+        protocol: ClientProtocol::HttpsExcept(insecure_registries.to_vec()),
+        ..Default::default()
+    })
+}
+
+/// HTTP client for other requests to a registry's host, e.g. for stream
+/// metadata. As with the registry client, plain HTTP is only used for
+/// insecure registries.
+pub fn http_client(url: &str, insecure_registries: &[String]) -> Result<reqwest::Client> {
+    let url = reqwest::Url::parse(url)?;
+    let host = url.host_str().unwrap_or_default();
+    let host = match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    };
+    let client = reqwest::Client::builder()
+        .timeout(HTTP_TIMEOUT)
+        .https_only(!insecure_registries.contains(&host))
+        .build()?;
+    Ok(client)
+}
+
+pub fn is_not_found(error: &OciDistributionError) -> bool {
+    match error {
+        OciDistributionError::ImageManifestNotFoundError(_) => true,
+        OciDistributionError::RegistryError { envelope, .. } => {
+            let mut errors = envelope.errors.iter();
+            errors.any(|e| e.code == OciErrorCode::ManifestUnknown)
+        }
+        OciDistributionError::ServerError { code, .. } => *code == 404,
+        _ => false,
+    }
+}
+
+/// Identity that registry credentials authenticate as. Secrets are hashed,
+/// so that they are not kept in the cache.
+fn auth_identity(auth: &RegistryAuth) -> Result<String> {
+    let digest = |secret: &str| hash(MessageDigest::sha256(), secret.as_bytes()).map(hex::encode);
+    Ok(match auth {
+        RegistryAuth::Anonymous => "anonymous".to_string(),
+        RegistryAuth::Basic(username, password) => {
+            format!("basic:{username}:{}", digest(password)?)
+        }
+        RegistryAuth::Bearer(token) => format!("bearer:{}", digest(token)?),
+    })
+}
+
+/// Key of a lookup in the registry cache. Different credentials may see
+/// different content, and an image may be looked up for one architecture
+/// of a multi-architecture image.
+pub fn cache_key(
+    image_ref: &Reference,
+    auth: &RegistryAuth,
+    architecture: Option<&str>,
+) -> Result<String> {
+    let identity = auth_identity(auth)?;
+    let architecture = architecture.unwrap_or_default();
+    Ok(format!("{} {identity} {architecture}", image_ref.whole()))
+}
+
+/// Manifest and config of an image, possibly for one architecture of a
+/// multi-architecture image. Images that are pinned by digest are looked up
+/// in the cache first, and whether they were found is cached per
+/// credentials.
+pub async fn pull_manifest_and_config(
+    cache: &RegistryCache,
+    client: &oci_client::Client,
+    image_ref: &Reference,
+    auth: &RegistryAuth,
+    architecture: Option<&str>,
+) -> Result<Arc<ImageMetadata>> {
+    let key = cache_key(image_ref, auth, architecture)?;
+    let pinned = image_ref.digest().is_some();
+    match cache.get(&key).filter(|_| pinned) {
+        Some(CachedLookup::Found(metadata)) => return Ok(metadata),
+        Some(CachedLookup::NotFound) => {
+            return Err(anyhow!(
+                "Image {} was not found in its registry recently",
+                image_ref.whole()
+            ));
+        }
+        Some(CachedLookup::Platforms(_)) | None => {}
+    }
+    let (manifest, config) = match client.pull_manifest_and_config(image_ref, auth).await {
+        Ok((manifest, _, config)) => (manifest, config),
+        Err(e) if pinned && is_not_found(&e) => {
+            cache.insert(key, CachedLookup::NotFound);
+            return Err(e.into());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let metadata = Arc::new(ImageMetadata { manifest, config });
+    if pinned {
+        cache.insert(key, CachedLookup::Found(metadata.clone()));
+    }
+    Ok(metadata)
+}
+
+/// Architectures of multi-architecture images that PCRs are computed for,
+/// as both OCI platforms and the kubernetes.io/arch node label name them
+pub const SUPPORTED_ARCHITECTURES: [&str; 2] = ["amd64", "arm64"];
+
+/// Manifests of the supported Linux architectures of a multi-architecture
+/// image, pinned by digest, which are empty if it has none. None if the
+/// image is not an image index. Images that are pinned by digest are looked
+/// up in the cache first.
+pub async fn image_platforms(
+    cache: &RegistryCache,
+    client: &oci_client::Client,
+    image_ref: &Reference,
+    auth: &RegistryAuth,
+) -> Result<Option<BTreeMap<String, Reference>>> {
+    let key = format!("platforms {}", cache_key(image_ref, auth, None)?);
+    let pinned = image_ref.digest().is_some();
+    if let Some(CachedLookup::Platforms(platforms)) = cache.get(&key).filter(|_| pinned) {
+        return Ok(platforms);
+    }
+    let platforms = fetch_image_platforms(client, image_ref, auth).await?;
+    if pinned {
+        cache.insert(key, CachedLookup::Platforms(platforms.clone()));
+    }
+    Ok(platforms)
+}
+
+async fn fetch_image_platforms(
+    client: &oci_client::Client,
+    image_ref: &Reference,
+    auth: &RegistryAuth,
+) -> Result<Option<BTreeMap<String, Reference>>> {
+    let (manifest, _) = client.pull_manifest(image_ref, auth).await?;
+    let OciManifest::ImageIndex(index) = manifest else {
+        return Ok(None);
+    };
+    let mut platforms = BTreeMap::new();
+    for entry in index.manifests.iter() {
+        let Some(platform) = entry.platform.as_ref() else {
+            continue;
+        };
+        let architecture = platform.architecture.as_str();
+        if platform.os != "linux" || !SUPPORTED_ARCHITECTURES.contains(&architecture) {
+            continue;
+        }
+        let reference = Reference::with_digest(
+            image_ref.registry().to_string(),
+            image_ref.repository().to_string(),
+            entry.digest.clone(),
+        );
+        // Variants of an architecture boot the same, so the first one is used
+        platforms
+            .entry(architecture.to_string())
+            .or_insert(reference);
+    }
+    Ok(Some(platforms))
+}
+
+pub async fn fetch_pull_secrets(client: Client, names: &[String]) -> Result<Vec<Secret>> {
+    let secrets: Api<Secret> = Api::default_namespaced(client);
+    let mut pull_secrets = vec![];
+    for name in names.iter() {
+        pull_secrets.push(secrets.get(name).await?);
+    }
+    Ok(pull_secrets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{Engine as _, engine::general_purpose};
+    use k8s_openapi::ByteString;
+    use kube::api::ObjectMeta;
+    use std::collections::BTreeMap;
+    use trusted_cluster_operator_lib::pull_secrets::DOCKER_CONFIG_JSON_KEY;
+    use trusted_cluster_operator_test_utils::registry::*;
+
+    fn dummy_pull_secret(auths: serde_json::Value) -> Secret {
+        let config = serde_json::json!({ "auths": auths });
+        Secret {
+            metadata: ObjectMeta {
+                name: Some("pull-secret".to_string()),
+                ..Default::default()
+            },
+            data: Some(BTreeMap::from([(
+                DOCKER_CONFIG_JSON_KEY.to_string(),
+                ByteString(config.to_string().into_bytes()),
+            )])),
+            type_: Some("kubernetes.io/dockerconfigjson".to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Registry content with an image's config, and the image's manifest
+    fn dummy_image_content() -> (RegistryContent, Vec<u8>) {
+        let mut content = RegistryContent::default();
+        let config = content.add_blob("os", br#"{"config":{}}"#);
+        let manifest = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": OCI_MANIFEST_MEDIA_TYPE,
+            "config": {
+                "mediaType": "application/vnd.oci.image.config.v1+json",
+                "digest": config,
+                "size": 13,
+            },
+            "layers": [],
+        });
+        (content, manifest.to_string().into_bytes())
+    }
+
+    /// Image index with a manifest per platform, each given as OS and
+    /// architecture
+    fn dummy_index(content: &mut RegistryContent, platforms: &[(&str, &str)]) -> Vec<u8> {
+        let manifests: Vec<_> = platforms
+            .iter()
+            .map(|(os, architecture)| {
+                let manifest = format!(r#"{{"schemaVersion":2,"arch":"{architecture}"}}"#);
+                let digest = sha256_digest(manifest.as_bytes());
+                content.add_manifest("os", &digest, manifest.as_bytes());
+                serde_json::json!({
+                    "mediaType": OCI_MANIFEST_MEDIA_TYPE,
+                    "digest": digest,
+                    "size": manifest.len(),
+                    "platform": { "os": os, "architecture": architecture },
+                })
+            })
+            .collect();
+        let index = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.index.v1+json",
+            "manifests": manifests,
+        });
+        index.to_string().into_bytes()
+    }
+
+    #[tokio::test]
+    async fn test_image_platforms() {
+        let mut content = RegistryContent::default();
+        let platforms = [
+            ("linux", "amd64"),
+            ("linux", "arm64"),
+            ("linux", "s390x"),
+            ("unknown", "unknown"),
+        ];
+        let index = dummy_index(&mut content, &platforms);
+        let digest = sha256_digest(&index);
+        content.add_manifest("os", &digest, &index);
+        let addr = serve_registry(content).await;
+        let image_ref = format!("{addr}/os@{digest}").parse().unwrap();
+        let amd64 = sha256_digest(br#"{"schemaVersion":2,"arch":"amd64"}"#);
+        let amd64 = format!("{addr}/os@{amd64}");
+        let client = oci_client(&[addr]);
+        let auth = RegistryAuth::Anonymous;
+        let cache = RegistryCache::default();
+        let platforms = image_platforms(&cache, &client, &image_ref, &auth).await;
+        let platforms = platforms.unwrap().unwrap();
+        let architectures: Vec<_> = platforms.keys().collect();
+        assert_eq!(architectures, ["amd64", "arm64"]);
+        assert_eq!(platforms["amd64"].whole(), amd64);
+        let key = format!("platforms {}", cache_key(&image_ref, &auth, None).unwrap());
+        let cached = cache.get(&key);
+        assert!(matches!(cached, Some(CachedLookup::Platforms(Some(p))) if p.len() == 2));
+    }
+
+    #[tokio::test]
+    async fn test_image_platforms_unsupported() {
+        let mut content = RegistryContent::default();
+        let index = dummy_index(&mut content, &[("linux", "s390x")]);
+        let digest = sha256_digest(&index);
+        content.add_manifest("os", &digest, &index);
+        let addr = serve_registry(content).await;
+        let image_ref = format!("{addr}/os@{digest}").parse().unwrap();
+        let client = oci_client(&[addr]);
+        let auth = RegistryAuth::Anonymous;
+        let cache = RegistryCache::default();
+        let platforms = image_platforms(&cache, &client, &image_ref, &auth).await;
+        assert!(platforms.unwrap().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_image_platforms_single_image() {
+        let (mut content, manifest) = dummy_image_content();
+        let digest = sha256_digest(&manifest);
+        content.add_manifest("os", &digest, &manifest);
+        let addr = serve_registry(content).await;
+        let image_ref = format!("{addr}/os@{digest}").parse().unwrap();
+        let client = oci_client(&[addr]);
+        let auth = RegistryAuth::Anonymous;
+        let cache = RegistryCache::default();
+        let platforms = image_platforms(&cache, &client, &image_ref, &auth).await;
+        assert!(platforms.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pull_manifest_and_config_cached() {
+        let (mut content, manifest) = dummy_image_content();
+        let digest = sha256_digest(&manifest);
+        content.add_manifest("os", &digest, &manifest);
+        let addr = serve_registry(content).await;
+        let image_ref = format!("{addr}/os@{digest}").parse().unwrap();
+        let cache = RegistryCache::default();
+        let client = oci_client(&[addr]);
+        let auth = RegistryAuth::Anonymous;
+        let pull = pull_manifest_and_config(&cache, &client, &image_ref, &auth, None);
+        assert_eq!(pull.await.unwrap().config, r#"{"config":{}}"#);
+        // The registry is only served over plain HTTP, so this must not
+        // reach it
+        let client = oci_client(&[]);
+        let pull = pull_manifest_and_config(&cache, &client, &image_ref, &auth, None);
+        assert_eq!(pull.await.unwrap().config, r#"{"config":{}}"#);
+    }
+
+    #[tokio::test]
+    async fn test_pull_manifest_and_config_not_found() {
+        let addr = serve_registry(RegistryContent::default()).await;
+        let digest = sha256_digest(b"missing");
+        let image_ref = format!("{addr}/os@{digest}").parse().unwrap();
+        let cache = RegistryCache::default();
+        let client = oci_client(&[addr]);
+        let auth = RegistryAuth::Anonymous;
+        let pull = pull_manifest_and_config(&cache, &client, &image_ref, &auth, None);
+        assert!(pull.await.is_err());
+        let key = cache_key(&image_ref, &auth, None).unwrap();
+        assert!(matches!(cache.get(&key), Some(CachedLookup::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_pull_manifest_and_config_not_found_authenticated() {
+        let addr = serve_registry(RegistryContent::default()).await;
+        let digest = sha256_digest(b"missing");
+        let image_ref = format!("{addr}/os@{digest}").parse().unwrap();
+        let secret = dummy_pull_secret(serde_json::json!({
+            addr.clone(): { "username": "user", "password": "pass" },
+        }));
+        let auth = registry_auth(&[secret], &image_ref).unwrap();
+        assert_eq!(auth, basic("user", "pass"));
+        let cache = RegistryCache::default();
+        let client = oci_client(&[addr]);
+        let pull = pull_manifest_and_config(&cache, &client, &image_ref, &auth, None);
+        assert!(pull.await.is_err());
+        let key = cache_key(&image_ref, &auth, None).unwrap();
+        assert!(matches!(cache.get(&key), Some(CachedLookup::NotFound)));
+        // Other credentials may have access
+        let anonymous = cache_key(&image_ref, &RegistryAuth::Anonymous, None).unwrap();
+        assert!(cache.get(&anonymous).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pull_manifest_and_config_tag_uncached() {
+        let (mut content, manifest) = dummy_image_content();
+        content.add_manifest("os", "stable", &manifest);
+        let addr = serve_registry(content).await;
+        let image_ref = format!("{addr}/os:stable").parse().unwrap();
+        let cache = RegistryCache::default();
+        let client = oci_client(&[addr]);
+        let auth = RegistryAuth::Anonymous;
+        let pull = pull_manifest_and_config(&cache, &client, &image_ref, &auth, None);
+        assert!(pull.await.is_ok());
+        let key = cache_key(&image_ref, &auth, None).unwrap();
+        assert!(cache.get(&key).is_none());
+    }
+
+    fn basic(username: &str, password: &str) -> RegistryAuth {
+        RegistryAuth::Basic(username.to_string(), password.to_string())
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let digest = format!("sha256:{}", "a".repeat(64));
+        let image_ref = format!("quay.io/org/image@{digest}").parse().unwrap();
+        let key = |auth, architecture| cache_key(&image_ref, &auth, architecture).unwrap();
+        let anonymous = key(RegistryAuth::Anonymous, None);
+        assert_ne!(anonymous, key(basic("user", "pass"), None));
+        assert_ne!(
+            key(basic("user", "pass"), None),
+            key(basic("user", "other"), None)
+        );
+        assert_ne!(
+            key(basic("user", "pass"), None),
+            key(basic("other", "pass"), None)
+        );
+        assert_ne!(anonymous, key(RegistryAuth::Anonymous, Some("arm64")));
+        assert!(!key(basic("user", "pass"), None).contains("pass"));
+    }
+
+    #[test]
+    fn test_registry_auth_encoded() {
+        let auth = general_purpose::STANDARD.encode("user:pass");
+        let secret = dummy_pull_secret(serde_json::json!({
+            "https://quay.io/v1/": { "auth": auth },
+        }));
+        let image_ref = "quay.io/org/image:latest".parse().unwrap();
+        let auth = registry_auth(&[secret], &image_ref).unwrap();
+        assert_eq!(auth, basic("user", "pass"));
+    }
+
+    #[test]
+    fn test_registry_auth_most_specific() {
+        let secret = dummy_pull_secret(serde_json::json!({
+            "quay.io": { "username": "registry", "password": "pass" },
+            "quay.io/org": { "username": "org", "password": "pass" },
+            "quay.io/org/other": { "username": "other", "password": "pass" },
+        }));
+        let image_ref = "quay.io/org/image:latest".parse().unwrap();
+        let auth = registry_auth(&[secret], &image_ref).unwrap();
+        assert_eq!(auth, basic("org", "pass"));
+    }
+
+    #[test]
+    fn test_registry_auth_no_match() {
+        let secret = dummy_pull_secret(serde_json::json!({
+            "quay.io/org": { "username": "org", "password": "pass" },
+        }));
+        let image_ref = "quay.io/organization/image:latest".parse().unwrap();
+        let auth = registry_auth(&[secret], &image_ref).unwrap();
+        assert_eq!(auth, RegistryAuth::Anonymous);
+    }
+
+    #[test]
+    fn test_registry_auth_not_a_pull_secret() {
+        let mut secret = dummy_pull_secret(serde_json::json!({}));
+        secret.data = None;
+        let image_ref = "quay.io/org/image:latest".parse().unwrap();
+        assert!(registry_auth(&[secret], &image_ref).is_err());
+    }
+}
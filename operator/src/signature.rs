@@ -0,0 +1,697 @@
+// SPDX-FileCopyrightText: Jakob Naucke <jnaucke@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+use anyhow::{Context, Result, anyhow};
+use base64::{Engine as _, engine::general_purpose};
+use log::warn;
+use oci_client::{Reference, secrets::RegistryAuth};
+use openssl::hash::{MessageDigest, hash};
+use openssl::pkey::{Id, PKey, PKeyRef, Public};
+use openssl::sign::Verifier;
+use openssl::stack::Stack;
+use openssl::x509::{X509, X509StoreContext, store::X509StoreBuilder, verify::X509VerifyParam};
+use serde::{Deserialize, Serialize};
+use x509_parser::prelude::{ASN1Time, FromDer, GeneralName, X509Certificate};
+
+use crate::registry;
+use trusted_cluster_operator_lib::{
+    TrustedExecutionClusterImageVerification,
+    TrustedExecutionClusterImageVerificationKeylessIdentities as KeylessIdentity,
+};
+
+/// cosign stores signatures as layers of this type under the
+/// sha256-<digest>.sig tag of the signed image's repository
+const SIMPLE_SIGNING_MEDIA_TYPE: &str = "application/vnd.dev.cosign.simplesigning.v1+json";
+const SIGNATURE_ANNOTATION: &str = "dev.cosignproject.cosign/signature";
+const CERTIFICATE_ANNOTATION: &str = "dev.sigstore.cosign/certificate";
+const CHAIN_ANNOTATION: &str = "dev.sigstore.cosign/chain";
+const BUNDLE_ANNOTATION: &str = "dev.sigstore.cosign/bundle";
+
+/// Fulcio certificate extensions holding the OIDC issuer, as a raw value
+/// and as a DER-encoded UTF8String respectively
+const FULCIO_ISSUER_V1_OID: &str = "1.3.6.1.4.1.57264.1.1";
+const FULCIO_ISSUER_V2_OID: &str = "1.3.6.1.4.1.57264.1.8";
+
+struct CosignSignature {
+    payload: Vec<u8>,
+    /// Base64-encoded, as in the annotation and the Rekor entry
+    signature: String,
+    certificate: Option<String>,
+    chain: Option<String>,
+    bundle: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SimpleSigning {
+    critical: SimpleSigningCritical,
+}
+
+#[derive(Deserialize)]
+struct SimpleSigningCritical {
+    image: SimpleSigningImage,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct SimpleSigningImage {
+    docker_manifest_digest: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RekorBundle {
+    signed_entry_timestamp: String,
+    payload: RekorPayload,
+}
+
+/// Rekor signs the canonical JSON of this, so fields are declared in
+/// lexicographic order
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RekorPayload {
+    body: String,
+    integrated_time: i64,
+    #[serde(rename = "logID")]
+    log_id: String,
+    log_index: i64,
+}
+
+#[derive(Deserialize)]
+struct HashedRekord {
+    spec: HashedRekordSpec,
+}
+
+#[derive(Deserialize)]
+struct HashedRekordSpec {
+    data: HashedRekordData,
+    signature: HashedRekordSignature,
+}
+
+#[derive(Deserialize)]
+struct HashedRekordData {
+    hash: HashedRekordHash,
+}
+
+#[derive(Deserialize)]
+struct HashedRekordHash {
+    algorithm: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HashedRekordSignature {
+    content: String,
+    public_key: HashedRekordPublicKey,
+}
+
+/// Base64-encoded PEM of the certificate or key that made the signature
+#[derive(Deserialize)]
+struct HashedRekordPublicKey {
+    content: String,
+}
+
+/// Image verification policy with parsed keys and certificates
+struct VerificationPolicy {
+    public_keys: Vec<PKey<Public>>,
+    keyless_identities: Vec<KeylessIdentity>,
+    fulcio_certificates: Vec<X509>,
+    rekor_public_key: Option<PKey<Public>>,
+}
+
+impl VerificationPolicy {
+    fn from_crd(crd: &TrustedExecutionClusterImageVerification) -> Result<Self> {
+        let public_keys = crd.public_keys.iter().flatten();
+        let public_keys = public_keys.map(|k| PKey::public_key_from_pem(k.as_bytes()));
+        let fulcio_certificates = crd.fulcio_certificates.as_ref();
+        let fulcio_certificates = fulcio_certificates.map(|c| X509::stack_from_pem(c.as_bytes()));
+        let rekor_public_key = crd.rekor_public_key.as_ref();
+        let rekor_public_key = rekor_public_key.map(|k| PKey::public_key_from_pem(k.as_bytes()));
+        Ok(Self {
+            public_keys: public_keys.collect::<Result<_, _>>()?,
+            keyless_identities: crd.keyless_identities.clone().unwrap_or_default(),
+            fulcio_certificates: fulcio_certificates.transpose()?.unwrap_or_default(),
+            rekor_public_key: rekor_public_key.transpose()?,
+        })
+    }
+
+    fn accepts(&self, signature: &CosignSignature) -> bool {
+        let payload = &signature.payload;
+        let by_key = self.public_keys.iter().any(|key| {
+            verify_signature(key, payload, &signature.signature).unwrap_or_else(|e| {
+                warn!("Discarding malformed signature: {e}");
+                false
+            })
+        });
+        if by_key || self.keyless_identities.is_empty() {
+            return by_key;
+        }
+        self.verify_keyless(signature).unwrap_or_else(|e| {
+            warn!("Discarding malformed keyless signature: {e}");
+            false
+        })
+    }
+
+    /// Check that a Fulcio code signing certificate for one of the
+    /// identities was valid when the signature was logged in Rekor for that
+    /// certificate, and that it made the signature
+    fn verify_keyless(&self, signature: &CosignSignature) -> Result<bool> {
+        let (Some(certificate), Some(bundle)) = (&signature.certificate, &signature.bundle) else {
+            return Ok(false);
+        };
+        let rekor_public_key = self.rekor_public_key.as_ref();
+        let rekor_public_key = rekor_public_key.context("No Rekor public key was given")?;
+        let bundle: RekorBundle = serde_json::from_str(bundle)?;
+        let canonical = serde_json::to_vec(&bundle.payload)?;
+        let set = &bundle.signed_entry_timestamp;
+        if !verify_signature(rekor_public_key, &canonical, set)? {
+            return Ok(false);
+        }
+        let entry = general_purpose::STANDARD.decode(&bundle.payload.body)?;
+        let entry: HashedRekord = serde_json::from_slice(&entry)?;
+        let entry_hash = &entry.spec.data.hash;
+        let digest = match entry_hash.algorithm.as_str() {
+            "sha256" => MessageDigest::sha256(),
+            "sha384" => MessageDigest::sha384(),
+            "sha512" => MessageDigest::sha512(),
+            _ => return Ok(false),
+        };
+        let payload_hash = hex::encode(hash(digest, &signature.payload)?);
+        if entry_hash.value != payload_hash || entry.spec.signature.content != signature.signature {
+            return Ok(false);
+        }
+
+        let certificate = X509::from_pem(certificate.as_bytes())?;
+        let der = certificate.to_der()?;
+        // The entry must have been logged for this certificate
+        let logged = &entry.spec.signature.public_key.content;
+        let logged = general_purpose::STANDARD.decode(logged)?;
+        if X509::from_pem(&logged)?.to_der()? != der {
+            return Ok(false);
+        }
+        let chain = signature.chain.as_ref();
+        let chain = chain
+            .map(|c| X509::stack_from_pem(c.as_bytes()))
+            .transpose()?;
+        let integrated_time = bundle.payload.integrated_time;
+        if !self.verify_certificate(&certificate, chain.unwrap_or_default(), integrated_time)? {
+            return Ok(false);
+        }
+        let (_, parsed) = X509Certificate::from_der(&der)
+            .map_err(|e| anyhow!("Failed to parse certificate: {e}"))?;
+        let logged_at = ASN1Time::from_timestamp(integrated_time)?;
+        if !parsed.validity().is_valid_at(logged_at) || !is_code_signing(&parsed)? {
+            return Ok(false);
+        }
+        let (names, issuer) = keyless_identity(&parsed)?;
+        let identity_matches = self.keyless_identities.iter().any(|identity| {
+            names.contains(&identity.subject) && issuer.as_ref() == Some(&identity.issuer)
+        });
+        let public_key = certificate.public_key()?;
+        Ok(identity_matches
+            && verify_signature(&public_key, &signature.payload, &signature.signature)?)
+    }
+
+    /// Verify a certificate against the Fulcio certificates at the given
+    /// time, as Fulcio certificates are short-lived
+    fn verify_certificate(&self, certificate: &X509, chain: Vec<X509>, time: i64) -> Result<bool> {
+        let mut store = X509StoreBuilder::new()?;
+        for trusted in self.fulcio_certificates.iter() {
+            store.add_cert(trusted.clone())?;
+        }
+        let mut param = X509VerifyParam::new()?;
+        param.set_time(time as _);
+        store.set_param(&param)?;
+        let store = store.build();
+        let mut untrusted = Stack::new()?;
+        for intermediate in chain {
+            untrusted.push(intermediate)?;
+        }
+        let mut context = X509StoreContext::new()?;
+        let valid = context.init(&store, certificate, &untrusted, |c| c.verify_cert())?;
+        Ok(valid)
+    }
+}
+
+/// Digest that cosign signs with for a key. Ed25519 signs the data itself.
+fn signature_digest(key: &PKeyRef<Public>) -> Result<Option<MessageDigest>> {
+    let digest = match key.id() {
+        Id::ED25519 => return Ok(None),
+        Id::EC => match key.ec_key()?.group().degree() {
+            384 => MessageDigest::sha384(),
+            521 => MessageDigest::sha512(),
+            _ => MessageDigest::sha256(),
+        },
+        _ => MessageDigest::sha256(),
+    };
+    Ok(Some(digest))
+}
+
+fn verify_signature(key: &PKeyRef<Public>, data: &[u8], signature: &str) -> Result<bool> {
+    let signature = general_purpose::STANDARD.decode(signature)?;
+    let mut verifier = match signature_digest(key)? {
+        Some(digest) => Verifier::new(digest, key)?,
+        None => Verifier::new_without_digest(key)?,
+    };
+    // openssl errors on signatures that are not even well-formed
+    Ok(verifier.verify_oneshot(&signature, data).unwrap_or(false))
+}
+
+/// Whether a certificate may sign code, as Fulcio certificates must
+fn is_code_signing(certificate: &X509Certificate) -> Result<bool> {
+    let usage = certificate.extended_key_usage()?;
+    Ok(usage.is_some_and(|u| u.value.code_signing))
+}
+
+/// Subject alternative names and OIDC issuer of a Fulcio certificate
+fn keyless_identity(certificate: &X509Certificate) -> Result<(Vec<String>, Option<String>)> {
+    let names = certificate.subject_alternative_name()?;
+    let names = names.iter().flat_map(|n| n.value.general_names.iter());
+    let names = names.filter_map(|name| match name {
+        GeneralName::RFC822Name(email) => Some(email.to_string()),
+        GeneralName::URI(uri) => Some(uri.to_string()),
+        _ => None,
+    });
+    let extension = |oid| {
+        let mut extensions = certificate.iter_extensions();
+        extensions.find(|e| e.oid.to_id_string() == oid)
+    };
+    let issuer = match extension(FULCIO_ISSUER_V2_OID) {
+        Some(v2) => String::from_der(v2.value).ok().map(|(_, issuer)| issuer),
+        None => {
+            extension(FULCIO_ISSUER_V1_OID).and_then(|v1| String::from_utf8(v1.value.to_vec()).ok())
+        }
+    };
+    Ok((names.collect(), issuer))
+}
+
+async fn fetch_signatures(
+    client: &oci_client::Client,
+    image_ref: &Reference,
+    auth: &RegistryAuth,
+) -> Result<Vec<CosignSignature>> {
+    let digest = image_ref
+        .digest()
+        .context("Image reference had no digest")?;
+    let signature_ref = Reference::with_tag(
+        image_ref.registry().to_string(),
+        image_ref.repository().to_string(),
+        format!("{}.sig", digest.replace(':', "-")),
+    );
+    let manifest = match client.pull_image_manifest(&signature_ref, auth).await {
+        Ok((manifest, _)) => manifest,
+        Err(e) if registry::is_not_found(&e) => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    let layers = manifest.layers.iter();
+    let mut signatures = vec![];
+    for layer in layers.filter(|l| l.media_type == SIMPLE_SIGNING_MEDIA_TYPE) {
+        let annotations = layer.annotations.clone().unwrap_or_default();
+        let Some(signature) = annotations.get(SIGNATURE_ANNOTATION) else {
+            continue;
+        };
+        let mut payload = vec![];
+        client
+            .pull_blob(&signature_ref, layer, &mut payload)
+            .await?;
+        signatures.push(CosignSignature {
+            payload,
+            signature: signature.clone(),
+            certificate: annotations.get(CERTIFICATE_ANNOTATION).cloned(),
+            chain: annotations.get(CHAIN_ANNOTATION).cloned(),
+            bundle: annotations.get(BUNDLE_ANNOTATION).cloned(),
+        });
+    }
+    Ok(signatures)
+}
+
+/// Whether the image carries a cosign signature for its digest that the
+/// policy accepts
+pub async fn verify_image(
+    client: &oci_client::Client,
+    image_ref: &Reference,
+    auth: &RegistryAuth,
+    policy: &TrustedExecutionClusterImageVerification,
+) -> Result<bool> {
+    let policy = VerificationPolicy::from_crd(policy)?;
+    let digest = image_ref
+        .digest()
+        .context("Image reference had no digest")?;
+    let signatures = fetch_signatures(client, image_ref, auth).await?;
+    let signs_digest = |signature: &&CosignSignature| {
+        let payload = serde_json::from_slice::<SimpleSigning>(&signature.payload);
+        payload.is_ok_and(|p| p.critical.image.docker_manifest_digest == digest)
+    };
+    let mut signatures = signatures.iter().filter(signs_digest);
+    Ok(signatures.any(|s| policy.accepts(s)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry;
+    use k8s_openapi::chrono::Utc;
+    use openssl::asn1::{Asn1Object, Asn1OctetString, Asn1Time};
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+    use openssl::pkey::Private;
+    use openssl::sign::Signer;
+    use openssl::x509::extension::{BasicConstraints, ExtendedKeyUsage, SubjectAlternativeName};
+    use openssl::x509::{X509Builder, X509Extension, X509NameBuilder, X509Ref};
+    use trusted_cluster_operator_test_utils::registry::*;
+
+    const IMAGE_DIGEST: &str =
+        "sha256:e71dad00aa0e3d70540e726a0c66407e3004d96e045ab6c253186e327a2419e5";
+    const ISSUER: &str = "https://issuer.example.com";
+    const SUBJECT: &str = "signer@example.com";
+
+    fn generate_ec_key(curve: Nid) -> PKey<Private> {
+        let group = EcGroup::from_curve_name(curve).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    fn generate_key() -> PKey<Private> {
+        generate_ec_key(Nid::X9_62_PRIME256V1)
+    }
+
+    fn public_pem(key: &PKey<Private>) -> String {
+        String::from_utf8(key.public_key_to_pem().unwrap()).unwrap()
+    }
+
+    fn sign(key: &PKey<Private>, data: &[u8]) -> String {
+        let public_key = PKey::public_key_from_pem(public_pem(key).as_bytes()).unwrap();
+        let mut signer = match signature_digest(&public_key).unwrap() {
+            Some(digest) => Signer::new(digest, key).unwrap(),
+            None => Signer::new_without_digest(key).unwrap(),
+        };
+        let signature = signer.sign_oneshot_to_vec(data).unwrap();
+        general_purpose::STANDARD.encode(signature)
+    }
+
+    fn simple_signing_payload(digest: &str) -> Vec<u8> {
+        let payload = serde_json::json!({
+            "critical": {
+                "identity": { "docker-reference": "image" },
+                "image": { "docker-manifest-digest": digest },
+                "type": "cosign container image signature",
+            },
+            "optional": null,
+        });
+        payload.to_string().into_bytes()
+    }
+
+    fn key_policy(key: &PKey<Private>) -> TrustedExecutionClusterImageVerification {
+        TrustedExecutionClusterImageVerification {
+            public_keys: Some(vec![public_pem(key)]),
+            keyless_identities: None,
+            fulcio_certificates: None,
+            rekor_public_key: None,
+        }
+    }
+
+    /// Serve an image signature from a stand-in registry
+    async fn serve_signature(payload: &[u8], signature: &str) -> Reference {
+        let mut content = RegistryContent::default();
+        let payload_digest = content.add_blob("image", payload);
+        let config_digest = content.add_blob("image", b"{}");
+        let manifest = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": OCI_MANIFEST_MEDIA_TYPE,
+            "config": {
+                "mediaType": "application/vnd.oci.image.config.v1+json",
+                "digest": config_digest,
+                "size": 2,
+            },
+            "layers": [{
+                "mediaType": SIMPLE_SIGNING_MEDIA_TYPE,
+                "digest": payload_digest,
+                "size": payload.len(),
+                "annotations": { SIGNATURE_ANNOTATION: signature },
+            }],
+        });
+        let tag = format!("{}.sig", IMAGE_DIGEST.replace(':', "-"));
+        content.add_manifest("image", &tag, manifest.to_string().as_bytes());
+        let addr = serve_registry(content).await;
+        format!("{addr}/image@{IMAGE_DIGEST}").parse().unwrap()
+    }
+
+    async fn verify(
+        image_ref: &Reference,
+        policy: &TrustedExecutionClusterImageVerification,
+    ) -> bool {
+        let client = registry::oci_client(&[image_ref.registry().to_string()]);
+        let auth = RegistryAuth::Anonymous;
+        verify_image(&client, image_ref, &auth, policy)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_verify_image_public_key() {
+        let key = generate_key();
+        let payload = simple_signing_payload(IMAGE_DIGEST);
+        let image_ref = serve_signature(&payload, &sign(&key, &payload)).await;
+        assert!(verify(&image_ref, &key_policy(&key)).await);
+    }
+
+    #[tokio::test]
+    async fn test_verify_image_public_key_p384() {
+        let key = generate_ec_key(Nid::SECP384R1);
+        let payload = simple_signing_payload(IMAGE_DIGEST);
+        let image_ref = serve_signature(&payload, &sign(&key, &payload)).await;
+        assert!(verify(&image_ref, &key_policy(&key)).await);
+    }
+
+    #[tokio::test]
+    async fn test_verify_image_public_key_ed25519() {
+        let key = PKey::generate_ed25519().unwrap();
+        let payload = simple_signing_payload(IMAGE_DIGEST);
+        let image_ref = serve_signature(&payload, &sign(&key, &payload)).await;
+        assert!(verify(&image_ref, &key_policy(&key)).await);
+    }
+
+    #[tokio::test]
+    async fn test_verify_image_other_key() {
+        let payload = simple_signing_payload(IMAGE_DIGEST);
+        let image_ref = serve_signature(&payload, &sign(&generate_key(), &payload)).await;
+        assert!(!verify(&image_ref, &key_policy(&generate_key())).await);
+    }
+
+    #[tokio::test]
+    async fn test_verify_image_other_digest() {
+        let key = generate_key();
+        let payload = simple_signing_payload(&sha256_digest(b"other"));
+        let image_ref = serve_signature(&payload, &sign(&key, &payload)).await;
+        assert!(!verify(&image_ref, &key_policy(&key)).await);
+    }
+
+    #[tokio::test]
+    async fn test_verify_image_unsigned() {
+        let addr = serve_registry(RegistryContent::default()).await;
+        let image_ref = format!("{addr}/image@{IMAGE_DIGEST}").parse().unwrap();
+        assert!(!verify(&image_ref, &key_policy(&generate_key())).await);
+    }
+
+    fn fulcio_ca(key: &PKey<Private>, not_before: i64) -> X509 {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "fulcio").unwrap();
+        let name = name.build();
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(key).unwrap();
+        let not_before = Asn1Time::from_unix(not_before as _).unwrap();
+        builder.set_not_before(&not_before).unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        let constraints = BasicConstraints::new().critical().ca().build().unwrap();
+        builder.append_extension(constraints).unwrap();
+        builder.sign(key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    /// Certificate valid for ten minutes, as Fulcio issues them
+    fn fulcio_leaf(
+        key: &PKey<Private>,
+        ca: &X509Ref,
+        ca_key: &PKey<Private>,
+        issued: i64,
+        code_signing: bool,
+    ) -> X509 {
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_issuer_name(ca.subject_name()).unwrap();
+        builder.set_pubkey(key).unwrap();
+        let not_before = Asn1Time::from_unix(issued as _).unwrap();
+        builder.set_not_before(&not_before).unwrap();
+        let not_after = Asn1Time::from_unix((issued + 600) as _).unwrap();
+        builder.set_not_after(&not_after).unwrap();
+        let context = builder.x509v3_context(Some(ca), None);
+        let san = SubjectAlternativeName::new().email(SUBJECT).build(&context);
+        builder.append_extension(san.unwrap()).unwrap();
+        if code_signing {
+            let usage = ExtendedKeyUsage::new().code_signing().build().unwrap();
+            builder.append_extension(usage).unwrap();
+        }
+        let oid = Asn1Object::from_str("1.3.6.1.4.1.57264.1.1").unwrap();
+        let issuer = Asn1OctetString::new_from_bytes(ISSUER.as_bytes()).unwrap();
+        let issuer = X509Extension::new_from_der(&oid, false, &issuer).unwrap();
+        builder.append_extension(issuer).unwrap();
+        builder.sign(ca_key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    /// Ways in which a keyless signature deviates from a valid one
+    #[derive(Default)]
+    struct KeylessFlaws {
+        not_code_signing: bool,
+        issued_after_logging: bool,
+        other_certificate_logged: bool,
+    }
+
+    fn keyless_signature(
+        ca: &X509,
+        ca_key: &PKey<Private>,
+        rekor_key: &PKey<Private>,
+    ) -> CosignSignature {
+        keyless_signature_with(ca, ca_key, rekor_key, KeylessFlaws::default())
+    }
+
+    fn keyless_signature_with(
+        ca: &X509,
+        ca_key: &PKey<Private>,
+        rekor_key: &PKey<Private>,
+        flaws: KeylessFlaws,
+    ) -> CosignSignature {
+        // Logged an hour ago, so the certificate has expired since
+        let integrated_time = Utc::now().timestamp() - 3600;
+        let issued = match flaws.issued_after_logging {
+            true => integrated_time + 60,
+            false => integrated_time,
+        };
+        let key = generate_key();
+        let code_signing = !flaws.not_code_signing;
+        let certificate = fulcio_leaf(&key, ca, ca_key, issued, code_signing);
+        let certificate = String::from_utf8(certificate.to_pem().unwrap()).unwrap();
+        let logged = match flaws.other_certificate_logged {
+            true => fulcio_leaf(&generate_key(), ca, ca_key, issued, code_signing),
+            false => X509::from_pem(certificate.as_bytes()).unwrap(),
+        };
+        let logged = String::from_utf8(logged.to_pem().unwrap()).unwrap();
+        let payload = simple_signing_payload(IMAGE_DIGEST);
+        let payload_hash = hash(MessageDigest::sha256(), &payload).unwrap();
+        let signature = sign(&key, &payload);
+        let entry = serde_json::json!({
+            "apiVersion": "0.0.1",
+            "kind": "hashedrekord",
+            "spec": {
+                "data": { "hash": { "algorithm": "sha256", "value": hex::encode(payload_hash) } },
+                "signature": {
+                    "content": signature,
+                    "publicKey": { "content": general_purpose::STANDARD.encode(&logged) },
+                },
+            },
+        });
+        let rekor_payload = RekorPayload {
+            body: general_purpose::STANDARD.encode(entry.to_string()),
+            integrated_time,
+            log_id: "log".to_string(),
+            log_index: 1,
+        };
+        let set = sign(rekor_key, &serde_json::to_vec(&rekor_payload).unwrap());
+        let bundle = serde_json::json!({
+            "SignedEntryTimestamp": set,
+            "Payload": rekor_payload,
+        });
+        CosignSignature {
+            payload,
+            signature,
+            certificate: Some(certificate),
+            chain: None,
+            bundle: Some(bundle.to_string()),
+        }
+    }
+
+    fn keyless_policy(ca: &X509, rekor_key: &PKey<Private>, subject: &str) -> VerificationPolicy {
+        let crd = TrustedExecutionClusterImageVerification {
+            public_keys: None,
+            keyless_identities: Some(vec![KeylessIdentity {
+                issuer: ISSUER.to_string(),
+                subject: subject.to_string(),
+            }]),
+            fulcio_certificates: Some(String::from_utf8(ca.to_pem().unwrap()).unwrap()),
+            rekor_public_key: Some(public_pem(rekor_key)),
+        };
+        VerificationPolicy::from_crd(&crd).unwrap()
+    }
+
+    #[test]
+    fn test_verify_keyless() {
+        let (ca_key, rekor_key) = (generate_key(), generate_key());
+        let ca = fulcio_ca(&ca_key, Utc::now().timestamp() - 86400);
+        let signature = keyless_signature(&ca, &ca_key, &rekor_key);
+        assert!(keyless_policy(&ca, &rekor_key, SUBJECT).accepts(&signature));
+    }
+
+    #[test]
+    fn test_verify_keyless_other_identity() {
+        let (ca_key, rekor_key) = (generate_key(), generate_key());
+        let ca = fulcio_ca(&ca_key, Utc::now().timestamp() - 86400);
+        let signature = keyless_signature(&ca, &ca_key, &rekor_key);
+        let policy = keyless_policy(&ca, &rekor_key, "other@example.com");
+        assert!(!policy.accepts(&signature));
+    }
+
+    #[test]
+    fn test_verify_keyless_unlogged() {
+        let (ca_key, rekor_key) = (generate_key(), generate_key());
+        let ca = fulcio_ca(&ca_key, Utc::now().timestamp() - 86400);
+        let signature = keyless_signature(&ca, &ca_key, &generate_key());
+        assert!(!keyless_policy(&ca, &rekor_key, SUBJECT).accepts(&signature));
+    }
+
+    #[test]
+    fn test_verify_keyless_untrusted_ca() {
+        let (ca_key, rekor_key) = (generate_key(), generate_key());
+        let not_before = Utc::now().timestamp() - 86400;
+        let ca = fulcio_ca(&ca_key, not_before);
+        let signature = keyless_signature(&ca, &ca_key, &rekor_key);
+        let other_ca = fulcio_ca(&generate_key(), not_before);
+        let policy = keyless_policy(&other_ca, &rekor_key, SUBJECT);
+        assert!(!policy.accepts(&signature));
+    }
+
+    fn assert_keyless_rejected(flaws: KeylessFlaws) {
+        let (ca_key, rekor_key) = (generate_key(), generate_key());
+        let ca = fulcio_ca(&ca_key, Utc::now().timestamp() - 86400);
+        let signature = keyless_signature_with(&ca, &ca_key, &rekor_key, flaws);
+        assert!(!keyless_policy(&ca, &rekor_key, SUBJECT).accepts(&signature));
+    }
+
+    #[test]
+    fn test_verify_keyless_not_code_signing() {
+        assert_keyless_rejected(KeylessFlaws {
+            not_code_signing: true,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_verify_keyless_issued_after_logging() {
+        assert_keyless_rejected(KeylessFlaws {
+            issued_after_logging: true,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_verify_keyless_other_certificate_logged() {
+        assert_keyless_rejected(KeylessFlaws {
+            other_certificate_logged: true,
+            ..Default::default()
+        });
+    }
+}
@@ -3,13 +3,27 @@
 // SPDX-License-Identifier: MIT
 
 use compute_pcrs_lib::Pcr;
-use k8s_openapi::{api::core::v1::ConfigMap, chrono::Utc};
-use kube::Client;
-use operator::RvContextData;
-use std::collections::BTreeMap;
+use k8s_openapi::{
+    api::core::v1::{ConfigMap, PodSpec},
+    apimachinery::pkg::apis::meta::v1::OwnerReference,
+    chrono::Utc,
+};
+use kube::{
+    Client,
+    api::{ObjectList, ObjectMeta},
+};
+use operator::{PcrSelection, RvContextData};
+use std::sync::{Arc, atomic::AtomicBool};
+use std::{collections::BTreeMap, time::Duration};
 
 use crate::trustee;
-use trusted_cluster_operator_lib::reference_values::{ImagePcr, ImagePcrs, PCR_CONFIG_FILE};
+use trusted_cluster_operator_lib::reference_values::{
+    DEFAULT_PCR_SELECTION, ImagePcr, ImagePcrs, PCR_CONFIG_FILE, PCR_SHARDS, image_pcrs_shard_name,
+    pcrs_shard_name,
+};
+use trusted_cluster_operator_lib::{
+    ApprovedImageStatusPcrSource, TrustedExecutionClusterPcrLabelTrust,
+};
 
 pub fn dummy_pcrs() -> ImagePcrs {
     ImagePcrs(BTreeMap::from([(
@@ -29,6 +43,11 @@ pub fn dummy_pcrs() -> ImagePcrs {
                 },
             ],
             reference: "ref".to_string(),
+            firmware_profile_pcrs: BTreeMap::new(),
+            source: ApprovedImageStatusPcrSource::Label,
+            compute_image: None,
+            staged: None,
+            platforms: BTreeMap::new(),
         },
     )]))
 }
@@ -43,21 +62,79 @@ pub fn dummy_trustee_map() -> ConfigMap {
     }
 }
 
-pub fn dummy_pcrs_map() -> ConfigMap {
+pub fn pcrs_map(name: String, image_pcrs: &ImagePcrs) -> ConfigMap {
     let data = BTreeMap::from([(
         PCR_CONFIG_FILE.to_string(),
-        serde_json::to_string(&dummy_pcrs()).unwrap(),
+        serde_json::to_string(image_pcrs).unwrap(),
     )]);
     ConfigMap {
+        metadata: ObjectMeta {
+            name: Some(name),
+            resource_version: Some("1".to_string()),
+            ..Default::default()
+        },
         data: Some(data),
         ..Default::default()
     }
 }
 
+/// Shard that stores the dummy PCRs
+pub fn dummy_pcrs_map() -> ConfigMap {
+    pcrs_map(image_pcrs_shard_name("cos"), &dummy_pcrs())
+}
+
+/// All shards, one of which stores the dummy PCRs
+pub fn dummy_pcrs_maps() -> ObjectList<ConfigMap> {
+    pcrs_maps(dummy_pcrs)
+}
+
+/// All shards, storing the given PCRs
+pub fn pcrs_maps(image_pcrs: impl Fn() -> ImagePcrs) -> ObjectList<ConfigMap> {
+    let items = (0..PCR_SHARDS).map(pcrs_shard_name).map(|name| {
+        let mut image_pcrs = image_pcrs();
+        image_pcrs
+            .0
+            .retain(|image, _| image_pcrs_shard_name(image) == name);
+        pcrs_map(name, &image_pcrs)
+    });
+    ObjectList {
+        types: Default::default(),
+        metadata: Default::default(),
+        items: items.collect(),
+    }
+}
+
 pub fn generate_rv_ctx(client: Client) -> RvContextData {
     RvContextData {
         client,
-        owner_reference: Default::default(),
-        pcrs_compute_image: String::new(),
+        owner_reference: OwnerReference {
+            name: "test".to_string(),
+            ..Default::default()
+        },
+        pcrs_compute_image: Default::default(),
+        pcrs_compute_pod_settings: Default::default(),
+        pcrs_compute_job_settings: Default::default(),
+        pcr_selection: PcrSelection {
+            pcrs: DEFAULT_PCR_SELECTION.to_vec(),
+            firmware_profiles: vec![],
+        },
+        image_pull_secrets: vec![],
+        image_verification: None,
+        pcr_label_trust: TrustedExecutionClusterPcrLabelTrust::Trust,
+        insecure_registries: vec![],
+        image_volumes: Arc::new(AtomicBool::new(true)),
+        image_drain: Duration::ZERO,
+        reference_value_updates: Default::default(),
+        registry_cache: Default::default(),
+    }
+}
+
+pub fn assert_hardened(pod_spec: &PodSpec) {
+    let pod_ctx = pod_spec.security_context.as_ref().unwrap();
+    assert_eq!(pod_ctx.run_as_non_root, Some(true));
+    for container in pod_spec.containers.iter() {
+        let ctx = container.security_context.as_ref().unwrap();
+        assert_eq!(ctx.read_only_root_filesystem, Some(true));
+        assert_eq!(ctx.allow_privilege_escalation, Some(false));
     }
 }
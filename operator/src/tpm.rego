@@ -8,14 +8,23 @@ default configuration := 2
 
 default executables := 33
 
+# Each approved image holds its selected PCRs, which must all match.
+# An empty tuple would match any node, so it never does.
+
 ## TPM validation
 executables := 3 if {
-	lower(input.tpm.pcrs[4]) in data.reference.tpm_pcr4
-	lower(input.tpm.pcrs[14]) in data.reference.tpm_pcr14
+	some image in data.reference.tpm_pcrs
+	count(image) > 0
+	every id, value in image {
+		lower(input.tpm.pcrs[to_number(id)]) == value
+	}
 }
 
 # Azure SNP vTPM validation
 executables := 3 if {
-	lower(input.azsnpvtpm.tpm.pcr04) in data.reference.tpm_pcr4
-	lower(input.azsnpvtpm.tpm.pcr14) in data.reference.tpm_pcr14
+	some image in data.reference.tpm_pcrs
+	count(image) > 0
+	every id, value in image {
+		lower(input.azsnpvtpm.tpm[sprintf("pcr%02d", [to_number(id)])]) == value
+	}
 }
@@ -13,18 +13,31 @@ use k8s_openapi::api::core::v1::{
     PodTemplateSpec, Secret, SecretVolumeSource, Service, ServicePort, ServiceSpec, Volume,
     VolumeMount,
 };
+use k8s_openapi::api::networking::v1::{
+    HTTPIngressPath, HTTPIngressRuleValue, Ingress, IngressBackend, IngressRule,
+    IngressServiceBackend, IngressSpec, NetworkPolicy, NetworkPolicyIngressRule, NetworkPolicyPeer,
+    ServiceBackendPort,
+};
 use k8s_openapi::apimachinery::pkg::{
     apis::meta::v1::{LabelSelector, OwnerReference},
     util::intstr::IntOrString,
 };
 use k8s_openapi::chrono::{DateTime, TimeDelta, Utc};
-use kube::{Api, Client, Resource, api::ObjectMeta};
-use log::info;
-use operator::{RvContextData, create_or_info_if_exists};
+use kube::api::{ApiResource, DynamicObject, GroupVersionKind, ObjectMeta};
+use kube::{Api, Client, Resource};
+use log::{info, warn};
+use operator::{
+    PodSettings, RvContextData, cidr_ingress_rule, create_or_info_if_exists,
+    generate_network_policy, harden_pod_spec, network_policy_port,
+};
 use serde::{Serialize, Serializer};
-use serde_json::Value::String as JsonString;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use trusted_cluster_operator_lib::reference_values::*;
+use trusted_cluster_operator_lib::{
+    TrustedExecutionCluster, TrustedExecutionClusterStatusReferenceValues,
+    TrustedExecutionClusterTrusteeExposure, TrustedExecutionClusterTrusteeExposureType,
+    update_status,
+};
 
 const TRUSTEE_DATA_DIR: &str = "/opt/trustee";
 const TRUSTEE_SECRETS_PATH: &str = "/opt/trustee/kbs-repository/default";
@@ -35,6 +48,9 @@ pub(crate) const TRUSTEE_DATA_MAP: &str = "trustee-data";
 const ATT_POLICY_MAP: &str = "attestation-policy";
 const DEPLOYMENT_NAME: &str = "trustee-deployment";
 const INTERNAL_KBS_PORT: i32 = 8080;
+/// Size limit of a ConfigMap, and thus of an image PCRs shard and of the
+/// reference values
+const CONFIG_MAP_SIZE_LIMIT: usize = 1 << 20;
 
 fn primitive_date_time_to_str<S>(d: &DateTime<Utc>, s: S) -> Result<S::Ok, S::Error>
 where
@@ -56,41 +72,87 @@ struct ReferenceValue {
     pub value: serde_json::Value,
 }
 
-pub fn get_image_pcrs(image_pcrs_map: ConfigMap) -> Result<ImagePcrs> {
-    let err = "Image PCRs map existed, but had no data";
-    let image_pcrs_data = image_pcrs_map.data.context(err)?;
-    let err = "Image PCRs data existed, but had no file";
-    let image_pcrs_str = image_pcrs_data.get(PCR_CONFIG_FILE).context(err)?;
-    serde_json::from_str(image_pcrs_str).map_err(Into::into)
-}
+/// Reference value with one PCR tuple per approved image and firmware
+/// profile. The attestation policy requires all PCRs of a node to match the
+/// same tuple, so PCRs of different images cannot be combined.
+const TPM_PCRS_REFERENCE: &str = "pcrs";
 
 fn recompute_reference_values(image_pcrs: ImagePcrs) -> Vec<ReferenceValue> {
     // TODO many grub+shim:many OS image recompute once supported
-    let mut reference_values_in =
-        BTreeMap::from([("svn".to_string(), vec![JsonString("1".to_string())])]);
-    for pcr in image_pcrs.0.values().flat_map(|v| &v.pcrs) {
-        reference_values_in
-            .entry(format!("pcr{}", pcr.id))
-            .or_default()
-            .push(JsonString(pcr.value.clone()));
-    }
+    let pcr_tuples: BTreeSet<BTreeMap<String, String>> = image_pcrs
+        .0
+        .values()
+        // PCRs staged by a new compute-pcrs image are trusted alongside
+        .flat_map(|image| image.staged.as_deref().into_iter().chain([image]))
+        .flat_map(|image| image.pcr_sets())
+        // An empty tuple would match any node
+        .filter(|set| !set.is_empty())
+        .map(|set| {
+            let tuple = set.iter().map(|p| (p.id.to_string(), p.value.clone()));
+            tuple.collect()
+        })
+        .collect();
+    let reference_values_in = BTreeMap::from([
+        ("svn".to_string(), serde_json::json!(["1"])),
+        (
+            TPM_PCRS_REFERENCE.to_string(),
+            serde_json::json!(pcr_tuples),
+        ),
+    ]);
     reference_values_in
-        .iter()
-        .map(|(name, values)| ReferenceValue {
+        .into_iter()
+        .map(|(name, value)| ReferenceValue {
             version: "0.1.0".to_string(),
             name: format!("tpm_{name}"),
             expiration: Utc::now() + TimeDelta::days(365),
-            value: serde_json::Value::Array(values.to_vec()),
+            value,
         })
         .collect()
 }
 
+/// Recompute reference values from the image PCRs. Concurrent updates,
+/// e.g. from a burst of completed jobs, are coalesced.
 pub async fn update_reference_values(ctx: RvContextData) -> Result<()> {
-    let config_maps: Api<ConfigMap> = Api::default_namespaced(ctx.client);
-    let image_pcrs_map = config_maps.get(PCR_CONFIG_MAP).await?;
-    let reference_values = recompute_reference_values(get_image_pcrs(image_pcrs_map)?);
+    let updates = ctx.reference_value_updates.clone();
+    let cluster = ctx.owner_reference.name;
+    let compute_image = ctx.pcrs_compute_image.pinned();
+    updates
+        .run(|| do_update_reference_values(ctx.client, cluster, compute_image))
+        .await
+}
+
+/// Whether PCRs are staged and every image has PCRs as the compute-pcrs
+/// image yields them, so that the previous PCRs can be dropped
+fn compute_image_switched(image_pcrs: &ImagePcrs, compute_image: &str) -> bool {
+    let mut images = image_pcrs.0.values();
+    images.clone().any(|p| p.staged.is_some())
+        && images.all(|p| {
+            let pcrs = p.by_compute_image(compute_image);
+            pcrs.is_some_and(ImagePcr::is_complete)
+        })
+}
+
+async fn do_update_reference_values(
+    client: Client,
+    cluster: String,
+    compute_image: String,
+) -> Result<()> {
+    let store = ImagePcrsStore::new(client.clone());
+    let (mut image_pcrs, mut shard_sizes) = store.get_with_shard_sizes().await?;
+    // Switch to the new PCRs of all images at once
+    if compute_image_switched(&image_pcrs, &compute_image) {
+        let staged = image_pcrs.0.iter().filter(|(_, p)| p.staged.is_some());
+        let names = staged.map(|(name, _)| name.as_str());
+        store.promote_staged(names, &compute_image).await?;
+        info!("All images were recomputed by {compute_image}, dropping their previous PCRs");
+        (image_pcrs, shard_sizes) = store.get_with_shard_sizes().await?;
+    }
+    let image_count = image_pcrs.0.len();
+    let reference_values = recompute_reference_values(image_pcrs);
     let rv_json = serde_json::to_string(&reference_values)?;
+    let status = reference_values_status(image_count, &shard_sizes, &reference_values, &rv_json);
 
+    let config_maps: Api<ConfigMap> = Api::default_namespaced(client.clone());
     let mut trustee_map = config_maps.get(TRUSTEE_DATA_MAP).await?;
     let err = format!("ConfigMap {TRUSTEE_DATA_MAP} existed, but had no data");
     let trustee_data = trustee_map.data.as_mut().context(err)?;
@@ -100,9 +162,45 @@ pub async fn update_reference_values(ctx: RvContextData) -> Result<()> {
         .replace(TRUSTEE_DATA_MAP, &Default::default(), &trustee_map)
         .await?;
     info!("Recomputed reference values");
+
+    // Reference values are in place, so failing to report them is no error
+    let clusters: Api<TrustedExecutionCluster> = Api::default_namespaced(client);
+    let status = serde_json::json!({"referenceValues": status});
+    if let Err(e) = update_status!(clusters, &cluster, status) {
+        warn!("Failed to report reference values size of TrustedExecutionCluster {cluster}: {e}");
+    }
     Ok(())
 }
 
+/// Sizes and entry counts of image PCRs and reference values. Warns when
+/// either approaches the ConfigMap size limit.
+fn reference_values_status(
+    image_count: usize,
+    shard_sizes: &[usize],
+    reference_values: &[ReferenceValue],
+    rv_json: &str,
+) -> TrustedExecutionClusterStatusReferenceValues {
+    let largest_shard = shard_sizes.iter().copied().max().unwrap_or_default();
+    let pcrs_name = format!("tpm_{TPM_PCRS_REFERENCE}");
+    let pcrs = reference_values.iter().find(|rv| rv.name == pcrs_name);
+    let tuple_count = pcrs.and_then(|rv| rv.value.as_array()).map_or(0, Vec::len);
+    for (what, size) in [
+        ("largest image PCRs shard", largest_shard),
+        ("reference values", rv_json.len()),
+    ] {
+        if size > CONFIG_MAP_SIZE_LIMIT * 3 / 4 {
+            warn!("Size of {what} is {size} bytes, close to the limit of {CONFIG_MAP_SIZE_LIMIT}");
+        }
+    }
+    TrustedExecutionClusterStatusReferenceValues {
+        image_count: image_count as i32,
+        image_pcrs_bytes: shard_sizes.iter().sum::<usize>() as i64,
+        largest_shard_bytes: largest_shard as i64,
+        pcr_tuple_count: tuple_count as i32,
+        reference_values_bytes: rv_json.len() as i64,
+    }
+}
+
 fn generate_luks_key() -> Result<Vec<u8>> {
     // Constraint: 32 bytes b64-encoded, thus 24
     let mut pass = [0; 24];
@@ -256,7 +354,7 @@ pub async fn generate_kbs_service(
     owner_reference: OwnerReference,
     kbs_port: Option<i32>,
 ) -> Result<()> {
-    let svc_name = "kbs-service";
+    let svc_name = KBS_SERVICE_NAME;
     let selector = Some(BTreeMap::from([("app".to_string(), "kbs".to_string())]));
 
     let service = Service {
@@ -281,6 +379,237 @@ pub async fn generate_kbs_service(
     Ok(())
 }
 
+/// Label of the namespace of OpenShift's router
+const OPENSHIFT_INGRESS_POLICY_GROUP: &str = "policy-group.network.openshift.io/ingress";
+
+/// Nodes attest from the given CIDRs, or from anywhere if none are given.
+/// Ingress and Route endpoints are served by in-cluster pods, which the
+/// CIDRs do not cover, so traffic from these is allowed as well.
+fn kbs_ingress_rules(
+    node_cidrs: &[String],
+    exposure: Option<&TrustedExecutionClusterTrusteeExposure>,
+) -> Vec<NetworkPolicyIngressRule> {
+    let mut rules = vec![cidr_ingress_rule(INTERNAL_KBS_PORT, node_cidrs)];
+    if node_cidrs.is_empty() {
+        return rules;
+    }
+    let namespace_labels = match exposure.map(|e| (&e.r#type, e)) {
+        Some((TrustedExecutionClusterTrusteeExposureType::Ingress, e)) => {
+            let name = e.ingress_controller_namespace.clone();
+            let label = |n| BTreeMap::from([("kubernetes.io/metadata.name".to_string(), n)]);
+            name.map(label)
+        }
+        Some((TrustedExecutionClusterTrusteeExposureType::Route, _)) => {
+            let group = OPENSHIFT_INGRESS_POLICY_GROUP.to_string();
+            Some(BTreeMap::from([(group, String::new())]))
+        }
+        _ => return rules,
+    };
+    rules.push(NetworkPolicyIngressRule {
+        from: Some(vec![NetworkPolicyPeer {
+            namespace_selector: Some(LabelSelector {
+                match_labels: namespace_labels,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }]),
+        ports: Some(vec![network_policy_port(INTERNAL_KBS_PORT)]),
+    });
+    rules
+}
+
+pub async fn generate_kbs_network_policy(
+    client: Client,
+    owner_reference: OwnerReference,
+    node_cidrs: &[String],
+    exposure: Option<&TrustedExecutionClusterTrusteeExposure>,
+) -> Result<()> {
+    let labels = BTreeMap::from([("app".to_string(), "kbs".to_string())]);
+    let ingress = kbs_ingress_rules(node_cidrs, exposure);
+    let network_policy = generate_network_policy("kbs", owner_reference, labels, ingress, None);
+    create_or_info_if_exists!(client, NetworkPolicy, network_policy);
+    Ok(())
+}
+
+const KBS_SERVICE_NAME: &str = "kbs-service";
+const KBS_EXTERNAL_NAME: &str = "kbs-external";
+
+fn route_api_resource() -> ApiResource {
+    ApiResource::from_gvk(&GroupVersionKind::gvk("route.openshift.io", "v1", "Route"))
+}
+
+fn generate_kbs_load_balancer(owner_reference: OwnerReference, port: i32) -> Service {
+    Service {
+        metadata: ObjectMeta {
+            name: Some(KBS_EXTERNAL_NAME.to_string()),
+            owner_references: Some(vec![owner_reference]),
+            ..Default::default()
+        },
+        spec: Some(ServiceSpec {
+            type_: Some("LoadBalancer".to_string()),
+            selector: Some(BTreeMap::from([("app".to_string(), "kbs".to_string())])),
+            ports: Some(vec![ServicePort {
+                name: Some("kbs-port".to_string()),
+                port,
+                target_port: Some(IntOrString::Int(INTERNAL_KBS_PORT)),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn generate_kbs_ingress(
+    owner_reference: OwnerReference,
+    exposure: &TrustedExecutionClusterTrusteeExposure,
+    port: i32,
+) -> Ingress {
+    let backend = IngressBackend {
+        service: Some(IngressServiceBackend {
+            name: KBS_SERVICE_NAME.to_string(),
+            port: Some(ServiceBackendPort {
+                number: Some(port),
+                ..Default::default()
+            }),
+        }),
+        ..Default::default()
+    };
+    Ingress {
+        metadata: ObjectMeta {
+            name: Some(KBS_EXTERNAL_NAME.to_string()),
+            owner_references: Some(vec![owner_reference]),
+            ..Default::default()
+        },
+        spec: Some(IngressSpec {
+            ingress_class_name: exposure.ingress_class_name.clone(),
+            rules: Some(vec![IngressRule {
+                host: exposure.host.clone(),
+                http: Some(HTTPIngressRuleValue {
+                    paths: vec![HTTPIngressPath {
+                        backend,
+                        path: Some("/".to_string()),
+                        path_type: "Prefix".to_string(),
+                    }],
+                }),
+            }]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn generate_kbs_route(
+    owner_reference: OwnerReference,
+    exposure: &TrustedExecutionClusterTrusteeExposure,
+) -> DynamicObject {
+    let mut route = DynamicObject::new(KBS_EXTERNAL_NAME, &route_api_resource());
+    route.metadata.owner_references = Some(vec![owner_reference]);
+    let mut spec = serde_json::json!({
+        "to": {"kind": "Service", "name": KBS_SERVICE_NAME},
+        "port": {"targetPort": "kbs-port"},
+    });
+    if let Some(host) = &exposure.host {
+        spec["host"] = host.clone().into();
+    }
+    route.data = serde_json::json!({"spec": spec});
+    route
+}
+
+/// Create an endpoint that makes KBS reachable from outside the cluster
+pub async fn generate_kbs_exposure(
+    client: Client,
+    owner_reference: OwnerReference,
+    exposure: &TrustedExecutionClusterTrusteeExposure,
+    kbs_port: Option<i32>,
+) -> Result<()> {
+    let port = kbs_port.unwrap_or(INTERNAL_KBS_PORT);
+    match exposure.r#type {
+        TrustedExecutionClusterTrusteeExposureType::LoadBalancer => {
+            let service = generate_kbs_load_balancer(owner_reference, port);
+            create_or_info_if_exists!(client, Service, service);
+        }
+        TrustedExecutionClusterTrusteeExposureType::Ingress => {
+            let ingress = generate_kbs_ingress(owner_reference, exposure, port);
+            create_or_info_if_exists!(client, Ingress, ingress);
+        }
+        TrustedExecutionClusterTrusteeExposureType::Route => {
+            let route = generate_kbs_route(owner_reference, exposure);
+            let routes: Api<DynamicObject> =
+                Api::default_namespaced_with(client, &route_api_resource());
+            match routes.create(&Default::default(), &route).await {
+                Ok(_) => info!("Create Route {KBS_EXTERNAL_NAME}"),
+                Err(kube::Error::Api(ae)) if ae.code == 409 => {
+                    info!("Route {KBS_EXTERNAL_NAME} already exists");
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn format_addr(host: &str, port: Option<i32>) -> String {
+    let host = match host.contains(':') {
+        true => format!("[{host}]"),
+        false => host.to_string(),
+    };
+    match port {
+        Some(port) => format!("{host}:{port}"),
+        None => host,
+    }
+}
+
+/// Read the address assigned to the KBS endpoint back from its status.
+/// None if it has not been assigned yet.
+pub async fn discover_kbs_addr(
+    client: Client,
+    exposure: &TrustedExecutionClusterTrusteeExposure,
+    kbs_port: Option<i32>,
+) -> Result<Option<String>> {
+    let addr = match exposure.r#type {
+        TrustedExecutionClusterTrusteeExposureType::LoadBalancer => {
+            let services: Api<Service> = Api::default_namespaced(client);
+            let service = services.get_opt(KBS_EXTERNAL_NAME).await?;
+            let ingress = service
+                .and_then(|s| s.status)
+                .and_then(|s| s.load_balancer)
+                .and_then(|lb| lb.ingress)
+                .and_then(|i| i.into_iter().next());
+            let port = kbs_port.unwrap_or(INTERNAL_KBS_PORT);
+            ingress
+                .and_then(|i| i.ip.or(i.hostname))
+                .map(|host| format_addr(&host, Some(port)))
+        }
+        TrustedExecutionClusterTrusteeExposureType::Ingress => {
+            let ingresses: Api<Ingress> = Api::default_namespaced(client);
+            let ingress = ingresses.get_opt(KBS_EXTERNAL_NAME).await?;
+            let lb_ingress = ingress
+                .and_then(|i| i.status)
+                .and_then(|s| s.load_balancer)
+                .and_then(|lb| lb.ingress)
+                .and_then(|i| i.into_iter().next());
+            // Ingress controllers only fill in the status once they serve
+            // the Ingress, so an explicit host is not known to work before
+            lb_ingress.and_then(|i| {
+                let host = exposure.host.clone().or(i.ip).or(i.hostname);
+                host.map(|h| format_addr(&h, None))
+            })
+        }
+        TrustedExecutionClusterTrusteeExposureType::Route => {
+            let routes: Api<DynamicObject> =
+                Api::default_namespaced_with(client, &route_api_resource());
+            let route = routes.get_opt(KBS_EXTERNAL_NAME).await?;
+            let host = route.and_then(|r| {
+                let host = &r.data["status"]["ingress"][0]["host"];
+                host.as_str().map(ToString::to_string)
+            });
+            host.map(|h| format_addr(&h, None))
+        }
+    };
+    Ok(addr)
+}
+
 fn generate_kbs_volume_templates() -> [(&'static str, &'static str, Volume); 3] {
     [
         (
@@ -319,9 +648,9 @@ fn generate_kbs_volume_templates() -> [(&'static str, &'static str, Volume); 3]
     ]
 }
 
-fn generate_kbs_pod_spec(image: &str) -> PodSpec {
+fn generate_kbs_pod_spec(image: &str, settings: &PodSettings) -> PodSpec {
     let volumes = generate_kbs_volume_templates();
-    PodSpec {
+    let mut pod_spec = PodSpec {
         containers: vec![Container {
             command: Some(vec![
                 "/usr/local/bin/kbs".to_string(),
@@ -357,16 +686,22 @@ fn generate_kbs_pod_spec(image: &str) -> PodSpec {
                 .collect(),
         ),
         ..Default::default()
-    }
+    };
+    settings.apply(&mut pod_spec);
+    // KBS does not interact with the Kubernetes API
+    pod_spec.automount_service_account_token = Some(false);
+    harden_pod_spec(&mut pod_spec);
+    pod_spec
 }
 
 pub async fn generate_kbs_deployment(
     client: Client,
     owner_reference: OwnerReference,
     image: &str,
+    settings: PodSettings,
 ) -> Result<()> {
     let selector = Some(BTreeMap::from([("app".to_string(), "kbs".to_string())]));
-    let pod_spec = generate_kbs_pod_spec(image);
+    let pod_spec = generate_kbs_pod_spec(image, &settings);
 
     // Inspired by trustee-operator
     let deployment = Deployment {
@@ -400,14 +735,17 @@ pub async fn generate_kbs_deployment(
 mod tests {
     use super::*;
     use crate::test_utils::*;
+    use compute_pcrs_lib::Pcr;
     use http::{Method, Request, StatusCode};
+    use k8s_openapi::api::core::v1::{LoadBalancerIngress, LoadBalancerStatus, ServiceStatus};
     use kube::client::Body;
+    use trusted_cluster_operator_lib::ApprovedImageStatusPcrSource;
     use trusted_cluster_operator_test_utils::mock_client::*;
 
     #[test]
     fn test_get_image_pcrs_success() {
         let config_map = dummy_pcrs_map();
-        let image_pcrs = get_image_pcrs(config_map).unwrap();
+        let image_pcrs = get_image_pcrs(&config_map).unwrap();
         assert_eq!(image_pcrs.0["cos"].pcrs.len(), 2);
         assert_eq!(image_pcrs.0["cos"].pcrs[0].value, "pcr0_val");
     }
@@ -415,7 +753,7 @@ mod tests {
     #[test]
     fn test_get_image_pcrs_no_data() {
         let config_map = ConfigMap::default();
-        let err = get_image_pcrs(config_map).err().unwrap();
+        let err = get_image_pcrs(&config_map).err().unwrap();
         assert!(err.to_string().contains("but had no data"));
     }
 
@@ -425,7 +763,7 @@ mod tests {
             data: Some(BTreeMap::new()),
             ..Default::default()
         };
-        let err = get_image_pcrs(config_map).err().unwrap();
+        let err = get_image_pcrs(&config_map).err().unwrap();
         assert!(err.to_string().contains("but had no file"));
     }
 
@@ -436,33 +774,230 @@ mod tests {
             data: Some(data),
             ..Default::default()
         };
-        assert!(get_image_pcrs(config_map).is_err());
+        assert!(get_image_pcrs(&config_map).is_err());
     }
 
     #[test]
     fn test_recompute_reference_values() {
         let result = recompute_reference_values(dummy_pcrs());
-        assert_eq!(result.len(), 3);
-        let rv = result.iter().find(|rv| rv.name == "tpm_pcr0").unwrap();
-        let val_arr = rv.value.as_array().unwrap();
-        let vals: Vec<_> = val_arr.iter().map(|v| v.as_str().unwrap()).collect();
-        assert_eq!(vals, vec!["pcr0_val".to_string()]);
+        assert_eq!(result.len(), 2);
+        let rv = result.iter().find(|rv| rv.name == "tpm_pcrs").unwrap();
+        let tuples = rv.value.as_array().unwrap();
+        assert_eq!(tuples.len(), 1);
+        assert_eq!(tuples[0]["0"], "pcr0_val");
+        assert_eq!(tuples[0]["1"], "pcr1_val");
+    }
+
+    #[test]
+    fn test_recompute_reference_values_empty() {
+        let mut image_pcrs = dummy_pcrs();
+        let mut empty = dummy_pcrs().0.remove("cos").unwrap();
+        empty.pcrs.clear();
+        image_pcrs.0.insert("empty".to_string(), empty);
+        let result = recompute_reference_values(image_pcrs);
+        let rv = result.iter().find(|rv| rv.name == "tpm_pcrs").unwrap();
+        let tuples = rv.value.as_array().unwrap();
+        assert_eq!(tuples.len(), 1);
+        assert_eq!(tuples[0]["0"], "pcr0_val");
+    }
+
+    #[test]
+    fn test_recompute_reference_values_per_image() {
+        let mut image_pcrs = dummy_pcrs();
+        let mut other = dummy_pcrs().0.remove("cos").unwrap();
+        other.pcrs[1].value = "other_pcr1_val".to_string();
+        image_pcrs.0.insert("other".to_string(), other);
+        let result = recompute_reference_values(image_pcrs);
+        let rv = result.iter().find(|rv| rv.name == "tpm_pcrs").unwrap();
+        let tuples = rv.value.as_array().unwrap();
+        assert_eq!(tuples.len(), 2);
+        assert!(tuples.iter().all(|t| t["0"] == "pcr0_val"));
+        assert!(tuples.iter().any(|t| t["1"] == "pcr1_val"));
+        assert!(tuples.iter().any(|t| t["1"] == "other_pcr1_val"));
+    }
+
+    #[test]
+    fn test_recompute_reference_values_per_profile() {
+        let mut image_pcrs = dummy_pcrs();
+        let image_pcr = image_pcrs.0.get_mut("cos").unwrap();
+        let profile_pcr = |value: &str| Pcr {
+            id: 7,
+            value: value.to_string(),
+            parts: vec![],
+        };
+        image_pcr.firmware_profile_pcrs = BTreeMap::from([
+            ("a".to_string(), vec![profile_pcr("pcr7_a")]),
+            ("b".to_string(), vec![profile_pcr("pcr7_b")]),
+        ]);
+        let result = recompute_reference_values(image_pcrs);
+        let rv = result.iter().find(|rv| rv.name == "tpm_pcrs").unwrap();
+        let tuples = rv.value.as_array().unwrap();
+        assert_eq!(tuples.len(), 2);
+        assert!(tuples.iter().all(|t| t["0"] == "pcr0_val"));
+        assert!(tuples.iter().any(|t| t["7"] == "pcr7_a"));
+        assert!(tuples.iter().any(|t| t["7"] == "pcr7_b"));
+    }
+
+    #[test]
+    fn test_recompute_reference_values_platforms() {
+        let mut image_pcrs = dummy_pcrs();
+        let platform = || PlatformPcrs {
+            pcrs: dummy_pcrs().0.remove("cos").unwrap().pcrs,
+            firmware_profile_pcrs: BTreeMap::new(),
+        };
+        let (amd64, mut arm64) = (platform(), platform());
+        arm64.pcrs[1].value = "arm64_pcr1_val".to_string();
+        let image_pcr = image_pcrs.0.get_mut("cos").unwrap();
+        image_pcr.pcrs = vec![];
+        image_pcr.platforms = BTreeMap::from([
+            ("amd64".to_string(), Some(amd64)),
+            ("arm64".to_string(), Some(arm64)),
+            ("s390x".to_string(), None),
+        ]);
+        let result = recompute_reference_values(image_pcrs);
+        let rv = result.iter().find(|rv| rv.name == "tpm_pcrs").unwrap();
+        let tuples = rv.value.as_array().unwrap();
+        // Pending architectures add no tuple
+        assert_eq!(tuples.len(), 2);
+        assert!(tuples.iter().any(|t| t["1"] == "pcr1_val"));
+        assert!(tuples.iter().any(|t| t["1"] == "arm64_pcr1_val"));
+    }
+
+    /// PCRs of "cos" computed by "old", with PCRs staged by "new"
+    fn staged_pcrs() -> ImagePcrs {
+        let mut image_pcrs = dummy_pcrs();
+        let mut staged = dummy_pcrs().0.remove("cos").unwrap();
+        staged.pcrs[1].value = "new_pcr1_val".to_string();
+        staged.source = ApprovedImageStatusPcrSource::Computed;
+        staged.compute_image = Some("new".to_string());
+        let image_pcr = image_pcrs.0.get_mut("cos").unwrap();
+        image_pcr.source = ApprovedImageStatusPcrSource::Computed;
+        image_pcr.compute_image = Some("old".to_string());
+        image_pcr.staged = Some(Box::new(staged));
+        image_pcrs
+    }
+
+    #[test]
+    fn test_recompute_reference_values_staged() {
+        let result = recompute_reference_values(staged_pcrs());
+        let rv = result.iter().find(|rv| rv.name == "tpm_pcrs").unwrap();
+        let tuples = rv.value.as_array().unwrap();
+        assert_eq!(tuples.len(), 2);
+        assert!(tuples.iter().any(|t| t["1"] == "pcr1_val"));
+        assert!(tuples.iter().any(|t| t["1"] == "new_pcr1_val"));
+    }
+
+    #[test]
+    fn test_compute_image_switched() {
+        let mut image_pcrs = staged_pcrs();
+        assert!(compute_image_switched(&image_pcrs, "new"));
+        // Nothing was recomputed by "newer" yet
+        assert!(!compute_image_switched(&image_pcrs, "newer"));
+        // Label PCRs do not depend on the compute image
+        let label = dummy_pcrs().0.remove("cos").unwrap();
+        image_pcrs.0.insert("label".to_string(), label);
+        assert!(compute_image_switched(&image_pcrs, "new"));
+        let mut outstanding = dummy_pcrs().0.remove("cos").unwrap();
+        outstanding.source = ApprovedImageStatusPcrSource::Computed;
+        outstanding.compute_image = Some("old".to_string());
+        image_pcrs.0.insert("outstanding".to_string(), outstanding);
+        assert!(!compute_image_switched(&image_pcrs, "new"));
+        // Computed PCRs that do not name their compute image are stale
+        let mut image_pcrs = staged_pcrs();
+        let mut legacy = dummy_pcrs().0.remove("cos").unwrap();
+        legacy.source = ApprovedImageStatusPcrSource::Computed;
+        image_pcrs.0.insert("legacy".to_string(), legacy);
+        assert!(!compute_image_switched(&image_pcrs, "new"));
+        // Nothing to switch
+        assert!(!compute_image_switched(&dummy_pcrs(), "new"));
+        // Staged PCRs still miss an architecture
+        let mut image_pcrs = staged_pcrs();
+        let image_pcr = image_pcrs.0.get_mut("cos").unwrap();
+        let staged = image_pcr.staged.as_mut().unwrap();
+        staged.platforms = BTreeMap::from([("arm64".to_string(), None)]);
+        assert!(!compute_image_switched(&image_pcrs, "new"));
+    }
+
+    #[test]
+    fn test_reference_values_status() {
+        let reference_values = recompute_reference_values(dummy_pcrs());
+        let status = reference_values_status(1, &[10, 30, 20], &reference_values, "[]");
+        assert_eq!(status.image_count, 1);
+        assert_eq!(status.image_pcrs_bytes, 60);
+        assert_eq!(status.largest_shard_bytes, 30);
+        assert_eq!(status.pcr_tuple_count, 1);
+        assert_eq!(status.reference_values_bytes, 2);
     }
 
     #[tokio::test]
     async fn test_update_rvs_success() {
         let clos = async |req: Request<_>, ctr| match (ctr, req.method()) {
             (0, &Method::GET) => {
-                assert!(req.uri().path().contains(PCR_CONFIG_MAP));
-                Ok(serde_json::to_string(&dummy_pcrs_map()).unwrap())
+                assert!(req.uri().query().unwrap().contains("labelSelector"));
+                Ok(serde_json::to_string(&dummy_pcrs_maps()).unwrap())
             }
             (1, &Method::GET) | (2, &Method::PUT) => {
                 assert!(req.uri().path().contains(TRUSTEE_DATA_MAP));
                 Ok(serde_json::to_string(&dummy_trustee_map()).unwrap())
             }
+            (3, &Method::PATCH) => {
+                assert!(req.uri().path().ends_with("/status"));
+                assert_body_contains(req, "\"imageCount\":1").await;
+                Ok(serde_json::to_string(&dummy_cluster()).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(4, clos, |client| {
+            let ctx = generate_rv_ctx(client);
+            assert!(update_reference_values(ctx).await.is_ok());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_update_rvs_compute_image_switch() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => Ok(serde_json::to_string(&pcrs_maps(staged_pcrs)).unwrap()),
+            (1, &Method::GET) => {
+                let shard = pcrs_map(image_pcrs_shard_name("cos"), &staged_pcrs());
+                Ok(serde_json::to_string(&shard).unwrap())
+            }
+            (2, &Method::PUT) => {
+                assert!(req.uri().path().ends_with(&image_pcrs_shard_name("cos")));
+                let body = req.into_body().collect_bytes().await.unwrap();
+                let shard: ConfigMap = serde_json::from_slice(&body).unwrap();
+                let image_pcrs = get_image_pcrs(&shard).unwrap();
+                let image_pcr = &image_pcrs.0["cos"];
+                assert_eq!(image_pcr.compute_image.as_deref(), Some("new"));
+                assert_eq!(image_pcr.pcrs[1].value, "new_pcr1_val");
+                assert!(image_pcr.staged.is_none());
+                Ok(serde_json::to_string(&shard).unwrap())
+            }
+            (3, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_maps()).unwrap()),
+            (4, &Method::GET) | (5, &Method::PUT) => {
+                assert!(req.uri().path().contains(TRUSTEE_DATA_MAP));
+                Ok(serde_json::to_string(&dummy_trustee_map()).unwrap())
+            }
+            (6, &Method::PATCH) => Ok(serde_json::to_string(&dummy_cluster()).unwrap()),
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(7, clos, |client| {
+            let ctx = generate_rv_ctx(client);
+            ctx.pcrs_compute_image.set(String::new(), "new".to_string());
+            assert!(update_reference_values(ctx).await.is_ok());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_update_rvs_status_error() {
+        let clos = async |req: Request<_>, ctr| match (ctr, req.method()) {
+            (0, &Method::GET) => Ok(serde_json::to_string(&dummy_pcrs_maps()).unwrap()),
+            (1, &Method::GET) | (2, &Method::PUT) => {
+                Ok(serde_json::to_string(&dummy_trustee_map()).unwrap())
+            }
+            (3, &Method::PATCH) => Err(StatusCode::INTERNAL_SERVER_ERROR),
             _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
         };
-        count_check!(3, clos, |client| {
+        count_check!(4, clos, |client| {
             let ctx = generate_rv_ctx(client);
             assert!(update_reference_values(ctx).await.is_ok());
         });
@@ -470,22 +1005,25 @@ mod tests {
 
     #[tokio::test]
     async fn test_update_rvs_no_pcr_map() {
-        let clos = async |req: Request<_>, _| match (req.uri().path(), req.method()) {
-            (p, &Method::GET) if p.contains(PCR_CONFIG_MAP) => Err(StatusCode::NOT_FOUND),
+        let clos = async |req: Request<_>, _| match req.method() {
+            &Method::GET => {
+                let mut maps = dummy_pcrs_maps();
+                maps.items.pop();
+                Ok(serde_json::to_string(&maps).unwrap())
+            }
             _ => panic!("unexpected API interaction: {req:?}"),
         };
         count_check!(1, clos, |client| {
             let ctx = generate_rv_ctx(client);
-            assert!(update_reference_values(ctx).await.is_err());
+            let err = update_reference_values(ctx).await.err().unwrap();
+            assert!(err.to_string().contains("not found"));
         });
     }
 
     #[tokio::test]
     async fn test_update_rvs_no_trustee_map() {
         let clos = async |req: Request<_>, ctr| match (ctr, req.uri().path()) {
-            (0, p) if p.contains(PCR_CONFIG_MAP) => {
-                Ok(serde_json::to_string(&dummy_pcrs_map()).unwrap())
-            }
+            (0, _) => Ok(serde_json::to_string(&dummy_pcrs_maps()).unwrap()),
             (1, p) if p.contains(TRUSTEE_DATA_MAP) => Err(StatusCode::NOT_FOUND),
             _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
         };
@@ -498,9 +1036,7 @@ mod tests {
     #[tokio::test]
     async fn test_update_rvs_no_trustee_data() {
         let clos = async |req: Request<_>, ctr| match (ctr, req.uri().path()) {
-            (0, p) if p.contains(PCR_CONFIG_MAP) => {
-                Ok(serde_json::to_string(&dummy_pcrs_map()).unwrap())
-            }
+            (0, _) => Ok(serde_json::to_string(&dummy_pcrs_maps()).unwrap()),
             (1, p) if p.contains(TRUSTEE_DATA_MAP) => {
                 Ok(serde_json::to_string(&ConfigMap::default()).unwrap())
             }
@@ -696,15 +1232,206 @@ mod tests {
         test_create_error(clos).await;
     }
 
+    #[test]
+    fn test_generate_kbs_pod_spec_settings() {
+        let settings = PodSettings {
+            node_selector: Some(BTreeMap::from([("kbs".to_string(), "true".to_string())])),
+            priority_class_name: Some("high".to_string()),
+            ..Default::default()
+        };
+        let pod_spec = generate_kbs_pod_spec("image", &settings);
+        assert_eq!(pod_spec.node_selector, settings.node_selector);
+        assert_eq!(pod_spec.priority_class_name, Some("high".to_string()));
+    }
+
+    #[test]
+    fn test_generate_kbs_pod_spec_hardened() {
+        let pod_spec = generate_kbs_pod_spec("image", &Default::default());
+        assert_hardened(&pod_spec);
+        assert_eq!(pod_spec.automount_service_account_token, Some(false));
+    }
+
+    #[tokio::test]
+    async fn test_generate_kbs_network_policy_success() {
+        let clos = |client| generate_kbs_network_policy(client, Default::default(), &[], None);
+        test_create_success::<_, _, NetworkPolicy>(clos).await;
+    }
+
+    #[tokio::test]
+    async fn test_generate_kbs_network_policy_error() {
+        let clos = |client| generate_kbs_network_policy(client, Default::default(), &[], None);
+        test_create_error(clos).await;
+    }
+
+    fn dummy_exposure(
+        r#type: TrustedExecutionClusterTrusteeExposureType,
+    ) -> TrustedExecutionClusterTrusteeExposure {
+        TrustedExecutionClusterTrusteeExposure {
+            r#type,
+            host: None,
+            ingress_class_name: None,
+            ingress_controller_namespace: None,
+        }
+    }
+
+    #[test]
+    fn test_kbs_ingress_rules() {
+        let cidrs = ["10.0.0.0/16".to_string()];
+        assert_eq!(kbs_ingress_rules(&[], None).len(), 1);
+        let load_balancer =
+            dummy_exposure(TrustedExecutionClusterTrusteeExposureType::LoadBalancer);
+        assert_eq!(kbs_ingress_rules(&cidrs, Some(&load_balancer)).len(), 1);
+
+        let mut ingress = dummy_exposure(TrustedExecutionClusterTrusteeExposureType::Ingress);
+        let ingress_rules = kbs_ingress_rules(&[], Some(&ingress));
+        assert_eq!(ingress_rules.len(), 1);
+        assert_eq!(ingress_rules[0].from, None);
+        let ingress_rules = kbs_ingress_rules(&cidrs, Some(&ingress));
+        let selector = ingress_rules[1].from.as_ref().unwrap()[0]
+            .namespace_selector
+            .clone();
+        assert_eq!(selector.unwrap().match_labels, None);
+        ingress.ingress_controller_namespace = Some("ingress-nginx".to_string());
+        let ingress_rules = kbs_ingress_rules(&cidrs, Some(&ingress));
+        let selector = ingress_rules[1].from.as_ref().unwrap()[0]
+            .namespace_selector
+            .clone();
+        let labels = selector.unwrap().match_labels.unwrap();
+        assert_eq!(labels["kubernetes.io/metadata.name"], "ingress-nginx");
+
+        let route = dummy_exposure(TrustedExecutionClusterTrusteeExposureType::Route);
+        let route_rules = kbs_ingress_rules(&cidrs, Some(&route));
+        let selector = route_rules[1].from.as_ref().unwrap()[0]
+            .namespace_selector
+            .clone();
+        let labels = selector.unwrap().match_labels.unwrap();
+        assert!(labels.contains_key(OPENSHIFT_INGRESS_POLICY_GROUP));
+    }
+
+    #[tokio::test]
+    async fn test_generate_kbs_exposure_load_balancer() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::POST) => {
+                assert!(req.uri().path().ends_with("/services"));
+                assert_body_contains(req, "LoadBalancer").await;
+                Ok(serde_json::to_string(&Service::default()).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(1, clos, |client| {
+            let exposure = dummy_exposure(TrustedExecutionClusterTrusteeExposureType::LoadBalancer);
+            let create = generate_kbs_exposure(client, Default::default(), &exposure, None);
+            assert!(create.await.is_ok());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_generate_kbs_exposure_route() {
+        let clos = async |req: Request<Body>, ctr| match (ctr, req.method()) {
+            (0, &Method::POST) => {
+                assert!(req.uri().path().contains("route.openshift.io"));
+                assert_body_contains(req, "kbs.example.com").await;
+                let route = generate_kbs_route(
+                    Default::default(),
+                    &dummy_exposure(TrustedExecutionClusterTrusteeExposureType::Route),
+                );
+                Ok(serde_json::to_string(&route).unwrap())
+            }
+            _ => panic!("unexpected API interaction: {req:?}, counter {ctr}"),
+        };
+        count_check!(1, clos, |client| {
+            let mut exposure = dummy_exposure(TrustedExecutionClusterTrusteeExposureType::Route);
+            exposure.host = Some("kbs.example.com".to_string());
+            let create = generate_kbs_exposure(client, Default::default(), &exposure, None);
+            assert!(create.await.is_ok());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_generate_kbs_exposure_error() {
+        let exposure = dummy_exposure(TrustedExecutionClusterTrusteeExposureType::Ingress);
+        let clos = |client| generate_kbs_exposure(client, Default::default(), &exposure, None);
+        test_create_error(clos).await;
+    }
+
+    #[tokio::test]
+    async fn test_discover_kbs_addr_load_balancer() {
+        let clos = async |_, _| {
+            let mut service = generate_kbs_load_balancer(Default::default(), INTERNAL_KBS_PORT);
+            service.status = Some(ServiceStatus {
+                load_balancer: Some(LoadBalancerStatus {
+                    ingress: Some(vec![LoadBalancerIngress {
+                        ip: Some("fd00::1".to_string()),
+                        ..Default::default()
+                    }]),
+                }),
+                ..Default::default()
+            });
+            Ok(serde_json::to_string(&service).unwrap())
+        };
+        count_check!(1, clos, |client| {
+            let exposure = dummy_exposure(TrustedExecutionClusterTrusteeExposureType::LoadBalancer);
+            let addr = discover_kbs_addr(client, &exposure, Some(443))
+                .await
+                .unwrap();
+            assert_eq!(addr, Some("[fd00::1]:443".to_string()));
+        });
+    }
+
+    #[tokio::test]
+    async fn test_discover_kbs_addr_ingress_pending() {
+        let clos = async |_, _| {
+            let exposure = dummy_exposure(TrustedExecutionClusterTrusteeExposureType::Ingress);
+            let ingress = generate_kbs_ingress(Default::default(), &exposure, INTERNAL_KBS_PORT);
+            Ok(serde_json::to_string(&ingress).unwrap())
+        };
+        count_check!(1, clos, |client| {
+            let mut exposure = dummy_exposure(TrustedExecutionClusterTrusteeExposureType::Ingress);
+            exposure.host = Some("kbs.example.com".to_string());
+            let addr = discover_kbs_addr(client, &exposure, None).await.unwrap();
+            assert_eq!(addr, None);
+        });
+    }
+
+    #[tokio::test]
+    async fn test_discover_kbs_addr_route() {
+        let clos = async |_, _| {
+            let exposure = dummy_exposure(TrustedExecutionClusterTrusteeExposureType::Route);
+            let mut route = generate_kbs_route(Default::default(), &exposure);
+            route.data["status"] =
+                serde_json::json!({"ingress": [{"host": "kbs.apps.example.com"}]});
+            Ok(serde_json::to_string(&route).unwrap())
+        };
+        count_check!(1, clos, |client| {
+            let exposure = dummy_exposure(TrustedExecutionClusterTrusteeExposureType::Route);
+            let addr = discover_kbs_addr(client, &exposure, None).await.unwrap();
+            assert_eq!(addr, Some("kbs.apps.example.com".to_string()));
+        });
+    }
+
+    #[tokio::test]
+    async fn test_discover_kbs_addr_not_found() {
+        let clos = async |_, _| Err(StatusCode::NOT_FOUND);
+        count_check!(1, clos, |client| {
+            let exposure = dummy_exposure(TrustedExecutionClusterTrusteeExposureType::LoadBalancer);
+            let addr = discover_kbs_addr(client, &exposure, None).await.unwrap();
+            assert_eq!(addr, None);
+        });
+    }
+
     #[tokio::test]
     async fn test_generate_kbs_depl_success() {
-        let clos = |client| generate_kbs_deployment(client, Default::default(), "image");
+        let clos = |client| {
+            generate_kbs_deployment(client, Default::default(), "image", Default::default())
+        };
         test_create_success::<_, _, Deployment>(clos).await;
     }
 
     #[tokio::test]
     async fn test_generate_kbs_depl_error() {
-        let clos = |client| generate_kbs_deployment(client, Default::default(), "image");
+        let clos = |client| {
+            generate_kbs_deployment(client, Default::default(), "image", Default::default())
+        };
         test_create_error(clos).await;
     }
 }
@@ -92,10 +92,15 @@ async fn get_public_trustee_addr(client: Client) -> anyhow::Result<String> {
     }
     let cluster = list.items.pop().unwrap();
     let name = cluster.metadata.name.as_deref().unwrap_or("<no name>");
-    cluster.spec.public_trustee_addr.context(format!(
-        "TrustedExecutionCluster {name} did not specify a public Trustee address. \
-         Add an address and re-register the node."
-    ))
+    // The operator records the effective address, which may have been
+    // discovered from an exposed endpoint
+    let status_addr = cluster.status.as_ref().and_then(|s| s.trustee_addr.clone());
+    status_addr
+        .or(cluster.spec.public_trustee_addr.clone())
+        .context(format!(
+            "TrustedExecutionCluster {name} did not specify a public Trustee address, \
+             and none was discovered yet. Add an address and re-register the node."
+        ))
 }
 
 async fn register_handler(remote_addr: Option<SocketAddr>) -> Result<impl warp::Reply, Infallible> {
@@ -191,6 +196,7 @@ mod tests {
     use super::*;
     use http::{Method, Request};
     use kube::api::ObjectList;
+    use trusted_cluster_operator_lib::TrustedExecutionClusterStatus;
     use trusted_cluster_operator_test_utils::mock_client::*;
 
     const TEST_IP: &str = "12.34.56.78";
@@ -212,6 +218,24 @@ mod tests {
         });
     }
 
+    #[tokio::test]
+    async fn test_get_public_trustee_addr_from_status() {
+        let clos = async |_, _| {
+            let mut clusters = dummy_clusters();
+            clusters.items[0].spec.public_trustee_addr = None;
+            clusters.items[0].status = Some(TrustedExecutionClusterStatus {
+                conditions: None,
+                trustee_addr: Some("kbs.apps.example.com".to_string()),
+                reference_values: None,
+            });
+            Ok(serde_json::to_string(&clusters).unwrap())
+        };
+        count_check!(1, clos, |client| {
+            let addr = get_public_trustee_addr(client).await.unwrap();
+            assert_eq!(addr, "kbs.apps.example.com".to_string());
+        });
+    }
+
     #[tokio::test]
     async fn test_get_public_trustee_addr_multiple() {
         let clos = async |_, _| {
@@ -15,11 +15,13 @@ use tokio::process::Command;
 pub mod timer;
 pub use timer::Poller;
 pub mod mock_client;
+pub mod registry;
 
 #[cfg(feature = "virtualization")]
 pub mod virt;
 
 use compute_pcrs_lib::Pcr;
+use trusted_cluster_operator_lib::reference_values::{PCR_SHARDS, pcrs_shard_name};
 
 pub fn compare_pcrs(actual: &[Pcr], expected: &[Pcr]) -> bool {
     if actual.len() != expected.len() {
@@ -446,6 +448,15 @@ resources:
                     serde_yaml::Value::String("publicTrusteeAddr".to_string()),
                     serde_yaml::Value::String(trustee_addr.clone()),
                 );
+                // kind assigns no user IDs, and the images would run as root
+                let settings: serde_yaml::Value = serde_yaml::from_str("runAsUser: 65534")?;
+                for key in [
+                    "trusteePodSettings",
+                    "registerServerPodSettings",
+                    "pcrsComputePodSettings",
+                ] {
+                    spec_map.insert(serde_yaml::Value::String(key.to_string()), settings.clone());
+                }
             }
         }
 
@@ -482,9 +493,11 @@ resources:
         self.wait_for_deployment_ready(&deployments_api, "trustee-deployment", 180)
             .await?;
 
+        // Shards are created in order, so the last one implies all others
+        let shard_name = pcrs_shard_name(PCR_SHARDS - 1);
         test_info!(
             &self.test_name,
-            "Waiting for image-pcrs ConfigMap to be created"
+            "Waiting for image PCRs ConfigMap {shard_name} to be created"
         );
         let configmap_api: Api<ConfigMap> = Api::namespaced(self.client.clone(), &ns);
 
@@ -492,7 +505,7 @@ resources:
             .with_timeout(Duration::from_secs(60))
             .with_interval(Duration::from_secs(5))
             .with_error_message(format!(
-                "image-pcrs ConfigMap in the namespace {} not found",
+                "{shard_name} ConfigMap in the namespace {} not found",
                 ns
             ));
 
@@ -501,10 +514,11 @@ resources:
             .poll_async(move || {
                 let api = configmap_api.clone();
                 let tn = test_name_owned.clone();
+                let shard_name = shard_name.clone();
                 async move {
-                    let result = api.get("image-pcrs").await;
+                    let result = api.get(&shard_name).await;
                     if result.is_ok() {
-                        test_info!(&tn, "image-pcrs ConfigMap created");
+                        test_info!(&tn, "{shard_name} ConfigMap created");
                     }
                     result
                 }
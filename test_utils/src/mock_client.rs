@@ -188,6 +188,19 @@ pub fn dummy_cluster() -> TrustedExecutionCluster {
             public_trustee_addr: Some("::".to_string()),
             register_server_port: None,
             trustee_kbs_port: None,
+            trustee_pod_settings: None,
+            register_server_pod_settings: None,
+            pcrs_compute_pod_settings: None,
+            pcrs_compute_job_settings: None,
+            node_network_cidrs: None,
+            trustee_exposure: None,
+            pcr_selection: None,
+            firmware_profiles: None,
+            image_pull_secrets: None,
+            image_verification: None,
+            pcr_label_trust: None,
+            insecure_registries: None,
+            image_drain_seconds: None,
         },
     }
 }
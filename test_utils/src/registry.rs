@@ -0,0 +1,92 @@
+// SPDX-FileCopyrightText: Jakob Naucke <jnaucke@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+use openssl::hash::{MessageDigest, hash};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+pub const OCI_MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+const BLOB_MEDIA_TYPE: &str = "application/octet-stream";
+
+/// Content of a stand-in registry, by request path
+#[derive(Clone, Default)]
+pub struct RegistryContent(BTreeMap<String, (String, Vec<u8>)>);
+
+impl RegistryContent {
+    /// Add a blob and return its digest
+    pub fn add_blob(&mut self, repository: &str, data: &[u8]) -> String {
+        let digest = sha256_digest(data);
+        let path = format!("/v2/{repository}/blobs/{digest}");
+        self.0
+            .insert(path, (BLOB_MEDIA_TYPE.to_string(), data.to_vec()));
+        digest
+    }
+
+    /// Add a manifest under a tag or digest
+    pub fn add_manifest(&mut self, repository: &str, reference: &str, manifest: &[u8]) {
+        let path = format!("/v2/{repository}/manifests/{reference}");
+        let content = (OCI_MANIFEST_MEDIA_TYPE.to_string(), manifest.to_vec());
+        self.0.insert(path, content);
+    }
+
+    /// Add any other file, e.g. to stand in for a web server
+    pub fn add_file(&mut self, path: &str, media_type: &str, data: &[u8]) {
+        let content = (media_type.to_string(), data.to_vec());
+        self.0.insert(path.to_string(), content);
+    }
+}
+
+pub fn sha256_digest(data: &[u8]) -> String {
+    let digest = hash(MessageDigest::sha256(), data).unwrap();
+    format!("sha256:{}", hex::encode(digest))
+}
+
+async fn respond(mut stream: TcpStream, content: Arc<RegistryContent>) -> std::io::Result<()> {
+    let mut request = vec![];
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut buf).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..read]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.split_whitespace();
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line.next().unwrap_or_default();
+    let (status, (media_type, body)) = match (path, content.0.get(path)) {
+        ("/v2/" | "/v2", _) => ("200 OK", ("application/json".to_string(), b"{}".to_vec())),
+        (_, Some(entry)) => ("200 OK", entry.clone()),
+        (_, None) => ("404 Not Found", (String::new(), vec![])),
+    };
+    let head = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {media_type}\r\n\
+         Docker-Content-Digest: {}\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        sha256_digest(&body),
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    if method != "HEAD" {
+        stream.write_all(&body).await?;
+    }
+    stream.shutdown().await
+}
+
+/// Serve a read-only subset of the OCI distribution API over plain HTTP,
+/// as a stand-in for a local registry. Returns the registry's host:port.
+pub async fn serve_registry(content: RegistryContent) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let content = Arc::new(content);
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(respond(stream, content.clone()));
+        }
+    });
+    addr.to_string()
+}
@@ -5,9 +5,14 @@
 use compute_pcrs_lib::{Part, Pcr};
 use k8s_openapi::api::apps::v1::Deployment;
 use k8s_openapi::api::core::v1::ConfigMap;
-use kube::{Api, api::DeleteParams};
+use kube::{
+    Api,
+    api::{DeleteParams, ListParams},
+};
 use std::time::Duration;
-use trusted_cluster_operator_lib::reference_values::ImagePcrs;
+use trusted_cluster_operator_lib::reference_values::{
+    ImagePcrs, PCR_SHARD_LABEL, PCR_SHARDS, get_image_pcrs, pcrs_shard_name,
+};
 use trusted_cluster_operator_lib::{ApprovedImage, TrustedExecutionCluster};
 use trusted_cluster_operator_test_utils::*;
 
@@ -33,7 +38,9 @@ named_test!(
         let deployments_api: Api<Deployment> = Api::namespaced(client.clone(), namespace);
         wait_for_resource_deleted(&deployments_api, "trustee-deployment", 120, 1).await?;
         wait_for_resource_deleted(&deployments_api, "register-server", 120, 1).await?;
-        wait_for_resource_deleted(&configmap_api, "image-pcrs", 120, 1).await?;
+        for shard in 0..PCR_SHARDS {
+            wait_for_resource_deleted(&configmap_api, &pcrs_shard_name(shard), 120, 1).await?;
+        }
 
         test_ctx.cleanup().await?;
 
@@ -49,50 +56,42 @@ async fn test_image_pcrs_configmap_updates() -> anyhow::Result<()> {
 
     let configmap_api: Api<ConfigMap> = Api::namespaced(client.clone(), namespace);
 
+    let list_params = ListParams::default().labels(PCR_SHARD_LABEL);
     let poller = Poller::new()
         .with_timeout(Duration::from_secs(180))
         .with_interval(Duration::from_secs(5))
-        .with_error_message("image-pcrs ConfigMap not populated with data".to_string());
+        .with_error_message("image-pcrs ConfigMaps not populated with data".to_string());
 
     poller
         .poll_async(|| {
             let api = configmap_api.clone();
+            let list_params = list_params.clone();
             async move {
-                let cm = api.get("image-pcrs").await?;
-
-                if let Some(data) = &cm.data {
-                    if let Some(image_pcrs_json) = data.get("image-pcrs.json") {
-                        if let Ok(image_pcrs) = serde_json::from_str::<ImagePcrs>(image_pcrs_json) {
-                            if !image_pcrs.0.is_empty() {
-                                return Ok(());
-                            }
+                let maps = api.list(&list_params).await?;
+                for cm in maps.items.iter() {
+                    if let Ok(image_pcrs) = get_image_pcrs(cm) {
+                        if !image_pcrs.0.is_empty() {
+                            return Ok(());
                         }
                     }
                 }
 
-                Err(anyhow::anyhow!("image-pcrs ConfigMap not yet populated with image-pcrs.json data"))
+                Err(anyhow::anyhow!("image-pcrs ConfigMaps not yet populated with image-pcrs.json data"))
             }
         })
         .await?;
 
-    let image_pcrs_cm = configmap_api.get("image-pcrs").await?;
-    assert_eq!(image_pcrs_cm.metadata.name.as_deref(), Some("image-pcrs"));
-
-    let data = image_pcrs_cm.data.as_ref()
-        .expect("image-pcrs ConfigMap should have data field");
-
-    assert!(!data.is_empty(), "image-pcrs ConfigMap should have data");
+    let image_pcrs_maps = configmap_api.list(&list_params).await?;
+    assert_eq!(image_pcrs_maps.items.len(), PCR_SHARDS, "all image-pcrs shards should exist");
 
-    let image_pcrs_json = data.get("image-pcrs.json")
-        .expect("image-pcrs ConfigMap should have image-pcrs.json key");
-
-    assert!(!image_pcrs_json.is_empty(), "image-pcrs.json should not be empty");
-
-    // Parse the image-pcrs.json using the ImagePcrs structure
-    let image_pcrs: ImagePcrs = serde_json::from_str(image_pcrs_json)
-        .expect("image-pcrs.json should be valid ImagePcrs JSON");
+    // Aggregate the image-pcrs.json of all shards using the ImagePcrs structure
+    let mut image_pcrs = ImagePcrs::default();
+    for cm in image_pcrs_maps.items.iter() {
+        let shard = get_image_pcrs(cm).expect("image-pcrs shard should have valid ImagePcrs JSON");
+        image_pcrs.0.extend(shard.0);
+    }
 
-    assert!(!image_pcrs.0.is_empty(), "image-pcrs.json should contain at least one image entry");
+    assert!(!image_pcrs.0.is_empty(), "image-pcrs shards should contain at least one image entry");
 
     let expected_pcrs = vec![
         Pcr {